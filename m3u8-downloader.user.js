@@ -337,7 +337,7 @@
     const startDownload = (url, name) => new Promise((resolve, reject) => {
         GM_xmlhttpRequest({
             method: 'POST', url: `${BACKEND_URL}/api/download`, headers: { 'Content-Type': 'application/json' },
-            data: JSON.stringify({ url, name, output_dir: null }),
+            data: JSON.stringify({ url, name, output_dir: null, referer: location.href, user_agent: navigator.userAgent }),
             onload: resp => {
                 if (resp.status === 200 || resp.status === 201) {
                     try { resolve(JSON.parse(resp.responseText).id); } catch (e) { reject('解析失败'); }
@@ -352,7 +352,7 @@
             method: 'POST',
             url: `${BACKEND_URL}/api/download/stream/init`,
             headers: { 'Content-Type': 'application/json' },
-            data: JSON.stringify({ url, name, output_dir: null }),
+            data: JSON.stringify({ url, name, output_dir: null, referer: location.href, user_agent: navigator.userAgent }),
             onload: resp => {
                 if (resp.status === 200 || resp.status === 201) {
                     try {
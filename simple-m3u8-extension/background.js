@@ -67,13 +67,15 @@ async function requestJson(url, options) {
   return data;
 }
 
-async function startRustDownload(tabId, url) {
+async function startRustDownload(tabId, url, pageUrl) {
   const task = await requestJson(`${BACKEND_URL}/api/download/stream/init`, {
     method: "POST",
     headers: { "Content-Type": "application/json" },
     body: JSON.stringify({
       name: `${taskNameFromUrl(url)}_${Date.now()}`,
-      url
+      url,
+      referer: pageUrl,
+      user_agent: navigator.userAgent
     })
   });
 
@@ -164,7 +166,7 @@ chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
       return;
     }
 
-    startRustDownload(tabId, message.url)
+    startRustDownload(tabId, message.url, sender.tab?.url)
       .then((result) => sendResponse({ ok: true, ...result }))
       .catch((error) => sendResponse({ ok: false, error: error.message }));
     return true;
@@ -19,12 +19,21 @@ pub const TCP_KEEPALIVE_SECONDS: u64 = 60;
 /// 默认并发下载数
 pub const DEFAULT_CONCURRENT_DOWNLOADS: usize = 8;
 
+/// 默认同时运行的最大任务数
+pub const DEFAULT_MAX_ACTIVE_TASKS: usize = 3;
+
+/// 按时段刷新全局限速的间隔（秒）
+pub const BANDWIDTH_SCHEDULE_INTERVAL_SECONDS: u64 = 30;
+
 /// 连接池最大空闲连接数
 pub const POOL_MAX_IDLE_PER_HOST: usize = 32;
 
 /// 文件写入缓冲区大小（字节）
 pub const WRITE_BUFFER_SIZE: usize = 64 * 1024; // 64KB
 
+/// 片段内下载进度的上报间隔（毫秒）
+pub const PROGRESS_REPORT_INTERVAL_MS: u64 = 200;
+
 /// WebSocket更新间隔（毫秒）
 pub const WS_UPDATE_INTERVAL_MS: u64 = 500;
 
@@ -36,3 +45,21 @@ pub const TS_SYNC_BYTE: u8 = 0x47;
 
 /// AES密钥长度（字节）
 pub const AES_KEY_LENGTH: usize = 16;
+
+/// TS包大小（字节）
+pub const TS_PACKET_SIZE: usize = 188;
+
+/// 合并后完整性校验默认允许的时长误差比例
+pub const DEFAULT_VERIFY_TOLERANCE: f64 = 0.02;
+
+/// 完整性校验允许的最小时长误差（秒），避免短视频因 EXTINF 舍入误判
+pub const VERIFY_MIN_SLACK_SECONDS: f64 = 1.0;
+
+/// 相邻样本的时间戳相差超过该值（秒）即视为间断
+pub const VERIFY_GAP_SECONDS: f64 = 1.0;
+
+/// 片段校验允许的连续计数器错误比例，超过即视为损坏
+pub const TS_MAX_CONTINUITY_ERROR_RATIO: f64 = 0.01;
+
+/// 下载目录中记录片段大小的文件，每行为 `文件名\tContent-Length\t写入的字节数`
+pub const SEGMENT_SIZES_FILE: &str = "segment_sizes.txt";
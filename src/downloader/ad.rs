@@ -0,0 +1,428 @@
+//! 广告/填充片段检测：按 URI 正则、主机不一致、短时段以及 CUE/SCTE-35 标记找出需要去掉的片段
+
+use crate::downloader::split_periods;
+use crate::error::{DownloadError, Result};
+use crate::utils::resolve_url;
+use m3u8_rs::MediaSegment;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use url::Url;
+
+/// 广告时长倒数到该值以下即视为结束（秒），避免 EXTINF 舍入误差多去掉一个片段
+const BREAK_END_TOLERANCE: f64 = 0.05;
+
+/// 广告/填充片段过滤条件，默认不过滤
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, clap::Args)]
+pub struct AdFilter {
+    /// 去掉 URI（解析为完整地址后）匹配该正则表达式的片段
+    #[arg(long, value_name = "REGEX")]
+    #[serde(default)]
+    pub skip_uri: Option<String>,
+    /// 去掉主机与大多数片段不同的片段
+    #[arg(long)]
+    #[serde(default)]
+    pub skip_foreign_host: bool,
+    /// 去掉时长不超过该秒数的不连续时段，最长的时段始终保留
+    #[arg(long, value_name = "SECONDS")]
+    #[serde(default)]
+    pub max_ad_period: Option<f64>,
+    /// 去掉 EXT-X-CUE-OUT/CUE-IN 与 SCTE-35 EXT-X-DATERANGE 标记的广告
+    #[arg(long)]
+    #[serde(default)]
+    pub skip_cue: bool,
+}
+
+/// 片段被去掉的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    UriPattern,
+    ForeignHost,
+    CueMarker,
+    DateRange,
+    ShortPeriod,
+    /// 被时段过滤条件去掉
+    PeriodFilter,
+    /// 不在指定的时间范围内
+    OutsideClip,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UriPattern => "URI 匹配",
+            Self::ForeignHost => "主机不一致",
+            Self::CueMarker => "CUE 广告标记",
+            Self::DateRange => "SCTE-35 DATERANGE",
+            Self::ShortPeriod => "短时段",
+            Self::PeriodFilter => "时段过滤",
+            Self::OutsideClip => "时间范围之外",
+        })
+    }
+}
+
+/// 连续的一段被去掉的片段，序号与时间均相对于原播放列表
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedRange {
+    pub first_segment: usize,
+    pub segment_count: usize,
+    /// 起始时间（秒，EXTINF 累加）
+    pub start: f64,
+    pub duration: f64,
+    pub reason: SkipReason,
+}
+
+/// 广告时段的开始与结束标记
+enum BreakEvent {
+    /// 广告开始，附带时长（未知时等待结束标记）
+    Start(Option<f64>),
+    /// 已在广告中（`CUE-OUT-CONT`），用于从中途开始的播放列表
+    Continue,
+    End,
+}
+
+impl AdFilter {
+    pub fn is_enabled(&self) -> bool {
+        self.skip_uri.is_some()
+            || self.skip_foreign_host
+            || self.max_ad_period.is_some()
+            || self.skip_cue
+    }
+
+    /// 逐个片段判断是否需要去掉，每个片段只记录最先命中的原因
+    pub fn detect(
+        &self,
+        segments: &[MediaSegment],
+        base_url: &Url,
+    ) -> Result<Vec<Option<SkipReason>>> {
+        let mut reasons = vec![None; segments.len()];
+        if !self.is_enabled() {
+            return Ok(reasons);
+        }
+        let urls = segments
+            .iter()
+            .map(|segment| resolve_url(base_url, &segment.uri))
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(pattern) = &self.skip_uri {
+            let regex = Regex::new(pattern)
+                .map_err(|e| DownloadError::validation("skip_uri", e.to_string()))?;
+            mark(&mut reasons, SkipReason::UriPattern, |i| {
+                regex.is_match(&urls[i])
+            });
+        }
+        if self.skip_foreign_host {
+            let hosts: Vec<Option<String>> = urls
+                .iter()
+                .map(|u| Url::parse(u).ok()?.host_str().map(str::to_string))
+                .collect();
+            if let Some(main_host) = main_host(segments, &hosts) {
+                mark(&mut reasons, SkipReason::ForeignHost, |i| {
+                    hosts[i].as_deref() != Some(main_host.as_str())
+                });
+            }
+        }
+        if self.skip_cue {
+            mark_breaks(segments, &mut reasons, SkipReason::CueMarker, cue_event);
+            mark_breaks(
+                segments,
+                &mut reasons,
+                SkipReason::DateRange,
+                daterange_event,
+            );
+        }
+        if let Some(max) = self.max_ad_period {
+            let periods = split_periods(segments);
+            let longest = periods
+                .iter()
+                .max_by(|a, b| a.duration.total_cmp(&b.duration))
+                .map(|p| p.index);
+            for period in periods.iter().filter(|p| p.duration <= max) {
+                if Some(period.index) != longest {
+                    for reason in &mut reasons[period.segments()] {
+                        reason.get_or_insert(SkipReason::ShortPeriod);
+                    }
+                }
+            }
+        }
+        Ok(reasons)
+    }
+}
+
+/// 把 `predicate` 命中且尚未记录原因的片段标记为 `reason`
+fn mark(reasons: &mut [Option<SkipReason>], reason: SkipReason, predicate: impl Fn(usize) -> bool) {
+    for (i, slot) in reasons.iter_mut().enumerate() {
+        if slot.is_none() && predicate(i) {
+            *slot = Some(reason);
+        }
+    }
+}
+
+/// 按时长计算占多数的主机，视为正片所在的主机
+fn main_host(segments: &[MediaSegment], hosts: &[Option<String>]) -> Option<String> {
+    let mut totals: HashMap<&str, f64> = HashMap::new();
+    for (segment, host) in segments.iter().zip(hosts) {
+        if let Some(host) = host {
+            *totals.entry(host).or_default() += f64::from(segment.duration);
+        }
+    }
+    totals
+        .into_iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(host, _)| host.to_string())
+}
+
+/// 按开始/结束标记标记广告片段：开始标记所在片段属于广告，结束标记所在片段恢复正片，
+/// 开始标记带有时长时按 EXTINF 倒数，到时自动结束
+fn mark_breaks(
+    segments: &[MediaSegment],
+    reasons: &mut [Option<SkipReason>],
+    reason: SkipReason,
+    event: impl Fn(&MediaSegment) -> Option<BreakEvent>,
+) {
+    let mut active: Option<Option<f64>> = None;
+    for (segment, slot) in segments.iter().zip(reasons.iter_mut()) {
+        match event(segment) {
+            Some(BreakEvent::Start(duration)) => active = Some(duration),
+            Some(BreakEvent::Continue) => {
+                active.get_or_insert(None);
+            }
+            Some(BreakEvent::End) => active = None,
+            None => {}
+        }
+        let Some(remaining) = active.as_mut() else {
+            continue;
+        };
+        slot.get_or_insert(reason);
+        if let Some(remaining) = remaining {
+            *remaining -= f64::from(segment.duration);
+            if *remaining <= BREAK_END_TOLERANCE {
+                active = None;
+            }
+        }
+    }
+}
+
+/// `#EXT-X-CUE-OUT[:DURATION=]30`、`#EXT-X-CUE-OUT-CONT`、`#EXT-X-CUE-IN`
+fn cue_event(segment: &MediaSegment) -> Option<BreakEvent> {
+    segment
+        .unknown_tags
+        .iter()
+        .rev()
+        .find_map(|tag| match tag.tag.as_str() {
+            "X-CUE-OUT" => Some(BreakEvent::Start(
+                tag.rest.as_deref().and_then(parse_cue_duration),
+            )),
+            "X-CUE-OUT-CONT" => Some(BreakEvent::Continue),
+            "X-CUE-IN" => Some(BreakEvent::End),
+            _ => None,
+        })
+}
+
+fn parse_cue_duration(rest: &str) -> Option<f64> {
+    rest.split(',').find_map(|part| {
+        let part = part.trim();
+        let value = part.split_once('=').map_or(Some(part), |(name, value)| {
+            name.eq_ignore_ascii_case("DURATION").then_some(value)
+        })?;
+        value.trim().parse().ok().filter(|d: &f64| *d > 0.0)
+    })
+}
+
+/// 带 `SCTE35-OUT` 的 DATERANGE 开始广告（时长取 DURATION、PLANNED-DURATION 或 END-DATE），
+/// 带 `SCTE35-IN` 的 DATERANGE 结束广告
+fn daterange_event(segment: &MediaSegment) -> Option<BreakEvent> {
+    let range = segment.daterange.as_ref()?;
+    let attributes = range.other_attributes.as_ref()?;
+    if attributes.contains_key("SCTE35-OUT") {
+        let duration = range.duration.or(range.planned_duration).or_else(|| {
+            let end = range.end_date?;
+            let millis = (end - range.start_date).num_milliseconds();
+            #[allow(clippy::cast_precision_loss)]
+            Some(millis as f64 / 1000.0)
+        });
+        Some(BreakEvent::Start(duration.filter(|d| *d > 0.0)))
+    } else if attributes.contains_key("SCTE35-IN") {
+        Some(BreakEvent::End)
+    } else {
+        None
+    }
+}
+
+/// 把逐片段的原因合并为连续的范围
+pub fn skipped_ranges(
+    segments: &[MediaSegment],
+    reasons: &[Option<SkipReason>],
+) -> Vec<SkippedRange> {
+    let mut ranges: Vec<SkippedRange> = Vec::new();
+    let mut position = 0.0;
+    for (i, (segment, reason)) in segments.iter().zip(reasons).enumerate() {
+        let duration = f64::from(segment.duration);
+        if let Some(reason) = *reason {
+            match ranges.last_mut() {
+                Some(last)
+                    if last.reason == reason && last.first_segment + last.segment_count == i =>
+                {
+                    last.segment_count += 1;
+                    last.duration += duration;
+                }
+                _ => ranges.push(SkippedRange {
+                    first_segment: i,
+                    segment_count: 1,
+                    start: position,
+                    duration,
+                    reason,
+                }),
+            }
+        }
+        position += duration;
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: &str) -> Vec<MediaSegment> {
+        let text = format!("#EXTM3U\n#EXT-X-TARGETDURATION:10\n{body}#EXT-X-ENDLIST\n");
+        m3u8_rs::parse_media_playlist_res(text.as_bytes())
+            .expect("测试播放列表无效")
+            .segments
+    }
+
+    fn detect(filter: &AdFilter, segments: &[MediaSegment]) -> Vec<Option<SkipReason>> {
+        let base = Url::parse("https://cdn.example.com/live/index.m3u8").unwrap();
+        filter.detect(segments, &base).unwrap()
+    }
+
+    fn cue() -> AdFilter {
+        AdFilter {
+            skip_cue: true,
+            ..AdFilter::default()
+        }
+    }
+
+    #[test]
+    fn parses_cue_durations() {
+        assert_eq!(parse_cue_duration("30"), Some(30.0));
+        assert_eq!(parse_cue_duration("DURATION=15.5"), Some(15.5));
+        assert_eq!(parse_cue_duration("ID=7, duration=12"), Some(12.0));
+        assert_eq!(parse_cue_duration("0"), None);
+        assert_eq!(parse_cue_duration("ID=7"), None);
+    }
+
+    #[test]
+    fn cue_out_duration_counts_down() {
+        // 12 秒广告由 6 秒与 5.98 秒两个片段组成，舍入误差不会多去掉之后的正片
+        let segments = parse(
+            "#EXTINF:10,\nm0.ts\n\
+             #EXT-X-CUE-OUT:DURATION=12\n#EXTINF:6,\na0.ts\n\
+             #EXTINF:5.98,\na1.ts\n\
+             #EXTINF:10,\nm1.ts\n",
+        );
+        let cue_marker = Some(SkipReason::CueMarker);
+        assert_eq!(
+            detect(&cue(), &segments),
+            [None, cue_marker, cue_marker, None]
+        );
+    }
+
+    #[test]
+    fn cue_out_without_duration_waits_for_cue_in() {
+        let segments = parse(
+            "#EXT-X-CUE-OUT\n#EXTINF:10,\na0.ts\n\
+             #EXTINF:10,\na1.ts\n\
+             #EXT-X-CUE-IN\n#EXTINF:10,\nm0.ts\n",
+        );
+        let cue_marker = Some(SkipReason::CueMarker);
+        assert_eq!(detect(&cue(), &segments), [cue_marker, cue_marker, None]);
+    }
+
+    #[test]
+    fn cue_out_cont_marks_break_already_in_progress() {
+        let segments = parse(
+            "#EXT-X-CUE-OUT-CONT:ElapsedTime=5,Duration=15\n#EXTINF:5,\na1.ts\n\
+             #EXT-X-CUE-OUT-CONT:ElapsedTime=10,Duration=15\n#EXTINF:5,\na2.ts\n\
+             #EXT-X-CUE-IN\n#EXTINF:10,\nm0.ts\n",
+        );
+        let cue_marker = Some(SkipReason::CueMarker);
+        assert_eq!(detect(&cue(), &segments), [cue_marker, cue_marker, None]);
+    }
+
+    #[test]
+    fn scte35_daterange_marks_break() {
+        let segments = parse(
+            "#EXTINF:10,\nm0.ts\n\
+             #EXT-X-DATERANGE:ID=\"ad1\",START-DATE=\"2024-01-01T00:00:10Z\",DURATION=10,SCTE35-OUT=0xFC30\n\
+             #EXTINF:5,\na0.ts\n\
+             #EXTINF:5,\na1.ts\n\
+             #EXTINF:10,\nm1.ts\n",
+        );
+        let date_range = Some(SkipReason::DateRange);
+        assert_eq!(
+            detect(&cue(), &segments),
+            [None, date_range, date_range, None]
+        );
+    }
+
+    #[test]
+    fn foreign_host_is_skipped() {
+        let segments = parse(
+            "#EXTINF:10,\nm0.ts\n\
+             #EXTINF:5,\nhttps://ads.example.net/a0.ts\n\
+             #EXTINF:10,\nhttps://cdn.example.com/live/m1.ts\n",
+        );
+        let filter = AdFilter {
+            skip_foreign_host: true,
+            ..AdFilter::default()
+        };
+        assert_eq!(
+            detect(&filter, &segments),
+            [None, Some(SkipReason::ForeignHost), None]
+        );
+    }
+
+    #[test]
+    fn longest_period_is_always_kept() {
+        let segments = parse(
+            "#EXTINF:10,\nm0.ts\n#EXTINF:10,\nm1.ts\n\
+             #EXT-X-DISCONTINUITY\n#EXTINF:5,\na0.ts\n\
+             #EXT-X-DISCONTINUITY\n#EXTINF:8,\nm2.ts\n",
+        );
+        let short = Some(SkipReason::ShortPeriod);
+        let filter = |max| AdFilter {
+            max_ad_period: Some(max),
+            ..AdFilter::default()
+        };
+        assert_eq!(detect(&filter(6.0), &segments), [None, None, short, None]);
+        // 所有时段都不超过上限时，最长的时段仍然保留
+        assert_eq!(detect(&filter(60.0), &segments), [None, None, short, short]);
+    }
+
+    #[test]
+    fn first_reason_wins_and_ranges_merge() {
+        let segments = parse(
+            "#EXTINF:10,\nm0.ts\n\
+             #EXT-X-CUE-OUT:15\n#EXTINF:5,\nad/a0.ts\n\
+             #EXTINF:5,\nad/a1.ts\n\
+             #EXTINF:5,\na2.ts\n\
+             #EXTINF:10,\nm1.ts\n",
+        );
+        let filter = AdFilter {
+            skip_uri: Some("/ad/".to_string()),
+            skip_cue: true,
+            ..AdFilter::default()
+        };
+        let reasons = detect(&filter, &segments);
+        let ranges = skipped_ranges(&segments, &reasons);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!((ranges[0].first_segment, ranges[0].segment_count), (1, 2));
+        assert_eq!(ranges[0].reason, SkipReason::UriPattern);
+        assert_eq!((ranges[0].start, ranges[0].duration), (10.0, 10.0));
+        assert_eq!((ranges[1].first_segment, ranges[1].segment_count), (3, 1));
+        assert_eq!(ranges[1].reason, SkipReason::CueMarker);
+    }
+}
@@ -0,0 +1,320 @@
+//! 时间范围裁剪：按 EXTINF 累计时长只选取与 `start`/`end` 窗口重叠的片段
+
+use crate::error::{DownloadError, Result};
+use m3u8_rs::MediaSegment;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// 播放列表中的时间位置（秒）
+///
+/// 字符串形式为时间戳（`1:02:03.5`、`02:03`）或时长（`90`、`90s`、`1h2m3.5s`）；
+/// JSON 中也可以直接使用秒数。
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct ClipTime(pub f64);
+
+impl ClipTime {
+    pub const fn seconds(self) -> f64 {
+        self.0
+    }
+}
+
+impl FromStr for ClipTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let value = s.trim().to_ascii_lowercase();
+        let invalid = || format!("时间格式无效: {s}（如 1:02:03.5、90、1h2m3s）");
+        let seconds = if value.contains(':') {
+            let parts: Vec<&str> = value.split(':').collect();
+            if parts.len() > 3 {
+                return Err(invalid());
+            }
+            parts.iter().try_fold(0.0, |total, part| {
+                part.parse::<f64>()
+                    .ok()
+                    .filter(|v| *v >= 0.0)
+                    .map(|v| total * 60.0 + v)
+                    .ok_or_else(invalid)
+            })?
+        } else if let Ok(seconds) = value.parse::<f64>() {
+            seconds
+        } else {
+            parse_units(&value).ok_or_else(invalid)?
+        };
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(invalid());
+        }
+        Ok(Self(seconds))
+    }
+}
+
+/// `1h2m3.5s` 形式的时长
+fn parse_units(value: &str) -> Option<f64> {
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in value.chars() {
+        let unit = match c {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        total += std::mem::take(&mut number).parse::<f64>().ok()? * unit;
+    }
+    number.is_empty().then_some(total)
+}
+
+impl fmt::Display for ClipTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s", self.0)
+    }
+}
+
+impl Serialize for ClipTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ClipTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(f64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(seconds) => format!("{seconds}").parse(),
+            Raw::Text(text) => text.parse(),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+/// 只下载播放列表中的一段时间
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, clap::Args)]
+pub struct ClipOptions {
+    /// 开始位置：时间戳（1:02:03.5）或时长（90、1h2m3s）
+    #[arg(long)]
+    #[serde(default)]
+    pub start: Option<ClipTime>,
+    /// 结束位置，格式同开始位置
+    #[arg(long)]
+    #[serde(default)]
+    pub end: Option<ClipTime>,
+    /// 合并时在开始/结束位置精确裁剪（需要FFmpeg），否则保留边界片段的完整内容
+    #[arg(long)]
+    #[serde(default)]
+    pub trim: bool,
+}
+
+/// 输出中需要保留的时间段（秒，相对于合并后的输出）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrimWindow {
+    pub offset: f64,
+    pub duration: Option<f64>,
+}
+
+impl ClipOptions {
+    pub const fn is_enabled(&self) -> bool {
+        self.start.is_some() || self.end.is_some()
+    }
+
+    fn window(&self) -> (f64, f64) {
+        (
+            self.start.map_or(0.0, ClipTime::seconds),
+            self.end.map_or(f64::INFINITY, ClipTime::seconds),
+        )
+    }
+
+    /// 逐个片段判断是否与时间窗口重叠，窗口为空或超出播放列表时返回错误
+    pub fn overlapping(&self, segments: &[MediaSegment]) -> Result<Vec<bool>> {
+        let (start, end) = self.window();
+        if end <= start {
+            return Err(DownloadError::validation(
+                "end",
+                format!("结束位置 {end}s 必须晚于开始位置 {start}s"),
+            ));
+        }
+        let mut position = 0.0;
+        let overlapping: Vec<bool> = segments
+            .iter()
+            .map(|segment| {
+                let segment_start = position;
+                position += f64::from(segment.duration);
+                segment_start < end && position > start
+            })
+            .collect();
+        if !overlapping.contains(&true) {
+            return Err(DownloadError::validation(
+                "start",
+                format!("开始位置 {start}s 超出播放列表时长 {position:.1}s"),
+            ));
+        }
+        Ok(overlapping)
+    }
+
+    /// 需要精确裁剪时，按最终保留的片段（`keep`）计算输出中的裁剪位置
+    ///
+    /// 窗口内去掉的广告等片段不计入输出时长。
+    pub fn trim_window(&self, segments: &[MediaSegment], keep: &[bool]) -> Option<TrimWindow> {
+        if !self.trim || !self.is_enabled() {
+            return None;
+        }
+        let (start, end) = self.window();
+        let (mut position, mut output) = (0.0, 0.0);
+        let (mut trim_start, mut trim_end) = (None, None);
+        for (segment, &kept) in segments.iter().zip(keep) {
+            let duration = f64::from(segment.duration);
+            if kept {
+                if trim_start.is_none() && position + duration > start {
+                    trim_start = Some(output + (start - position).max(0.0));
+                }
+                if trim_end.is_none() && position + duration >= end {
+                    trim_end = Some(output + (end - position).clamp(0.0, duration));
+                }
+                output += duration;
+            }
+            position += duration;
+        }
+        let offset = trim_start.unwrap_or(0.0);
+        Some(TrimWindow {
+            offset,
+            duration: trim_end.map(|end| (end - offset).max(0.0)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> f64 {
+        s.parse::<ClipTime>().unwrap().seconds()
+    }
+
+    fn segments(count: usize) -> Vec<MediaSegment> {
+        (0..count)
+            .map(|i| MediaSegment {
+                uri: format!("s{i}.ts"),
+                duration: 10.0,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn clip(start: Option<f64>, end: Option<f64>, trim: bool) -> ClipOptions {
+        ClipOptions {
+            start: start.map(ClipTime),
+            end: end.map(ClipTime),
+            trim,
+        }
+    }
+
+    #[test]
+    fn parses_time_formats() {
+        assert_eq!(time("1:02:03.5"), 3723.5);
+        assert_eq!(time("02:03"), 123.0);
+        assert_eq!(time("1h2m3s"), 3723.0);
+        assert_eq!(time("1H2M3.5S"), 3723.5);
+        assert_eq!(time("90"), 90.0);
+        assert_eq!(time("90s"), 90.0);
+        assert_eq!(time(" 2m "), 120.0);
+        for invalid in ["1:2:3:4", "abc", "-5", "1x", "1:-2", "h", "5m3"] {
+            assert!(invalid.parse::<ClipTime>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn deserializes_seconds_and_strings() {
+        let clip: ClipOptions = serde_json::from_str(r#"{"start": 90, "end": "1:30:00"}"#).unwrap();
+        assert_eq!(clip.start, Some(ClipTime(90.0)));
+        assert_eq!(clip.end, Some(ClipTime(5400.0)));
+        assert!(serde_json::from_str::<ClipOptions>(r#"{"start": -1}"#).is_err());
+    }
+
+    #[test]
+    fn selects_overlapping_segments() {
+        let segments = segments(4);
+        let overlapping = clip(Some(15.0), Some(25.0), false).overlapping(&segments);
+        assert_eq!(overlapping.unwrap(), [false, true, true, false]);
+        // 窗口边界恰好落在片段边界上时不包含相邻片段
+        let overlapping = clip(Some(10.0), Some(20.0), false).overlapping(&segments);
+        assert_eq!(overlapping.unwrap(), [false, true, false, false]);
+        let overlapping = clip(None, Some(5.0), false).overlapping(&segments);
+        assert_eq!(overlapping.unwrap(), [true, false, false, false]);
+    }
+
+    #[test]
+    fn empty_window_is_rejected() {
+        let segments = segments(4);
+        assert!(
+            clip(Some(20.0), Some(20.0), false)
+                .overlapping(&segments)
+                .is_err()
+        );
+        assert!(
+            clip(Some(30.0), Some(10.0), false)
+                .overlapping(&segments)
+                .is_err()
+        );
+        // 开始位置超出播放列表时长
+        assert!(
+            clip(Some(40.0), None, false)
+                .overlapping(&segments)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn trim_window_is_relative_to_output() {
+        let segments = segments(4);
+        assert_eq!(
+            clip(Some(15.0), Some(35.0), false).trim_window(&segments, &[true; 4]),
+            None
+        );
+        let window = clip(Some(15.0), Some(35.0), true);
+        assert_eq!(
+            window.trim_window(&segments, &[false, true, true, true]),
+            Some(TrimWindow {
+                offset: 5.0,
+                duration: Some(20.0)
+            })
+        );
+        let open_ended = clip(Some(15.0), None, true);
+        assert_eq!(
+            open_ended.trim_window(&segments, &[false, true, true, true]),
+            Some(TrimWindow {
+                offset: 5.0,
+                duration: None
+            })
+        );
+    }
+
+    #[test]
+    fn trim_window_skips_dropped_segments() {
+        let segments = segments(4);
+        let window = clip(Some(15.0), Some(35.0), true);
+        // 窗口内去掉的片段不计入输出，结束位置相应提前
+        assert_eq!(
+            window.trim_window(&segments, &[false, true, false, true]),
+            Some(TrimWindow {
+                offset: 5.0,
+                duration: Some(10.0)
+            })
+        );
+        // 开始位置所在的片段被去掉时，输出从下一个保留的片段开头开始
+        assert_eq!(
+            window.trim_window(&segments, &[false, false, true, true]),
+            Some(TrimWindow {
+                offset: 0.0,
+                duration: Some(15.0)
+            })
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use m3u8_rs::{Map, MediaSegment};
+use serde::{Deserialize, Serialize};
+
+/// 片段封装格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerFormat {
+    /// MPEG-TS 片段
+    MpegTs,
+    /// fMP4 / CMAF 片段（带 `EXT-X-MAP` 初始化段）
+    Fmp4,
+}
+
+impl ContainerFormat {
+    /// 根据播放列表是否包含 `EXT-X-MAP` 判断封装格式
+    pub fn detect(segments: &[MediaSegment]) -> Self {
+        if segments.iter().any(|s| s.map.is_some()) {
+            Self::Fmp4
+        } else {
+            Self::MpegTs
+        }
+    }
+
+    /// 合并临时文件的扩展名
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::MpegTs => "ts",
+            Self::Fmp4 => "mp4",
+        }
+    }
+
+    /// 传给 `FFmpeg -f` 的输入格式名
+    pub const fn ffmpeg_format(self) -> &'static str {
+        match self {
+            Self::MpegTs => "mpegts",
+            Self::Fmp4 => "mp4",
+        }
+    }
+
+    /// 转封装为MP4时需要的音频比特流过滤参数（TS 中的 ADTS 需转换为 ASC）
+    pub const fn audio_bitstream_filter(self) -> &'static [&'static str] {
+        match self {
+            Self::MpegTs => &["-bsf:a", "aac_adtstoasc"],
+            Self::Fmp4 => &[],
+        }
+    }
+}
+
+/// 输出文件格式
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// MP4（默认）
+    #[default]
+    Mp4,
+    /// MPEG-TS，TS 片段无需转封装
+    Ts,
+    /// Matroska
+    Mkv,
+    /// 只保留音频的 MP4 音频文件
+    M4a,
+    /// 只保留音频并编码为 MP3
+    Mp3,
+}
+
+impl OutputFormat {
+    /// 输出文件扩展名
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Ts => "ts",
+            Self::Mkv => "mkv",
+            Self::M4a => "m4a",
+            Self::Mp3 => "mp3",
+        }
+    }
+
+    /// 传给 `FFmpeg -f` 的输出格式名
+    pub const fn ffmpeg_muxer(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Ts => "mpegts",
+            Self::Mkv => "matroska",
+            Self::M4a => "ipod",
+            Self::Mp3 => "mp3",
+        }
+    }
+
+    /// 是否能封装字幕轨道
+    pub const fn supports_subtitles(self) -> bool {
+        matches!(self, Self::Mp4 | Self::Mkv)
+    }
+
+    /// 是否只输出音频
+    pub const fn is_audio_only(self) -> bool {
+        matches!(self, Self::M4a | Self::Mp3)
+    }
+
+    /// 是否为 MP4 系列封装（可使用 faststart，TS 中的 ADTS 需转换为 ASC）
+    pub const fn is_mp4_family(self) -> bool {
+        matches!(self, Self::Mp4 | Self::M4a)
+    }
+}
+
+/// 转码预设
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodePreset {
+    /// 不转码，直接复制音视频流（默认）
+    #[default]
+    Copy,
+    /// H.264 + AAC，兼容性最好
+    H264,
+    /// H.265 + AAC，体积更小
+    H265,
+    /// H.264 + AAC，限制为 720p 并降低码率
+    Small,
+}
+
+impl EncodePreset {
+    /// 视频编码参数，`Copy` 时为空
+    pub const fn video_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Copy => &[],
+            Self::H264 => &["-c:v", "libx264", "-preset", "medium", "-crf", "23"],
+            Self::H265 => &[
+                "-c:v", "libx265", "-preset", "medium", "-crf", "26", "-tag:v", "hvc1",
+            ],
+            Self::Small => &[
+                "-c:v",
+                "libx264",
+                "-preset",
+                "slow",
+                "-crf",
+                "28",
+                "-vf",
+                "scale=-2:'min(720,ih)'",
+            ],
+        }
+    }
+
+    /// 音频编码参数，`Copy` 时为空
+    pub const fn audio_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Copy => &[],
+            Self::H264 | Self::H265 => &["-c:a", "aac", "-b:a", "160k"],
+            Self::Small => &["-c:a", "aac", "-b:a", "96k"],
+        }
+    }
+}
+
+/// 输出格式与后处理选项
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, clap::Args)]
+pub struct OutputOptions {
+    /// 输出文件格式
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Mp4)]
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// 把索引（moov）移到文件开头，便于边下边播（仅 MP4/M4A）
+    #[arg(long)]
+    #[serde(default)]
+    pub faststart: bool,
+    /// 写入输出文件的标题
+    #[arg(long)]
+    #[serde(default)]
+    pub title: Option<String>,
+    /// 转码预设，默认不转码
+    #[arg(long, value_enum, default_value_t = EncodePreset::Copy)]
+    #[serde(default)]
+    pub preset: EncodePreset,
+}
+
+/// 播放列表中的初始化段
+///
+/// 解析器只把 `EXT-X-MAP` 挂在紧随其后的片段上，这里向后传递，
+/// 并对相同的初始化段去重。`data` 在下载后填充。
+#[derive(Debug, Clone, Default)]
+pub struct InitSections {
+    pub maps: Vec<Map>,
+    pub data: Vec<Vec<u8>>,
+    /// 每个片段使用的初始化段下标
+    pub segment_map: Vec<Option<usize>>,
+}
+
+impl InitSections {
+    pub fn from_segments(segments: &[MediaSegment]) -> Self {
+        let mut maps: Vec<Map> = Vec::new();
+        let mut current = None;
+        let segment_map = segments
+            .iter()
+            .map(|segment| {
+                if let Some(map) = &segment.map {
+                    let index = maps.iter().position(|m| m == map).unwrap_or_else(|| {
+                        maps.push(map.clone());
+                        maps.len() - 1
+                    });
+                    current = Some(index);
+                }
+                current
+            })
+            .collect();
+
+        Self {
+            maps,
+            data: Vec::new(),
+            segment_map,
+        }
+    }
+
+    /// 第 `index` 个片段使用的初始化段
+    pub fn init_for(&self, index: usize) -> Option<&[u8]> {
+        let current = self.segment_map.get(index).copied().flatten()?;
+        self.data.get(current).map(Vec::as_slice)
+    }
+
+    /// 第 `index` 个片段之前需要写入的初始化段（与上一个片段相同时返回 `None`）
+    pub fn init_before(&self, index: usize) -> Option<&[u8]> {
+        let current = self.segment_map.get(index).copied().flatten()?;
+        let previous = index
+            .checked_sub(1)
+            .and_then(|i| self.segment_map.get(i).copied().flatten());
+        if previous == Some(current) {
+            return None;
+        }
+        self.data.get(current).map(Vec::as_slice)
+    }
+}
@@ -0,0 +1,1312 @@
+//! MPEG-TS 解复用
+//!
+//! 从 PES 中取出 H.264/H.265 视频与 ADTS AAC/MP3 音频的访问单元，
+//! 并生成写入 MP4 所需的解码参数（avcC、hvcC 与 AudioSpecificConfig）。
+
+use crate::config::{TS_PACKET_SIZE, TS_SYNC_BYTE};
+use crate::downloader::sample_aes::{find_start_codes, remove_emulation_prevention};
+use crate::downloader::ts::{
+    PAT_PID, STREAM_TYPE_AAC, STREAM_TYPE_H264, STREAM_TYPE_H265, STREAM_TYPE_METADATA,
+    STREAM_TYPE_MPEG1_AUDIO, STREAM_TYPE_MPEG2_AUDIO, STREAM_TYPE_PRIVATE_SECTION,
+    STREAM_TYPE_SCTE35, parse_packet, parse_pat, parse_pmt, psi_section_range,
+};
+use crate::error::{DownloadError, Result};
+use std::collections::HashMap;
+
+/// PES 时间戳的时钟频率
+pub const MPEG_TIMESCALE: u32 = 90_000;
+/// 33 位时间戳的回绕周期
+const TIMESTAMP_WRAP: i64 = 1 << 33;
+/// 无法从相邻时间戳推算帧间隔时使用的帧时长（90kHz，25fps）
+const DEFAULT_FRAME_DURATION: i64 = 3600;
+/// 相邻时间戳间隔超过 10 秒时视为跳变，不作为帧间隔参考
+const MAX_FRAME_GAP: i64 = 10 * MPEG_TIMESCALE as i64;
+/// AAC 采样率表，下标为 `sampling_frequency_index`
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+/// MPEG 音频码率表（kbps）：MPEG-1 Layer I/II/III、MPEG-2/2.5 Layer I、MPEG-2/2.5 Layer II/III
+const MPEG_AUDIO_BITRATES: [[u32; 14]; 5] = [
+    [
+        32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+/// MPEG 音频 esds 中的 objectTypeIndication
+const OBJECT_TYPE_AAC: u8 = 0x40;
+const OBJECT_TYPE_MPEG1_AUDIO: u8 = 0x6B;
+const OBJECT_TYPE_MPEG2_AUDIO: u8 = 0x69;
+
+/// 轨道的编码与解码参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackCodec {
+    /// H.264，`config` 为 avcC 内容
+    Avc {
+        width: u16,
+        height: u16,
+        config: Vec<u8>,
+    },
+    /// H.265，`config` 为 hvcC 内容
+    Hevc {
+        width: u16,
+        height: u16,
+        config: Vec<u8>,
+    },
+    /// AAC，`config` 为 AudioSpecificConfig
+    Aac {
+        sample_rate: u32,
+        channels: u16,
+        config: Vec<u8>,
+    },
+    /// MP3，`object_type` 为 esds 中的 objectTypeIndication
+    Mp3 {
+        sample_rate: u32,
+        channels: u16,
+        object_type: u8,
+    },
+}
+
+impl TrackCodec {
+    pub const fn is_video(&self) -> bool {
+        matches!(self, Self::Avc { .. } | Self::Hevc { .. })
+    }
+
+    /// 轨道时间刻度：视频沿用 90kHz，音频使用采样率
+    pub const fn timescale(&self) -> u32 {
+        match self {
+            Self::Avc { .. } | Self::Hevc { .. } => MPEG_TIMESCALE,
+            Self::Aac { sample_rate, .. } | Self::Mp3 { sample_rate, .. } => *sample_rate,
+        }
+    }
+
+    /// esds 中的 objectTypeIndication，视频为 0
+    pub const fn object_type(&self) -> u8 {
+        match self {
+            Self::Avc { .. } | Self::Hevc { .. } => 0,
+            Self::Aac { .. } => OBJECT_TYPE_AAC,
+            Self::Mp3 { object_type, .. } => *object_type,
+        }
+    }
+}
+
+/// 一个访问单元（MP4 中的一个样本），时间戳以轨道时间刻度表示
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub track: usize,
+    pub dts: i64,
+    pub pts: i64,
+    pub keyframe: bool,
+    /// 视频为 4 字节长度前缀的 NAL 单元；AAC 去掉 ADTS 帧头，MP3 保留完整帧
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamKind {
+    H264,
+    H265,
+    Aac,
+    Mp3,
+}
+
+impl StreamKind {
+    /// PMT 流类型对应的轨道，元数据等非音视频流返回 `None`
+    fn from_stream_type(stream_type: u8) -> Result<Option<Self>> {
+        match stream_type {
+            STREAM_TYPE_H264 => Ok(Some(Self::H264)),
+            STREAM_TYPE_H265 => Ok(Some(Self::H265)),
+            STREAM_TYPE_AAC => Ok(Some(Self::Aac)),
+            STREAM_TYPE_MPEG1_AUDIO | STREAM_TYPE_MPEG2_AUDIO => Ok(Some(Self::Mp3)),
+            STREAM_TYPE_PRIVATE_SECTION | STREAM_TYPE_METADATA | STREAM_TYPE_SCTE35 => Ok(None),
+            other => Err(DownloadError::remux(format!(
+                "不支持的流类型: 0x{other:02X}"
+            ))),
+        }
+    }
+
+    const fn is_video(self) -> bool {
+        matches!(self, Self::H264 | Self::H265)
+    }
+}
+
+/// 不连续点之后的新时段：所有轨道使用同一个平移量，接在上一时段结尾
+#[derive(Debug)]
+struct PeriodStart {
+    /// 上一时段各轨道结束时间的最大值（90kHz）
+    start: i64,
+    /// 由新时段中第一个带时间戳的 PES 确定
+    offset: Option<i64>,
+}
+
+/// 单条轨道的时间线：展开 33 位回绕，时间戳回退时平移后续时间戳，保证解码顺序单调递增
+#[derive(Debug, Default)]
+struct Timeline {
+    last_raw: Option<i64>,
+    offset: i64,
+    last_dts: Option<i64>,
+    frame_duration: i64,
+    /// 进入新时段后尚未收到时间戳
+    rebase: bool,
+}
+
+impl Timeline {
+    /// 输入 90kHz 原始时间戳，返回调整后的 (DTS, PTS)
+    fn map(&mut self, dts: i64, pts: i64, period: &mut Option<PeriodStart>) -> (i64, i64) {
+        let raw = self
+            .last_raw
+            .map_or(dts, |last| unwrap_timestamp(dts, last));
+        self.last_raw = Some(raw);
+        if std::mem::take(&mut self.rebase)
+            && let Some(period) = period
+        {
+            self.offset = *period.offset.get_or_insert(period.start - raw);
+        }
+        let composition = unwrap_timestamp(pts, dts) - dts;
+
+        let mut mapped = raw + self.offset;
+        if let Some(last) = self.last_dts {
+            let delta = mapped - last;
+            if delta <= 0 {
+                // 时间戳回退（如不连续点）：接在上一帧之后
+                let target = last + self.duration();
+                self.offset += target - mapped;
+                mapped = target;
+            } else if delta <= MAX_FRAME_GAP {
+                self.frame_duration = delta;
+            }
+        }
+        self.last_dts = Some(mapped);
+        (mapped, mapped + composition)
+    }
+
+    /// 缺少时间戳的 PES 按上一帧的间隔顺延
+    fn advance(&mut self) -> Option<(i64, i64)> {
+        let dts = self.last_dts? + self.duration();
+        self.last_dts = Some(dts);
+        Some((dts, dts))
+    }
+
+    const fn duration(&self) -> i64 {
+        if self.frame_duration > 0 {
+            self.frame_duration
+        } else {
+            DEFAULT_FRAME_DURATION
+        }
+    }
+
+    /// 新时段的时间戳与上一时段无关，不再参照上一时段展开回绕
+    const fn start_period(&mut self) {
+        self.last_raw = None;
+        self.rebase = true;
+    }
+}
+
+/// 取 `value` 加减若干个回绕周期后最接近 `reference` 的值
+pub fn unwrap_timestamp(value: i64, reference: i64) -> i64 {
+    let base = reference - reference.rem_euclid(TIMESTAMP_WRAP) + value.rem_euclid(TIMESTAMP_WRAP);
+    [base - TIMESTAMP_WRAP, base, base + TIMESTAMP_WRAP]
+        .into_iter()
+        .min_by_key(|candidate| (candidate - reference).abs())
+        .unwrap_or(base)
+}
+
+/// 读取 PES 头中 5 字节的 33 位时间戳
+pub fn read_timestamp(bytes: &[u8]) -> i64 {
+    (i64::from(bytes[0] >> 1 & 0x07) << 30)
+        | (i64::from(bytes[1]) << 22)
+        | (i64::from(bytes[2] >> 1) << 15)
+        | (i64::from(bytes[3]) << 7)
+        | i64::from(bytes[4] >> 1)
+}
+
+struct EsTrack {
+    kind: StreamKind,
+    codec: Option<TrackCodec>,
+    /// 正在拼接的 PES
+    pes: Vec<u8>,
+    timeline: Timeline,
+    /// 视频：暂存的 VPS/SPS/PPS，凑齐后生成解码参数
+    params: [Option<Vec<u8>>; 3],
+    /// 视频：已写入解码参数的参数集，样本中相同的参数集会被去掉
+    parameter_sets: Vec<Vec<u8>>,
+    /// 视频：第一个关键帧之前的帧全部丢弃
+    started: bool,
+    /// 音频：跨 PES 的不完整帧
+    audio_buffer: Vec<u8>,
+    /// 音频：下一帧的时间戳（采样率刻度）
+    next_audio_ts: Option<i64>,
+}
+
+/// NAL 单元在转封装中的处理方式
+enum NalRole {
+    /// 访问单元分隔符，MP4 中不需要
+    Delimiter,
+    /// 参数集，值为 `params` 中的下标
+    Parameter(usize),
+    Keyframe,
+    Other,
+}
+
+impl EsTrack {
+    fn new(kind: StreamKind) -> Self {
+        Self {
+            kind,
+            codec: None,
+            pes: Vec::new(),
+            timeline: Timeline::default(),
+            params: [None, None, None],
+            parameter_sets: Vec::new(),
+            started: false,
+            audio_buffer: Vec::new(),
+            next_audio_ts: None,
+        }
+    }
+
+    fn nal_role(&self, header: u8) -> NalRole {
+        match self.kind {
+            StreamKind::H264 => match header & 0x1F {
+                9 => NalRole::Delimiter,
+                7 => NalRole::Parameter(1),
+                8 => NalRole::Parameter(2),
+                5 => NalRole::Keyframe,
+                _ => NalRole::Other,
+            },
+            _ => match (header >> 1) & 0x3F {
+                35 => NalRole::Delimiter,
+                32 => NalRole::Parameter(0),
+                33 => NalRole::Parameter(1),
+                34 => NalRole::Parameter(2),
+                16..=21 => NalRole::Keyframe,
+                _ => NalRole::Other,
+            },
+        }
+    }
+
+    /// 轨道已输出内容的结束时间（90kHz）
+    fn end_time(&self) -> Option<i64> {
+        if self.kind.is_video() {
+            Some(self.timeline.last_dts? + self.timeline.duration())
+        } else {
+            let timescale = i64::from(self.codec.as_ref()?.timescale());
+            Some(self.next_audio_ts? * i64::from(MPEG_TIMESCALE) / timescale)
+        }
+    }
+
+    /// 进入新时段：丢弃不完整的音频帧，视频从关键帧重新开始
+    fn start_period(&mut self) {
+        self.timeline.start_period();
+        self.audio_buffer.clear();
+        self.started = false;
+    }
+
+    fn process_pes(
+        &mut self,
+        index: usize,
+        period: &mut Option<PeriodStart>,
+        frames: &mut Vec<Frame>,
+    ) -> Result<()> {
+        let pes = std::mem::take(&mut self.pes);
+        if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+            return Ok(());
+        }
+        let start = 9 + usize::from(pes[8]);
+        let declared = usize::from(u16::from_be_bytes([pes[4], pes[5]]));
+        let end = if declared > 0 {
+            (6 + declared).min(pes.len())
+        } else {
+            pes.len()
+        };
+        if start > end {
+            return Ok(());
+        }
+
+        let flags = pes[7] >> 6;
+        let pts = (flags & 0x02 != 0 && start >= 14).then(|| read_timestamp(&pes[9..14]));
+        let dts = if flags == 0x03 && start >= 19 {
+            Some(read_timestamp(&pes[14..19]))
+        } else {
+            pts
+        };
+        let payload = &pes[start..end];
+
+        if self.kind.is_video() {
+            let timestamps = match (dts, pts) {
+                (Some(dts), Some(pts)) => Some(self.timeline.map(dts, pts, period)),
+                _ => self.timeline.advance(),
+            };
+            match timestamps {
+                Some((dts, pts)) => self.push_video(index, dts, pts, payload, frames),
+                None => Ok(()),
+            }
+        } else {
+            let pts = pts.map(|pts| self.timeline.map(pts, pts, period).0);
+            self.push_audio(index, pts, payload, frames)
+        }
+    }
+
+    fn push_video(
+        &mut self,
+        index: usize,
+        dts: i64,
+        pts: i64,
+        payload: &[u8],
+        frames: &mut Vec<Frame>,
+    ) -> Result<()> {
+        let mut data = Vec::with_capacity(payload.len() + 16);
+        let mut keyframe = false;
+        let starts = find_start_codes(payload);
+        for (i, &(_, begin)) in starts.iter().enumerate() {
+            let end = starts
+                .get(i + 1)
+                .map_or(payload.len(), |&(prefix, _)| prefix);
+            let unit = &payload[begin..end];
+            // 去掉下一个起始码之前的补零
+            let nal = &unit[..unit.iter().rposition(|&b| b != 0).map_or(0, |p| p + 1)];
+            let Some(&header) = nal.first() else {
+                continue;
+            };
+
+            match self.nal_role(header) {
+                NalRole::Delimiter => continue,
+                NalRole::Parameter(slot) => {
+                    if self.parameter_sets.iter().any(|p| p == nal) {
+                        continue;
+                    }
+                    if self.codec.is_none() {
+                        self.params[slot] = Some(nal.to_vec());
+                        continue;
+                    }
+                }
+                NalRole::Keyframe => keyframe = true,
+                NalRole::Other => {}
+            }
+
+            let len = u32::try_from(nal.len()).map_err(|_| DownloadError::remux("NAL 单元过大"))?;
+            data.extend_from_slice(&len.to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+
+        if self.codec.is_none() {
+            self.configure_video()?;
+        }
+        if self.codec.is_none() || data.is_empty() {
+            return Ok(());
+        }
+        if !self.started {
+            if !keyframe {
+                return Ok(());
+            }
+            self.started = true;
+        }
+
+        frames.push(Frame {
+            track: index,
+            dts,
+            pts,
+            keyframe,
+            data,
+        });
+        Ok(())
+    }
+
+    /// 参数集凑齐后生成 avcC / hvcC
+    fn configure_video(&mut self) -> Result<()> {
+        let [vps, Some(sps), Some(pps)] = &self.params else {
+            return Ok(());
+        };
+        let codec = match (self.kind, vps) {
+            (StreamKind::H264, _) => {
+                let info =
+                    parse_avc_sps(sps).ok_or_else(|| DownloadError::remux("H.264 SPS 解析失败"))?;
+                self.parameter_sets = vec![sps.clone(), pps.clone()];
+                TrackCodec::Avc {
+                    width: info.width,
+                    height: info.height,
+                    config: avc_config(sps, pps, &info),
+                }
+            }
+            (_, Some(vps)) => {
+                let info = parse_hevc_sps(sps)
+                    .ok_or_else(|| DownloadError::remux("H.265 SPS 解析失败"))?;
+                self.parameter_sets = vec![vps.clone(), sps.clone(), pps.clone()];
+                TrackCodec::Hevc {
+                    width: info.width,
+                    height: info.height,
+                    config: hevc_config(vps, sps, pps, &info),
+                }
+            }
+            _ => return Ok(()),
+        };
+        self.codec = Some(codec);
+        Ok(())
+    }
+
+    fn push_audio(
+        &mut self,
+        index: usize,
+        pts: Option<i64>,
+        payload: &[u8],
+        frames: &mut Vec<Frame>,
+    ) -> Result<()> {
+        self.audio_buffer.extend_from_slice(payload);
+        let mut pes_ts = pts;
+        let mut pos = 0;
+
+        while self.audio_buffer.len() - pos >= 7 {
+            let rest = &self.audio_buffer[pos..];
+            let Some(frame) = parse_audio_frame(self.kind, rest)? else {
+                // 不是帧头，逐字节重新同步
+                pos += 1;
+                continue;
+            };
+            if rest.len() < frame.frame_len {
+                break;
+            }
+
+            match &self.codec {
+                None => self.codec = Some(frame.codec.clone()),
+                Some(codec) if *codec != frame.codec => {
+                    return Err(DownloadError::remux("音频参数在流中发生变化"));
+                }
+                Some(_) => {}
+            }
+
+            let samples = i64::from(frame.samples);
+            // PES 时间戳与连续推算的时间相差不超过一帧时保持连续，出现空缺时以 PES 时间戳为准
+            if let Some(ts) = pes_ts.take() {
+                let ts = ts * i64::from(frame.codec.timescale()) / i64::from(MPEG_TIMESCALE);
+                self.next_audio_ts = Some(match self.next_audio_ts {
+                    Some(next) if ts <= next + samples => next,
+                    _ => ts,
+                });
+            }
+
+            if let Some(ts) = self.next_audio_ts {
+                frames.push(Frame {
+                    track: index,
+                    dts: ts,
+                    pts: ts,
+                    keyframe: true,
+                    data: rest[frame.header_len..frame.frame_len].to_vec(),
+                });
+                self.next_audio_ts = Some(ts + samples);
+            }
+            pos += frame.frame_len;
+        }
+
+        self.audio_buffer.drain(..pos);
+        Ok(())
+    }
+}
+
+/// 增量式 MPEG-TS 解复用器，按 PMT 中的顺序编号轨道
+///
+/// 只处理第一个节目；不同片段中同一类流的 PID 变化时仍归入同一条轨道。
+#[derive(Default)]
+pub struct TsDemuxer {
+    /// 不足一个 TS 包的剩余数据
+    remainder: Vec<u8>,
+    pmt_pid: Option<u16>,
+    pid_tracks: HashMap<u16, usize>,
+    tracks: Vec<EsTrack>,
+    period: Option<PeriodStart>,
+}
+
+impl TsDemuxer {
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// 轨道的解码参数，尚未确定时返回 `None`
+    pub fn codec(&self, track: usize) -> Option<&TrackCodec> {
+        self.tracks.get(track)?.codec.as_ref()
+    }
+
+    /// 送入一段 TS 数据，解出的访问单元追加到 `frames`
+    pub fn push(&mut self, data: &[u8], frames: &mut Vec<Frame>) -> Result<()> {
+        let mut input = data;
+        if !self.remainder.is_empty() {
+            let need = TS_PACKET_SIZE - self.remainder.len();
+            if input.len() < need {
+                self.remainder.extend_from_slice(input);
+                return Ok(());
+            }
+            let mut packet = std::mem::take(&mut self.remainder);
+            packet.extend_from_slice(&input[..need]);
+            input = &input[need..];
+            self.handle_packet(&packet, frames)?;
+        }
+
+        let mut pos = 0;
+        while pos < input.len() {
+            if input[pos] != TS_SYNC_BYTE {
+                pos += 1;
+                continue;
+            }
+            if input.len() - pos < TS_PACKET_SIZE {
+                break;
+            }
+            self.handle_packet(&input[pos..pos + TS_PACKET_SIZE], frames)?;
+            pos += TS_PACKET_SIZE;
+        }
+        self.remainder.extend_from_slice(&input[pos..]);
+        Ok(())
+    }
+
+    /// 输入结束，处理所有未完成的 PES
+    pub fn finish(&mut self, frames: &mut Vec<Frame>) -> Result<()> {
+        for (index, track) in self.tracks.iter_mut().enumerate() {
+            track.process_pes(index, &mut self.period, frames)?;
+        }
+        Ok(())
+    }
+
+    /// 不连续点（`EXT-X-DISCONTINUITY`）：结束上一时段，
+    /// 之后的时间戳按新时段重新计算，接在上一时段结尾，各轨道保持同步
+    pub fn discontinuity(&mut self, frames: &mut Vec<Frame>) -> Result<()> {
+        self.finish(frames)?;
+        self.remainder.clear();
+        self.period = self
+            .tracks
+            .iter()
+            .filter_map(EsTrack::end_time)
+            .max()
+            .map(|start| PeriodStart {
+                start,
+                offset: None,
+            });
+        for track in &mut self.tracks {
+            track.start_period();
+        }
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, buf: &[u8], frames: &mut Vec<Frame>) -> Result<()> {
+        let Some(packet) = parse_packet(buf) else {
+            return Ok(());
+        };
+
+        if packet.pid == PAT_PID || Some(packet.pid) == self.pmt_pid {
+            if packet.payload_unit_start
+                && let Some(range) = psi_section_range(packet.payload)
+            {
+                let section = &packet.payload[range];
+                if packet.pid == PAT_PID {
+                    self.pmt_pid = parse_pat(section).first().copied();
+                } else {
+                    self.update_streams(section)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let Some(&index) = self.pid_tracks.get(&packet.pid) else {
+            return Ok(());
+        };
+        let track = &mut self.tracks[index];
+        if packet.payload_unit_start {
+            track.process_pes(index, &mut self.period, frames)?;
+        } else if track.pes.is_empty() {
+            // 没有收到 PES 开头的数据无法使用
+            return Ok(());
+        }
+        track.pes.extend_from_slice(packet.payload);
+        Ok(())
+    }
+
+    /// 按 PMT 建立 PID 与轨道的对应：第 n 路视频/音频流对应第 n 条视频/音频轨道
+    fn update_streams(&mut self, section: &[u8]) -> Result<()> {
+        let mut pid_tracks = HashMap::new();
+        let (mut video, mut audio) = (0, 0);
+        for stream in parse_pmt(section) {
+            let Some(kind) = StreamKind::from_stream_type(stream.stream_type)? else {
+                continue;
+            };
+            let counter = if kind.is_video() {
+                &mut video
+            } else {
+                &mut audio
+            };
+            let ordinal = *counter;
+            *counter += 1;
+
+            let existing = self
+                .tracks
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| t.kind.is_video() == kind.is_video())
+                .nth(ordinal)
+                .map(|(i, _)| i);
+            let index = match existing {
+                Some(i) if self.tracks[i].kind != kind => {
+                    return Err(DownloadError::remux("流的编码格式在中途发生变化"));
+                }
+                Some(i) => i,
+                None => {
+                    self.tracks.push(EsTrack::new(kind));
+                    self.tracks.len() - 1
+                }
+            };
+            pid_tracks.insert(stream.pid, index);
+        }
+        self.pid_tracks = pid_tracks;
+        Ok(())
+    }
+}
+
+/// 按位读取 RBSP
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(u32::from(bit))
+    }
+
+    fn bits(&mut self, count: usize) -> Option<u32> {
+        (0..count).try_fold(0, |acc, _| Some((acc << 1) | self.bit()?))
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        if self.pos + count > self.data.len() * 8 {
+            return None;
+        }
+        self.pos += count;
+        Some(())
+    }
+
+    /// 无符号指数哥伦布编码
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    /// 有符号指数哥伦布编码
+    fn se(&mut self) -> Option<i32> {
+        let value = i64::from(self.ue()?);
+        let value = if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -value / 2
+        };
+        i32::try_from(value).ok()
+    }
+}
+
+/// SPS 中转封装需要的字段
+struct SpsInfo {
+    width: u16,
+    height: u16,
+    chroma_format_idc: u8,
+    bit_depth_luma_minus8: u8,
+    bit_depth_chroma_minus8: u8,
+    /// H.265：general profile_tier_level 的 12 个字节
+    general_ptl: [u8; 12],
+    /// H.265：`sps_max_sub_layers_minus1`
+    max_sub_layers_minus1: u8,
+    /// H.265：`sps_temporal_id_nesting_flag`
+    temporal_id_nesting: bool,
+}
+
+/// 裁剪后的画面尺寸，`sub_width`/`sub_height` 为色度子采样对应的裁剪单位
+fn cropped_size(
+    width: u32,
+    height: u32,
+    crop: [u32; 4],
+    sub_width: u32,
+    sub_height: u32,
+) -> Option<(u16, u16)> {
+    let width = width.checked_sub(sub_width.checked_mul(crop[0].checked_add(crop[1])?)?)?;
+    let height = height.checked_sub(sub_height.checked_mul(crop[2].checked_add(crop[3])?)?)?;
+    Some((u16::try_from(width).ok()?, u16::try_from(height).ok()?))
+}
+
+fn read_crop(reader: &mut BitReader<'_>) -> Option<[u32; 4]> {
+    if reader.bit()? == 0 {
+        return Some([0; 4]);
+    }
+    Some([reader.ue()?, reader.ue()?, reader.ue()?, reader.ue()?])
+}
+
+const fn chroma_subsampling(chroma_format_idc: u32) -> (u32, u32) {
+    match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    }
+}
+
+fn skip_scaling_list(reader: &mut BitReader<'_>, size: usize) -> Option<()> {
+    let (mut last, mut next) = (8, 8);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + reader.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/// 解析 H.264 SPS（含 NAL 头）
+fn parse_avc_sps(nal: &[u8]) -> Option<SpsInfo> {
+    let rbsp = remove_emulation_prevention(nal.get(1..)?);
+    let mut r = BitReader::new(&rbsp);
+    let profile_idc = r.bits(8)?;
+    r.skip(16)?;
+    r.ue()?;
+
+    let (mut chroma_format_idc, mut luma, mut chroma) = (1, 0, 0);
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 {
+            r.skip(1)?;
+        }
+        luma = r.ue()?;
+        chroma = r.ue()?;
+        r.skip(1)?;
+        if r.bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.bit()? == 1 {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.ue()?;
+    match r.ue()? {
+        0 => {
+            r.ue()?;
+        }
+        1 => {
+            r.skip(1)?;
+            r.se()?;
+            r.se()?;
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?;
+    r.skip(1)?;
+    let width_in_mbs = r.ue()? + 1;
+    let height_in_map_units = r.ue()? + 1;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.skip(1)?;
+    }
+    r.skip(1)?;
+    let crop = read_crop(&mut r)?;
+
+    let (sub_width, sub_height) = chroma_subsampling(chroma_format_idc);
+    let (width, height) = cropped_size(
+        width_in_mbs.checked_mul(16)?,
+        (2 - frame_mbs_only)
+            .checked_mul(height_in_map_units)?
+            .checked_mul(16)?,
+        crop,
+        sub_width,
+        sub_height * (2 - frame_mbs_only),
+    )?;
+
+    Some(SpsInfo {
+        width,
+        height,
+        chroma_format_idc: u8::try_from(chroma_format_idc).ok()?,
+        bit_depth_luma_minus8: u8::try_from(luma).ok()?,
+        bit_depth_chroma_minus8: u8::try_from(chroma).ok()?,
+        general_ptl: [0; 12],
+        max_sub_layers_minus1: 0,
+        temporal_id_nesting: false,
+    })
+}
+
+/// 解析 H.265 SPS（含 2 字节 NAL 头）
+fn parse_hevc_sps(nal: &[u8]) -> Option<SpsInfo> {
+    let rbsp = remove_emulation_prevention(nal.get(2..)?);
+    let mut r = BitReader::new(&rbsp);
+    r.skip(4)?;
+    let max_sub_layers_minus1 = r.bits(3)? as usize;
+    let temporal_id_nesting = r.bit()? == 1;
+    let general_ptl: [u8; 12] = rbsp.get(1..13)?.try_into().ok()?;
+    r.skip(96)?;
+
+    let mut sub_layers = [(false, false); 8];
+    for layer in sub_layers.iter_mut().take(max_sub_layers_minus1) {
+        *layer = (r.bit()? == 1, r.bit()? == 1);
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for &(profile_present, level_present) in sub_layers.iter().take(max_sub_layers_minus1) {
+        if profile_present {
+            r.skip(88)?;
+        }
+        if level_present {
+            r.skip(8)?;
+        }
+    }
+
+    r.ue()?;
+    let chroma_format_idc = r.ue()?;
+    if chroma_format_idc == 3 {
+        r.skip(1)?;
+    }
+    let width = r.ue()?;
+    let height = r.ue()?;
+    let crop = read_crop(&mut r)?;
+    let luma = r.ue()?;
+    let chroma = r.ue()?;
+
+    let (sub_width, sub_height) = chroma_subsampling(chroma_format_idc);
+    let (width, height) = cropped_size(width, height, crop, sub_width, sub_height)?;
+    Some(SpsInfo {
+        width,
+        height,
+        chroma_format_idc: u8::try_from(chroma_format_idc).ok()?,
+        bit_depth_luma_minus8: u8::try_from(luma).ok()?,
+        bit_depth_chroma_minus8: u8::try_from(chroma).ok()?,
+        general_ptl,
+        max_sub_layers_minus1: max_sub_layers_minus1 as u8,
+        temporal_id_nesting,
+    })
+}
+
+fn push_nal_with_length(out: &mut Vec<u8>, nal: &[u8]) {
+    out.extend_from_slice(&u16::try_from(nal.len()).unwrap_or(u16::MAX).to_be_bytes());
+    out.extend_from_slice(nal);
+}
+
+/// 生成 AVCDecoderConfigurationRecord
+fn avc_config(sps: &[u8], pps: &[u8], info: &SpsInfo) -> Vec<u8> {
+    let mut config = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+    push_nal_with_length(&mut config, sps);
+    config.push(1);
+    push_nal_with_length(&mut config, pps);
+    if !matches!(sps[1], 66 | 77 | 88) {
+        config.extend_from_slice(&[
+            0xFC | info.chroma_format_idc,
+            0xF8 | info.bit_depth_luma_minus8,
+            0xF8 | info.bit_depth_chroma_minus8,
+            0,
+        ]);
+    }
+    config
+}
+
+/// 生成 HEVCDecoderConfigurationRecord
+fn hevc_config(vps: &[u8], sps: &[u8], pps: &[u8], info: &SpsInfo) -> Vec<u8> {
+    let mut config = vec![1];
+    config.extend_from_slice(&info.general_ptl);
+    config.extend_from_slice(&[
+        0xF0,
+        0x00,
+        0xFC,
+        0xFC | info.chroma_format_idc,
+        0xF8 | info.bit_depth_luma_minus8,
+        0xF8 | info.bit_depth_chroma_minus8,
+        0x00,
+        0x00,
+        ((info.max_sub_layers_minus1 + 1) << 3) | (u8::from(info.temporal_id_nesting) << 2) | 0x03,
+        3,
+    ]);
+    for (nal_type, nal) in [(32u8, vps), (33, sps), (34, pps)] {
+        config.push(0x80 | nal_type);
+        config.extend_from_slice(&1u16.to_be_bytes());
+        push_nal_with_length(&mut config, nal);
+    }
+    config
+}
+
+/// 音频帧头解析结果
+struct AudioFrame {
+    /// 写入 MP4 时去掉的帧头长度（MP3 为 0）
+    header_len: usize,
+    frame_len: usize,
+    /// 每帧的采样数
+    samples: u32,
+    codec: TrackCodec,
+}
+
+/// 解析 `data` 开头的音频帧头，不是合法帧头时返回 `None`
+fn parse_audio_frame(kind: StreamKind, data: &[u8]) -> Result<Option<AudioFrame>> {
+    match kind {
+        StreamKind::Aac => parse_adts(data),
+        _ => Ok(parse_mpeg_audio(data)),
+    }
+}
+
+/// 解析 ADTS 帧头，并生成对应的 AudioSpecificConfig
+fn parse_adts(data: &[u8]) -> Result<Option<AudioFrame>> {
+    if data.len() < 7 || data[0] != 0xFF || data[1] & 0xF6 != 0xF0 {
+        return Ok(None);
+    }
+    let header_len = if data[1] & 0x01 != 0 { 7 } else { 9 };
+    let object_type = (data[2] >> 6) + 1;
+    let frequency_index = (data[2] >> 2) & 0x0F;
+    let channels = ((data[2] & 0x01) << 2) | (data[3] >> 6);
+    let frame_len = (usize::from(data[3] & 0x03) << 11)
+        | (usize::from(data[4]) << 3)
+        | (usize::from(data[5]) >> 5);
+    let Some(&sample_rate) = AAC_SAMPLE_RATES.get(usize::from(frequency_index)) else {
+        return Ok(None);
+    };
+    if frame_len <= header_len {
+        return Ok(None);
+    }
+    if channels == 0 {
+        return Err(DownloadError::remux("不支持由 PCE 定义声道布局的 AAC"));
+    }
+    if data[6] & 0x03 != 0 {
+        return Err(DownloadError::remux("不支持每帧包含多个原始数据块的 ADTS"));
+    }
+
+    let config = vec![
+        (object_type << 3) | (frequency_index >> 1),
+        ((frequency_index & 0x01) << 7) | (channels << 3),
+    ];
+    Ok(Some(AudioFrame {
+        header_len,
+        frame_len,
+        samples: 1024,
+        codec: TrackCodec::Aac {
+            sample_rate,
+            channels: u16::from(channels),
+            config,
+        },
+    }))
+}
+
+/// 解析 MPEG-1/2/2.5 Layer I/II/III 音频帧头
+fn parse_mpeg_audio(data: &[u8]) -> Option<AudioFrame> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (data[1] >> 3) & 0x03;
+    let layer = (data[1] >> 1) & 0x03;
+    let bitrate_index = usize::from(data[2] >> 4);
+    let rate_index = usize::from((data[2] >> 2) & 0x03);
+    let padding = u32::from((data[2] >> 1) & 0x01);
+    let channels = if data[3] >> 6 == 3 { 1 } else { 2 };
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let table = match (mpeg1, layer) {
+        (true, 3) => 0,
+        (true, 2) => 1,
+        (true, _) => 2,
+        (false, 3) => 3,
+        (false, _) => 4,
+    };
+    let bitrate = MPEG_AUDIO_BITRATES[table][bitrate_index - 1] * 1000;
+    let sample_rate = [44100, 48000, 32000][rate_index]
+        >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+
+    let (samples, frame_len) = match layer {
+        3 => (384, (12 * bitrate / sample_rate + padding) * 4),
+        2 => (1152, 144 * bitrate / sample_rate + padding),
+        _ if mpeg1 => (1152, 144 * bitrate / sample_rate + padding),
+        _ => (576, 72 * bitrate / sample_rate + padding),
+    };
+
+    Some(AudioFrame {
+        header_len: 0,
+        frame_len: frame_len as usize,
+        samples,
+        codec: TrackCodec::Mp3 {
+            sample_rate,
+            channels,
+            object_type: if mpeg1 {
+                OBJECT_TYPE_MPEG1_AUDIO
+            } else {
+                OBJECT_TYPE_MPEG2_AUDIO
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按位写入 RBSP，用于构造 SPS
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        len: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: usize) {
+            for i in (0..count).rev() {
+                if self.len.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                if (value >> i) & 1 == 1 {
+                    *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+                }
+                self.len += 1;
+            }
+        }
+
+        fn ue(&mut self, value: u32) {
+            let code = value + 1;
+            let width = 32 - code.leading_zeros() as usize;
+            self.bits(0, width - 1);
+            self.bits(code, width);
+        }
+
+        /// 写入 rbsp_stop_one_bit 并补齐字节
+        fn finish(mut self) -> Vec<u8> {
+            self.bits(1, 1);
+            self.bytes
+        }
+    }
+
+    /// 构造 H.264 SPS（含 NAL 头），`profile_idc` 为 100 时写入 High 扩展字段
+    fn avc_sps(
+        profile_idc: u8,
+        width_in_mbs: u32,
+        height_in_mbs: u32,
+        crop_bottom: u32,
+    ) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.ue(0);
+        if profile_idc == 100 {
+            w.ue(1);
+            w.ue(0);
+            w.ue(0);
+            w.bits(0, 2);
+        }
+        w.ue(0);
+        w.ue(0);
+        w.ue(0);
+        w.ue(1);
+        w.bits(0, 1);
+        w.ue(width_in_mbs - 1);
+        w.ue(height_in_mbs - 1);
+        w.bits(0b11, 2);
+        if crop_bottom > 0 {
+            w.bits(1, 1);
+            for value in [0, 0, 0, crop_bottom] {
+                w.ue(value);
+            }
+        } else {
+            w.bits(0, 1);
+        }
+        w.bits(0, 1);
+
+        let mut sps = vec![0x67, profile_idc, 0x00, 40];
+        sps.extend(w.finish());
+        sps
+    }
+
+    /// 构造 ADTS 帧头，`crc` 为真时帧头为 9 字节
+    fn adts_header(
+        object_type: u8,
+        frequency_index: u8,
+        channels: u8,
+        frame_len: usize,
+        crc: bool,
+    ) -> Vec<u8> {
+        let mut header = vec![
+            0xFF,
+            if crc { 0xF0 } else { 0xF1 },
+            ((object_type - 1) << 6) | (frequency_index << 2) | (channels >> 2),
+            ((channels & 0x03) << 6) | (frame_len >> 11) as u8,
+            (frame_len >> 3) as u8,
+            ((frame_len & 0x07) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        if crc {
+            header.extend_from_slice(&[0, 0]);
+        }
+        header
+    }
+
+    #[test]
+    fn adts_header_converts_to_audio_specific_config() {
+        let frame = parse_adts(&adts_header(2, 4, 2, 17, false))
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.header_len, 7);
+        assert_eq!(frame.frame_len, 17);
+        assert_eq!(frame.samples, 1024);
+        assert_eq!(
+            frame.codec,
+            TrackCodec::Aac {
+                sample_rate: 44100,
+                channels: 2,
+                config: vec![0x12, 0x10],
+            }
+        );
+    }
+
+    #[test]
+    fn adts_header_with_crc_converts_to_audio_specific_config() {
+        let frame = parse_adts(&adts_header(2, 3, 1, 32, true))
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.header_len, 9);
+        assert_eq!(
+            frame.codec,
+            TrackCodec::Aac {
+                sample_rate: 48000,
+                channels: 1,
+                config: vec![0x11, 0x88],
+            }
+        );
+    }
+
+    #[test]
+    fn adts_header_rejects_invalid_input() {
+        assert!(
+            parse_adts(&[0x47, 0x40, 0x00, 0x10, 0, 0, 0])
+                .unwrap()
+                .is_none()
+        );
+        // 采样率下标 13 未定义
+        assert!(
+            parse_adts(&adts_header(2, 13, 2, 17, false))
+                .unwrap()
+                .is_none()
+        );
+        // 声道数 0 需要 PCE
+        assert!(parse_adts(&adts_header(2, 4, 0, 17, false)).is_err());
+    }
+
+    #[test]
+    fn avc_sps_applies_frame_cropping() {
+        let info = parse_avc_sps(&avc_sps(100, 120, 68, 4)).unwrap();
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.chroma_format_idc, 1);
+        assert_eq!(info.bit_depth_luma_minus8, 0);
+        assert_eq!(info.bit_depth_chroma_minus8, 0);
+    }
+
+    #[test]
+    fn baseline_avcc_has_no_chroma_extension() {
+        let sps = avc_sps(66, 20, 15, 0);
+        let pps = [0x68, 0xCE, 0x38, 0x80];
+        let info = parse_avc_sps(&sps).unwrap();
+        assert_eq!((info.width, info.height), (320, 240));
+
+        let mut expected = vec![1, 66, 0x00, 40, 0xFF, 0xE1, 0, sps.len() as u8];
+        expected.extend_from_slice(&sps);
+        expected.extend_from_slice(&[1, 0, pps.len() as u8]);
+        expected.extend_from_slice(&pps);
+        assert_eq!(avc_config(&sps, &pps, &info), expected);
+    }
+
+    #[test]
+    fn high_profile_avcc_has_chroma_extension() {
+        let sps = avc_sps(100, 120, 68, 4);
+        let pps = [0x68, 0xEB, 0xE3, 0xCB];
+        let info = parse_avc_sps(&sps).unwrap();
+        let config = avc_config(&sps, &pps, &info);
+        assert_eq!(config[..4], [1, 100, 0x00, 40]);
+        assert_eq!(config[config.len() - 4..], [0xFD, 0xF8, 0xF8, 0x00]);
+        assert_eq!(config.len(), 6 + 2 + sps.len() + 1 + 2 + pps.len() + 4);
+    }
+
+    #[test]
+    fn timestamp_unwraps_to_nearest_period() {
+        assert_eq!(
+            unwrap_timestamp(100, TIMESTAMP_WRAP - 100),
+            TIMESTAMP_WRAP + 100
+        );
+        assert_eq!(unwrap_timestamp(TIMESTAMP_WRAP - 100, 100), -100);
+        assert_eq!(unwrap_timestamp(5000, 4000), 5000);
+    }
+
+    #[test]
+    fn timeline_follows_33_bit_wrap() {
+        let mut timeline = Timeline::default();
+        let start = TIMESTAMP_WRAP - 3600;
+        assert_eq!(timeline.map(start, start, &mut None), (start, start));
+        assert_eq!(
+            timeline.map(0, 0, &mut None),
+            (TIMESTAMP_WRAP, TIMESTAMP_WRAP)
+        );
+        assert_eq!(
+            timeline.map(3600, 3600, &mut None),
+            (TIMESTAMP_WRAP + 3600, TIMESTAMP_WRAP + 3600)
+        );
+    }
+
+    #[test]
+    fn timeline_keeps_composition_offset() {
+        let mut timeline = Timeline::default();
+        assert_eq!(timeline.map(1000, 8200, &mut None), (1000, 8200));
+        // PTS 已回绕而 DTS 尚未回绕
+        let dts = TIMESTAMP_WRAP - 100;
+        let mut timeline = Timeline::default();
+        assert_eq!(timeline.map(dts, 3500, &mut None), (dts, dts + 3600));
+    }
+
+    #[test]
+    fn timeline_continues_after_timestamp_regression() {
+        let mut timeline = Timeline::default();
+        timeline.map(90_000, 90_000, &mut None);
+        timeline.map(93_600, 93_600, &mut None);
+        assert_eq!(timeline.map(0, 0, &mut None).0, 97_200);
+        assert_eq!(timeline.map(3600, 3600, &mut None).0, 100_800);
+        assert_eq!(timeline.advance(), Some((104_400, 104_400)));
+    }
+
+    #[test]
+    fn new_period_rebases_all_tracks_with_shared_offset() {
+        let (mut video, mut audio) = (Timeline::default(), Timeline::default());
+        video.map(900_000, 903_600, &mut None);
+        audio.map(901_000, 901_000, &mut None);
+        video.start_period();
+        audio.start_period();
+
+        let mut period = Some(PeriodStart {
+            start: 1_000_000,
+            offset: None,
+        });
+        assert_eq!(
+            video.map(10_000, 13_600, &mut period),
+            (1_000_000, 1_003_600)
+        );
+        assert_eq!(audio.map(12_000, 12_000, &mut period).0, 1_002_000);
+        assert_eq!(period.unwrap().offset, Some(990_000));
+    }
+}
@@ -1,52 +1,310 @@
-use crate::config::AES_KEY_LENGTH;
+use crate::config::{AES_KEY_LENGTH, TS_PACKET_SIZE, TS_SYNC_BYTE};
+use crate::downloader::ContainerFormat;
+use crate::downloader::sample_aes::decrypt_sample_aes_ts;
 use crate::error::{DownloadError, Result};
 use aes::Aes128;
-use aes::cipher::{BlockDecryptMut, KeyIvInit, block_padding::Pkcs7};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use bytes::Bytes;
+use log::info;
+use m3u8_rs::{Key, KeyMethod, MediaSegment};
+use std::collections::HashMap;
 type Aes128CbcDec = cbc::Decryptor<Aes128>;
 
-/// 解密TS片段数据
-pub fn decrypt_segment(data: &[u8], key: &[u8], segment_index: usize) -> Result<Vec<u8>> {
-    if key.len() != AES_KEY_LENGTH {
+/// 单个片段的解密参数
+#[derive(Debug, Clone)]
+pub struct SegmentKey {
+    pub method: KeyMethod,
+    pub key: Vec<u8>,
+    pub iv: [u8; 16],
+}
+
+/// 按片段的加密方式解密数据
+pub fn decrypt_segment(data: &[u8], segment_key: &SegmentKey) -> Result<Vec<u8>> {
+    if segment_key.key.len() != AES_KEY_LENGTH {
         return Err(DownloadError::key(format!(
             "AES 密钥长度必须为 {AES_KEY_LENGTH} 字节"
         )));
     }
 
-    // 使用片段索引作为IV（初始化向量）
-    let mut iv = [0u8; 16];
-    let iv_bytes = (segment_index as u128).to_be_bytes();
-    iv.copy_from_slice(&iv_bytes);
+    match &segment_key.method {
+        KeyMethod::AES128 => decrypt_aes128(data, &segment_key.key, &segment_key.iv),
+        KeyMethod::SampleAES => decrypt_sample_aes_ts(data, &segment_key.key, &segment_key.iv),
+        method => Err(DownloadError::decryption(format!(
+            "不支持的加密方式: {method}"
+        ))),
+    }
+}
+
+/// AES-128 CBC 整段解密
+///
+/// 标准流使用 PKCS7 填充；部分服务端输出的 TS 密文没有填充，
+/// 此时保留解密结果的完整长度。
+fn decrypt_aes128(data: &[u8], key: &[u8], iv: &[u8; 16]) -> Result<Vec<u8>> {
+    let mut decryptor = Aes128CbcStream::new(key, iv)?;
+    let mut decrypted = decryptor.update(data);
+    decrypted.extend(decryptor.finish()?);
+    Ok(decrypted)
+}
+
+/// AES 分组长度（字节）
+const AES_BLOCK_SIZE: usize = 16;
+
+/// AES-128 CBC 流式解密
+///
+/// 密文可按任意大小分块输入，解密结果随之输出；始终保留最后一个分组，
+/// 在 [`finish`](Self::finish) 时校验并去除 PKCS7 填充。
+pub struct Aes128CbcStream {
+    cipher: Aes128CbcDec,
+    pending: Vec<u8>,
+    input_len: usize,
+    /// 明文的第一个字节，用于识别没有填充的 TS 数据
+    first_byte: Option<u8>,
+}
+
+impl Aes128CbcStream {
+    pub fn new(key: &[u8], iv: &[u8; 16]) -> Result<Self> {
+        if key.len() != AES_KEY_LENGTH {
+            return Err(DownloadError::key(format!(
+                "AES 密钥长度必须为 {AES_KEY_LENGTH} 字节"
+            )));
+        }
+        Ok(Self {
+            cipher: Aes128CbcDec::new(key.into(), iv.into()),
+            pending: Vec::with_capacity(AES_BLOCK_SIZE * 2),
+            input_len: 0,
+            first_byte: None,
+        })
+    }
+
+    /// 输入一块密文，返回可以确定不含填充的明文
+    pub fn update(&mut self, data: &[u8]) -> Vec<u8> {
+        self.input_len += data.len();
+        self.pending.extend_from_slice(data);
+        // 至少保留一个完整分组：它可能是带填充的最后一个分组
+        let ready = self.pending.len().saturating_sub(1) / AES_BLOCK_SIZE * AES_BLOCK_SIZE;
+        let mut decrypted: Vec<u8> = self.pending.drain(..ready).collect();
+        self.decrypt_blocks(&mut decrypted);
+        decrypted
+    }
+
+    /// 解密最后一个分组并去除 PKCS7 填充
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        if !self.input_len.is_multiple_of(AES_BLOCK_SIZE) {
+            return Err(DownloadError::decryption(format!(
+                "密文长度 {} 不是16的整数倍",
+                self.input_len
+            )));
+        }
+        let mut last = std::mem::take(&mut self.pending);
+        self.decrypt_blocks(&mut last);
+        if let Some(pad) = pkcs7_padding_len(self.input_len, self.first_byte, &last) {
+            last.truncate(last.len() - pad);
+        }
+        Ok(last)
+    }
+
+    fn decrypt_blocks(&mut self, data: &mut [u8]) {
+        for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+            self.cipher
+                .decrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        if self.first_byte.is_none() {
+            self.first_byte = data.first().copied();
+        }
+    }
+}
+
+/// 返回最后一个分组中 PKCS7 填充的长度，没有有效填充时返回 `None`
+///
+/// `total_len` 为去除填充前的明文长度。带填充的 TS 明文长度不可能是 188 的整数倍，
+/// 因此以同步字节开头且长度为 188 整数倍的明文视为没有填充，
+/// 即使末尾字节恰好构成合法的填充也保留。
+fn pkcs7_padding_len(total_len: usize, first_byte: Option<u8>, last_block: &[u8]) -> Option<usize> {
+    if total_len.is_multiple_of(TS_PACKET_SIZE) && first_byte == Some(TS_SYNC_BYTE) {
+        return None;
+    }
+    let pad = usize::from(*last_block.last()?);
+    if pad == 0 || pad > AES_BLOCK_SIZE || pad > last_block.len() {
+        return None;
+    }
+    last_block[last_block.len() - pad..]
+        .iter()
+        .all(|&b| usize::from(b) == pad)
+        .then_some(pad)
+}
+
+/// 片段的流式解密
+///
+/// AES-128 按分组边下载边解密；SAMPLE-AES 需要解析完整的 TS 包结构，
+/// 仍缓存整段后再解密。
+pub enum SegmentDecryptor {
+    Plain,
+    Aes128(Box<Aes128CbcStream>),
+    Buffered { key: SegmentKey, data: Vec<u8> },
+}
+
+impl SegmentDecryptor {
+    pub fn new(key: Option<&SegmentKey>) -> Result<Self> {
+        Ok(match key {
+            None => Self::Plain,
+            Some(key) if key.method == KeyMethod::AES128 => {
+                Self::Aes128(Box::new(Aes128CbcStream::new(&key.key, &key.iv)?))
+            }
+            Some(key) => Self::Buffered {
+                key: key.clone(),
+                data: Vec::new(),
+            },
+        })
+    }
+
+    /// 输入一块数据，返回可以写出的明文
+    pub fn update(&mut self, chunk: Bytes) -> Bytes {
+        match self {
+            Self::Plain => chunk,
+            Self::Aes128(decryptor) => Bytes::from(decryptor.update(&chunk)),
+            Self::Buffered { data, .. } => {
+                data.extend_from_slice(&chunk);
+                Bytes::new()
+            }
+        }
+    }
+
+    /// 结束输入，返回剩余的明文
+    pub fn finish(self) -> Result<Bytes> {
+        match self {
+            Self::Plain => Ok(Bytes::new()),
+            Self::Aes128(decryptor) => decryptor.finish().map(Bytes::from),
+            Self::Buffered { key, data } => decrypt_segment(&data, &key).map(Bytes::from),
+        }
+    }
+}
+
+/// 计算片段的IV（初始化向量）
+///
+/// 按 HLS 规范：优先使用 `EXT-X-KEY` 的 `IV` 属性，
+/// 否则使用片段的媒体序列号（大端序填充到16字节）。
+pub fn segment_iv(key: Option<&Key>, media_sequence: u64) -> Result<[u8; 16]> {
+    if let Some(iv) = key.and_then(|k| k.iv.as_deref()) {
+        return parse_hex_iv(iv);
+    }
+    Ok(u128::from(media_sequence).to_be_bytes())
+}
 
-    let cipher = Aes128CbcDec::new(key.into(), &iv.into());
+/// 解析 `0x` 开头的十六进制IV
+fn parse_hex_iv(iv: &str) -> Result<[u8; 16]> {
+    let hex = iv
+        .strip_prefix("0x")
+        .or_else(|| iv.strip_prefix("0X"))
+        .unwrap_or(iv);
+    if hex.is_empty() || hex.len() > 32 {
+        return Err(DownloadError::key(format!("IV 格式无效: {iv}")));
+    }
+    let value = u128::from_str_radix(hex, 16)
+        .map_err(|e| DownloadError::key(format!("IV 格式无效: {iv} - {e}")))?;
+    Ok(value.to_be_bytes())
+}
 
-    // 解密数据
-    let mut decrypted = data.to_owned();
-    let decrypted_data = cipher
-        .decrypt_padded_mut::<Pkcs7>(&mut decrypted)
-        .map_err(|e| DownloadError::decryption(format!("解密失败: {e:?}")))?;
+/// 片段前是否有不带 IV 的 `EXT-X-KEY:METHOD=NONE`
+///
+/// m3u8-rs 会拒绝这种写法（实际上是合法的），把它当作未知标签保留下来。
+fn has_method_none_tag(segment: &MediaSegment) -> bool {
+    segment.unknown_tags.iter().any(|tag| {
+        tag.tag == "X-KEY"
+            && tag
+                .rest
+                .as_deref()
+                .is_some_and(|rest| rest.trim().eq_ignore_ascii_case("METHOD=NONE"))
+    })
+}
 
-    Ok(decrypted_data.to_vec())
+/// 计算每个片段实际生效的 `EXT-X-KEY`
+///
+/// 解析器只把 `EXT-X-KEY` 挂在紧随其后的片段上，
+/// 这里向后传递，直到遇到下一个 `EXT-X-KEY`。`METHOD=NONE` 视为未加密。
+pub fn active_keys(segments: &[MediaSegment]) -> Vec<Option<Key>> {
+    let mut current: Option<Key> = None;
+    segments
+        .iter()
+        .map(|segment| {
+            if has_method_none_tag(segment) {
+                current = None;
+            }
+            if let Some(key) = &segment.key {
+                current = match key.method {
+                    KeyMethod::None => None,
+                    _ => Some(key.clone()),
+                };
+            }
+            current.clone()
+        })
+        .collect()
 }
 
-/// 从M3U8内容中提取加密密钥
-pub async fn extract_encryption_key(
-    m3u8_content: &str,
+/// 已下载密钥的缓存（密钥URI -> 密钥）
+pub type KeyCache = HashMap<String, Vec<u8>>;
+
+/// 为每个片段解析实际使用的密钥（支持密钥轮换）
+///
+/// 同一个密钥URI只下载一次，`METHOD=NONE` 的片段返回 `None`。
+/// SAMPLE-AES 目前只支持 TS 片段，fMP4 播放列表直接报错。
+pub async fn resolve_segment_keys(
+    segments: &[MediaSegment],
+    media_sequence: u64,
     client: &reqwest::Client,
     base_url: &url::Url,
-) -> Result<Option<Vec<u8>>> {
-    // 查找 EXT-X-KEY 标签
-    for line in m3u8_content.lines() {
-        if line.starts_with("#EXT-X-KEY:")
-            && let Some(uri_start) = line.find("URI=\"")
+    cache: &mut KeyCache,
+) -> Result<Vec<Option<SegmentKey>>> {
+    let mut result = Vec::with_capacity(segments.len());
+    let container = ContainerFormat::detect(segments);
+
+    for (i, active) in active_keys(segments).iter().enumerate() {
+        let Some(key) = active else {
+            result.push(None);
+            continue;
+        };
+
+        if !matches!(key.method, KeyMethod::AES128 | KeyMethod::SampleAES) {
+            return Err(DownloadError::decryption(format!(
+                "不支持的加密方式: {}",
+                key.method
+            )));
+        }
+        if key.method == KeyMethod::SampleAES && container == ContainerFormat::Fmp4 {
+            return Err(DownloadError::decryption(
+                "暂不支持 fMP4 片段的 SAMPLE-AES 解密",
+            ));
+        }
+        if let Some(format) = key.keyformat.as_deref()
+            && format != "identity"
         {
-            let uri_start = uri_start + 5; // "URI=\"的长度
-            if let Some(uri_end) = line[uri_start..].find('\"') {
-                let key_uri = &line[uri_start..uri_start + uri_end];
-                return Ok(Some(download_key(client, base_url, key_uri).await?));
-            }
+            return Err(DownloadError::decryption(format!(
+                "不支持的密钥格式: {format}"
+            )));
         }
+
+        let key_uri = key
+            .uri
+            .as_deref()
+            .ok_or_else(|| DownloadError::key("EXT-X-KEY 缺少 URI 属性"))?;
+        let key_url = crate::utils::resolve_url(base_url, key_uri)?;
+        let key_bytes = if let Some(cached) = cache.get(&key_url) {
+            cached.clone()
+        } else {
+            let downloaded = download_key(client, base_url, &key_url).await?;
+            info!("检测到加密流，已获取密钥: {key_url}");
+            cache.insert(key_url, downloaded.clone());
+            downloaded
+        };
+
+        result.push(Some(SegmentKey {
+            method: key.method.clone(),
+            key: key_bytes,
+            iv: segment_iv(Some(key), media_sequence + i as u64)?,
+        }));
     }
-    Ok(None)
+
+    Ok(result)
 }
 
 /// 下载密钥
@@ -64,3 +322,103 @@ async fn download_key(
 
     Ok(response.bytes().await?.to_vec())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+
+    const KEY: [u8; 16] = [0x11; 16];
+    const IV: [u8; 16] = [0x22; 16];
+
+    fn encrypt(plain: &[u8]) -> Vec<u8> {
+        let mut cipher = cbc::Encryptor::<Aes128>::new(&KEY.into(), &IV.into());
+        let mut data = plain.to_vec();
+        for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+            cipher.encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        data
+    }
+
+    fn pkcs7(plain: &[u8]) -> Vec<u8> {
+        let pad = AES_BLOCK_SIZE - plain.len() % AES_BLOCK_SIZE;
+        let mut data = plain.to_vec();
+        data.extend(std::iter::repeat_n(pad as u8, pad));
+        data
+    }
+
+    /// `packets` 个 TS 包，最后一个包以 `tail` 结尾
+    fn ts_packets(packets: usize, tail: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = (0..packets * TS_PACKET_SIZE)
+            .map(|i| {
+                if i % TS_PACKET_SIZE == 0 {
+                    TS_SYNC_BYTE
+                } else {
+                    0x5A
+                }
+            })
+            .collect();
+        let len = data.len();
+        data[len - tail.len()..].copy_from_slice(tail);
+        data
+    }
+
+    fn aes128_key() -> SegmentKey {
+        SegmentKey {
+            method: KeyMethod::AES128,
+            key: KEY.to_vec(),
+            iv: IV,
+        }
+    }
+
+    /// 按 `chunk` 字节分块流式解密
+    fn decrypt_streaming(data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut decryptor = Aes128CbcStream::new(&KEY, &IV).unwrap();
+        let mut plain = Vec::new();
+        for part in data.chunks(chunk) {
+            plain.extend(decryptor.update(part));
+        }
+        plain.extend(decryptor.finish().unwrap());
+        plain
+    }
+
+    #[test]
+    fn padded_ts_is_unpadded() {
+        let plain = ts_packets(3, &[]);
+        let encrypted = encrypt(&pkcs7(&plain));
+        assert_eq!(decrypt_segment(&encrypted, &aes128_key()).unwrap(), plain);
+        assert_eq!(decrypt_streaming(&encrypted, 100), plain);
+    }
+
+    #[test]
+    fn unpadded_ts_keeps_full_length() {
+        // 3 个包共 564 字节不是 16 的整数倍，取 4 个包（752 字节）
+        let plain = ts_packets(4, &[0x5A]);
+        let encrypted = encrypt(&plain);
+        assert_eq!(decrypt_segment(&encrypted, &aes128_key()).unwrap(), plain);
+        assert_eq!(decrypt_streaming(&encrypted, 7), plain);
+    }
+
+    #[test]
+    fn unpadded_ts_ending_like_padding_is_kept() {
+        for tail in [&[0x01][..], &[0x02, 0x02], &[0x04; 4]] {
+            let plain = ts_packets(4, tail);
+            let encrypted = encrypt(&plain);
+            assert_eq!(decrypt_segment(&encrypted, &aes128_key()).unwrap(), plain);
+            assert_eq!(decrypt_streaming(&encrypted, 64), plain);
+        }
+    }
+
+    #[test]
+    fn padded_non_ts_data_is_unpadded() {
+        let plain = b"ftyp not a transport stream".to_vec();
+        let encrypted = encrypt(&pkcs7(&plain));
+        assert_eq!(decrypt_streaming(&encrypted, 16), plain);
+    }
+
+    #[test]
+    fn invalid_padding_keeps_raw_data() {
+        let plain = [0x30; 32];
+        assert_eq!(decrypt_streaming(&encrypt(&plain), 32), plain);
+    }
+}
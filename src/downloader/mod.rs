@@ -1,24 +1,53 @@
-﻿mod encryption;
+﻿mod ad;
+mod clip;
+mod container;
+mod demux;
+mod encryption;
+mod mp4;
+mod period;
+mod probe;
+mod remux;
+mod rendition;
+mod sample_aes;
 mod segment;
-pub use encryption::{decrypt_segment, extract_encryption_key};
-use crate::validation;
-pub use segment::{merge_segments, merge_segments_to_temp_ts, merge_to_mp4_stream};
-use futures::{StreamExt, stream};
-
-
-
+mod ts;
+mod variant;
+mod verify;
+pub use ad::{AdFilter, SkipReason, SkippedRange, skipped_ranges};
+pub use clip::{ClipOptions, TrimWindow};
+pub use container::{ContainerFormat, EncodePreset, InitSections, OutputFormat, OutputOptions};
+pub use encryption::{
+    KeyCache, SegmentDecryptor, SegmentKey, active_keys, decrypt_segment, resolve_segment_keys,
+};
+pub use period::{Period, PeriodFilter, retain_segments, split_periods};
+pub use probe::probe_playlist;
+pub use remux::{Mp4Stream, Remuxer, StreamSender, remux_ts_to_mp4};
+pub use rendition::{
+    RenditionInfo, RenditionKind, RenditionTrack, SelectedRendition, iso639_2, list_renditions,
+    select_renditions, track_format,
+};
+pub use segment::{
+    merge_requires_ffmpeg, merge_segments, merge_segments_to_temp, merge_to_mp4_stream,
+    merge_track_segments, native_merge_error,
+};
+pub use ts::{ContinuityCheck, parse_packet};
+pub use variant::{VariantInfo, VariantPolicy, list_variants, select_variant};
+pub use verify::{VerificationReport, VerifyMode, VerifyOptions, verify_output};
+pub use crate::utils::download_segment::M3u8Downloader;
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use clap::Parser;
+use futures::{StreamExt, stream};
 use log::{error, info};
 use tokio::{fs, time::Instant};
 
 use crate::config::DEFAULT_RETRY_COUNT;
 use crate::error::{DownloadError, Result};
-pub use crate::utils::download_segment::M3u8Downloader;
-
-use crate::utils::{DownloadTask, is_already_downloaded};
+use crate::utils::rate_limit::parse_rate;
+use crate::utils::{DownloadTask, HttpOptions, SegmentCheck, is_already_downloaded};
+use crate::validation;
 
 #[derive(Parser)]
 pub struct Args {
@@ -40,6 +69,51 @@ pub struct Args {
     pub output_dir: String,
     /// 下载任务索引
     pub index: usize,
+    /// 直播录制模式：按目标时长刷新播放列表，直到出现 ENDLIST
+    #[arg(long)]
+    pub live: bool,
+    /// 直播录制的最长时长（秒）
+    #[arg(long)]
+    pub max_duration: Option<u64>,
+    /// 主播放列表的子流选择策略
+    #[arg(long, default_value = "highest")]
+    pub variant: VariantPolicy,
+    /// 优先选择的视频编码（如 avc1、hvc1）
+    #[arg(long)]
+    pub codec: Option<String>,
+    /// 需要下载的音频语言（逗号分隔，`all` 表示全部；默认选择分组默认音轨）
+    #[arg(long = "audio-lang", value_delimiter = ',')]
+    pub audio_languages: Vec<String>,
+    /// 需要下载的字幕语言（逗号分隔，`all` 表示全部；默认不下载字幕）
+    #[arg(long = "sub-lang", value_delimiter = ',')]
+    pub subtitle_languages: Vec<String>,
+    /// 请求头、Cookie、Referer 与 User-Agent
+    #[command(flatten)]
+    pub http: HttpOptions,
+    /// 输出格式、faststart、标题与转码预设
+    #[command(flatten)]
+    pub output: OutputOptions,
+    /// 任务限速（字节/秒，支持 `500K`、`2M`），与全局限速同时生效
+    #[arg(long = "limit-rate", value_parser = parse_rate)]
+    pub rate_limit: Option<u64>,
+    /// 转封装方式：默认使用内置转封装，不支持时改用FFmpeg
+    #[arg(long, value_enum, default_value_t = Remuxer::Auto)]
+    pub remuxer: Remuxer,
+    /// 按不连续时段过滤片段（如去掉插播广告）
+    #[arg(long, default_value = "all")]
+    pub periods: PeriodFilter,
+    /// 广告/填充片段过滤
+    #[command(flatten)]
+    pub ads: AdFilter,
+    /// 只下载指定的时间范围
+    #[command(flatten)]
+    pub clip: ClipOptions,
+    /// 合并后的完整性校验
+    #[command(flatten)]
+    pub verify: VerifyOptions,
+    /// 继续下载时对已存在片段的校验方式
+    #[arg(long, value_enum, default_value_t = SegmentCheck::Fast)]
+    pub segment_check: SegmentCheck,
 }
 
 #[derive(Clone)]
@@ -48,6 +122,14 @@ pub struct DownloadStats {
     pub completed_segments: usize,
     pub downloaded_bytes: u64,
     pub start_time: Instant,
+    /// 下载中片段的完成比例（片段序号 -> 0~1）
+    pub segment_progress: HashMap<usize, f64>,
+    /// 上次上报片段内进度的时间
+    pub last_report: Instant,
+    /// 被过滤掉的片段范围
+    pub skipped: Vec<SkippedRange>,
+    /// 合并后的完整性校验结果
+    pub verification: Option<VerificationReport>,
 }
 
 impl DownloadStats {
@@ -57,6 +139,10 @@ impl DownloadStats {
             completed_segments: 0,
             downloaded_bytes: 0,
             start_time: Instant::now(),
+            segment_progress: HashMap::new(),
+            last_report: Instant::now(),
+            skipped: Vec::new(),
+            verification: None,
         }
     }
 
@@ -73,7 +159,9 @@ impl DownloadStats {
     #[allow(clippy::cast_precision_loss)]
     pub fn get_progress_percentage(&self) -> f64 {
         if self.total_segments > 0 {
-            (self.completed_segments as f64 / self.total_segments as f64) * 100.0
+            let partial: f64 = self.segment_progress.values().sum();
+            ((self.completed_segments as f64 + partial) / self.total_segments as f64 * 100.0)
+                .min(100.0)
         } else {
             0.0
         }
@@ -115,6 +203,21 @@ pub async fn process_download_task(
         retry: DEFAULT_RETRY_COUNT,
         output_dir,
         index,
+        live: false,
+        max_duration: None,
+        variant: task.variant,
+        codec: task.codec.clone(),
+        audio_languages: task.audio_languages.clone(),
+        subtitle_languages: task.subtitle_languages.clone(),
+        http: task.http.clone(),
+        output: task.output.clone(),
+        rate_limit: task.rate_limit,
+        remuxer: Remuxer::default(),
+        periods: task.periods.clone(),
+        ads: task.ads.clone(),
+        clip: task.clip.clone(),
+        verify: task.verify.clone(),
+        segment_check: task.segment_check,
     };
 
     match M3u8Downloader::new(args) {
@@ -173,7 +276,7 @@ pub async fn process_download_tasks(tasks: &[DownloadTask], max_concurrent: usiz
     }
 
     // 创建并发流：同时启动最多 max_concurrent 个任务
-    let mut stream = stream::iter(tasks_to_download.into_iter())
+    let mut stream = stream::iter(tasks_to_download)
         .map(|(i, task)| {
             let name = task.name.clone();
             async move {
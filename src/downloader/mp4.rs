@@ -0,0 +1,969 @@
+//! MP4 封装
+//!
+//! 合并输出使用普通 MP4：样本依次写入 `mdat`，结束时在文件末尾写入 `moov`；
+//! 直传输出使用分片 MP4：先输出初始化段，之后每批样本输出一个 `moof` + `mdat`。
+
+use crate::config::WRITE_BUFFER_SIZE;
+use crate::downloader::demux::{Frame, MPEG_TIMESCALE, TrackCodec};
+use crate::error::{DownloadError, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// `mvhd`/`tkhd`/`elst` 使用的时间刻度（毫秒）
+const MOVIE_TIMESCALE: u32 = 1000;
+/// ISO 639-2 的 `und`，按 `mdhd` 要求压缩为 3 个 5 位字符
+const LANGUAGE_UNDETERMINED: u16 = 0x55C4;
+/// 单位矩阵
+const IDENTITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+/// 分片样本标志：不依赖其他帧的关键帧
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+/// 分片样本标志：依赖其他帧的非关键帧
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0101_0000;
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// 写入一个 box，内容写完后回填长度
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], content: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    put_u32(out, 0);
+    out.extend_from_slice(kind);
+    content(out);
+    let size = u32::try_from(out.len() - start).unwrap_or(u32::MAX);
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// 写入一个带版本号与标志位的 full box
+fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        put_u32(out, (u32::from(version) << 24) | (flags & 0x00FF_FFFF));
+        content(out);
+    });
+}
+
+/// 按时间刻度换算时长
+fn rescale(value: u64, from: u32, to: u32) -> u64 {
+    u64::try_from(u128::from(value) * u128::from(to) / u128::from(from.max(1))).unwrap_or(u64::MAX)
+}
+
+/// `audio_only` 时使用 M4A 品牌
+fn write_ftyp(out: &mut Vec<u8>, audio_only: bool) {
+    write_box(out, b"ftyp", |out| {
+        if audio_only {
+            out.extend_from_slice(b"M4A ");
+            put_u32(out, 0);
+            for brand in [b"M4A ", b"mp42", b"isom"] {
+                out.extend_from_slice(brand);
+            }
+        } else {
+            out.extend_from_slice(b"isom");
+            put_u32(out, 0x200);
+            for brand in [b"isom", b"iso2", b"avc1", b"mp41"] {
+                out.extend_from_slice(brand);
+            }
+        }
+    });
+}
+
+/// iTunes 风格的标题元数据（`udta/meta/ilst/©nam`）
+fn write_udta(out: &mut Vec<u8>, title: &str) {
+    write_box(out, b"udta", |out| {
+        write_full_box(out, b"meta", 0, 0, |out| {
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                put_u32(out, 0);
+                out.extend_from_slice(b"mdir");
+                out.extend_from_slice(b"appl");
+                out.extend_from_slice(&[0; 9]);
+            });
+            write_box(out, b"ilst", |out| {
+                write_box(out, b"\xA9nam", |out| {
+                    write_box(out, b"data", |out| {
+                        // 类型 1 表示 UTF-8 文本
+                        put_u32(out, 1);
+                        put_u32(out, 0);
+                        out.extend_from_slice(title.as_bytes());
+                    });
+                });
+            });
+        });
+    });
+}
+
+fn write_mvhd(out: &mut Vec<u8>, duration: u64, next_track_id: u32) {
+    let long = duration > u64::from(u32::MAX);
+    write_full_box(out, b"mvhd", u8::from(long), 0, |out| {
+        if long {
+            put_u64(out, 0);
+            put_u64(out, 0);
+            put_u32(out, MOVIE_TIMESCALE);
+            put_u64(out, duration);
+        } else {
+            put_u32(out, 0);
+            put_u32(out, 0);
+            put_u32(out, MOVIE_TIMESCALE);
+            put_u32(out, duration as u32);
+        }
+        put_u32(out, 0x0001_0000);
+        put_u16(out, 0x0100);
+        out.extend_from_slice(&[0; 10]);
+        for value in IDENTITY_MATRIX {
+            put_u32(out, value);
+        }
+        out.extend_from_slice(&[0; 24]);
+        put_u32(out, next_track_id);
+    });
+}
+
+fn write_tkhd(out: &mut Vec<u8>, track_id: u32, duration: u64, codec: &TrackCodec) {
+    let long = duration > u64::from(u32::MAX);
+    // 轨道已启用且用于播放
+    write_full_box(out, b"tkhd", u8::from(long), 0x03, |out| {
+        if long {
+            put_u64(out, 0);
+            put_u64(out, 0);
+            put_u32(out, track_id);
+            put_u32(out, 0);
+            put_u64(out, duration);
+        } else {
+            put_u32(out, 0);
+            put_u32(out, 0);
+            put_u32(out, track_id);
+            put_u32(out, 0);
+            put_u32(out, duration as u32);
+        }
+        out.extend_from_slice(&[0; 8]);
+        put_u16(out, 0);
+        put_u16(out, 0);
+        put_u16(out, if codec.is_video() { 0 } else { 0x0100 });
+        put_u16(out, 0);
+        for value in IDENTITY_MATRIX {
+            put_u32(out, value);
+        }
+        let (width, height) = match codec {
+            TrackCodec::Avc { width, height, .. } | TrackCodec::Hevc { width, height, .. } => {
+                (*width, *height)
+            }
+            _ => (0, 0),
+        };
+        put_u32(out, u32::from(width) << 16);
+        put_u32(out, u32::from(height) << 16);
+    });
+}
+
+/// 编辑列表中的一项：`media_time` 为 -1 时表示空白（延迟开始）
+struct Edit {
+    duration: u64,
+    media_time: i64,
+}
+
+fn write_edts(out: &mut Vec<u8>, edits: &[Edit]) {
+    if edits.is_empty() {
+        return;
+    }
+    let long = edits
+        .iter()
+        .any(|e| e.duration > u64::from(u32::MAX) || i32::try_from(e.media_time).is_err());
+    write_box(out, b"edts", |out| {
+        write_full_box(out, b"elst", u8::from(long), 0, |out| {
+            put_u32(out, edits.len() as u32);
+            for edit in edits {
+                if long {
+                    put_u64(out, edit.duration);
+                    put_u64(out, edit.media_time as u64);
+                } else {
+                    put_u32(out, edit.duration as u32);
+                    put_u32(out, edit.media_time as u32);
+                }
+                put_u32(out, 0x0001_0000);
+            }
+        });
+    });
+}
+
+fn write_mdhd(out: &mut Vec<u8>, timescale: u32, duration: u64) {
+    let long = duration > u64::from(u32::MAX);
+    write_full_box(out, b"mdhd", u8::from(long), 0, |out| {
+        if long {
+            put_u64(out, 0);
+            put_u64(out, 0);
+            put_u32(out, timescale);
+            put_u64(out, duration);
+        } else {
+            put_u32(out, 0);
+            put_u32(out, 0);
+            put_u32(out, timescale);
+            put_u32(out, duration as u32);
+        }
+        put_u16(out, LANGUAGE_UNDETERMINED);
+        put_u16(out, 0);
+    });
+}
+
+fn write_hdlr(out: &mut Vec<u8>, codec: &TrackCodec) {
+    let (handler, name): (&[u8; 4], &[u8]) = if codec.is_video() {
+        (b"vide", b"VideoHandler\0")
+    } else {
+        (b"soun", b"SoundHandler\0")
+    };
+    write_full_box(out, b"hdlr", 0, 0, |out| {
+        put_u32(out, 0);
+        out.extend_from_slice(handler);
+        out.extend_from_slice(&[0; 12]);
+        out.extend_from_slice(name);
+    });
+}
+
+fn write_media_header(out: &mut Vec<u8>, codec: &TrackCodec) {
+    if codec.is_video() {
+        write_full_box(out, b"vmhd", 0, 0x01, |out| out.extend_from_slice(&[0; 8]));
+    } else {
+        write_full_box(out, b"smhd", 0, 0, |out| put_u32(out, 0));
+    }
+    write_box(out, b"dinf", |out| {
+        write_full_box(out, b"dref", 0, 0, |out| {
+            put_u32(out, 1);
+            // 媒体数据位于同一文件中
+            write_full_box(out, b"url ", 0, 0x01, |_| {});
+        });
+    });
+}
+
+fn write_stsd(out: &mut Vec<u8>, codec: &TrackCodec) {
+    write_full_box(out, b"stsd", 0, 0, |out| {
+        put_u32(out, 1);
+        match codec {
+            TrackCodec::Avc {
+                width,
+                height,
+                config,
+            } => write_visual_entry(out, b"avc1", *width, *height, b"avcC", config),
+            TrackCodec::Hevc {
+                width,
+                height,
+                config,
+            } => write_visual_entry(out, b"hvc1", *width, *height, b"hvcC", config),
+            TrackCodec::Aac {
+                sample_rate,
+                channels,
+                config,
+            } => write_audio_entry(out, *sample_rate, *channels, codec.object_type(), config),
+            TrackCodec::Mp3 {
+                sample_rate,
+                channels,
+                ..
+            } => write_audio_entry(out, *sample_rate, *channels, codec.object_type(), &[]),
+        }
+    });
+}
+
+fn write_visual_entry(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    width: u16,
+    height: u16,
+    config_kind: &[u8; 4],
+    config: &[u8],
+) {
+    write_box(out, kind, |out| {
+        out.extend_from_slice(&[0; 6]);
+        put_u16(out, 1);
+        out.extend_from_slice(&[0; 16]);
+        put_u16(out, width);
+        put_u16(out, height);
+        // 72 dpi
+        put_u32(out, 0x0048_0000);
+        put_u32(out, 0x0048_0000);
+        put_u32(out, 0);
+        put_u16(out, 1);
+        out.extend_from_slice(&[0; 32]);
+        put_u16(out, 0x0018);
+        put_u16(out, 0xFFFF);
+        write_box(out, config_kind, |out| out.extend_from_slice(config));
+    });
+}
+
+fn write_audio_entry(
+    out: &mut Vec<u8>,
+    sample_rate: u32,
+    channels: u16,
+    object_type: u8,
+    config: &[u8],
+) {
+    write_box(out, b"mp4a", |out| {
+        out.extend_from_slice(&[0; 6]);
+        put_u16(out, 1);
+        out.extend_from_slice(&[0; 8]);
+        put_u16(out, channels);
+        put_u16(out, 16);
+        put_u32(out, 0);
+        put_u32(out, sample_rate.min(0xFFFF) << 16);
+        write_full_box(out, b"esds", 0, 0, |out| {
+            write_es_descriptor(out, object_type, config);
+        });
+    });
+}
+
+/// 写入 MPEG-4 描述符，长度固定使用 4 字节编码
+fn write_descriptor(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    let len = content.len() as u32;
+    out.push(tag);
+    out.extend_from_slice(&[
+        0x80 | ((len >> 21) & 0x7F) as u8,
+        0x80 | ((len >> 14) & 0x7F) as u8,
+        0x80 | ((len >> 7) & 0x7F) as u8,
+        (len & 0x7F) as u8,
+    ]);
+    out.extend_from_slice(content);
+}
+
+fn write_es_descriptor(out: &mut Vec<u8>, object_type: u8, config: &[u8]) {
+    // 音频流（streamType 5），bufferSizeDB 与码率留空
+    let mut decoder_config = vec![object_type, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    if !config.is_empty() {
+        write_descriptor(&mut decoder_config, 0x05, config);
+    }
+    let mut es = vec![0, 0, 0];
+    write_descriptor(&mut es, 0x04, &decoder_config);
+    write_descriptor(&mut es, 0x06, &[0x02]);
+    write_descriptor(out, 0x03, &es);
+}
+
+/// 无法从相邻样本推算时使用的样本时长：视频按 25fps，音频按每帧采样数
+fn default_duration(codec: &TrackCodec) -> u32 {
+    match codec {
+        TrackCodec::Avc { .. } | TrackCodec::Hevc { .. } => MPEG_TIMESCALE / 25,
+        TrackCodec::Aac { .. } => 1024,
+        TrackCodec::Mp3 { sample_rate, .. } if *sample_rate < 32000 => 576,
+        TrackCodec::Mp3 { .. } => 1152,
+    }
+}
+
+/// 样本表中的一个样本
+struct SampleEntry {
+    dts: i64,
+    /// 显示时间与解码时间之差
+    composition: i64,
+    size: u32,
+    sync: bool,
+}
+
+/// 普通 MP4 中一条轨道的样本表
+struct SampleTable {
+    codec: TrackCodec,
+    samples: Vec<SampleEntry>,
+    /// 每个块的 (文件偏移, 样本数)
+    chunks: Vec<(u64, u32)>,
+}
+
+impl SampleTable {
+    /// 每个样本的时长，最后一个样本沿用前一个样本的时长
+    fn durations(&self) -> Vec<u32> {
+        let mut durations: Vec<u32> = self
+            .samples
+            .windows(2)
+            .map(|pair| u32::try_from(pair[1].dts - pair[0].dts).unwrap_or(0))
+            .collect();
+        let last = durations
+            .last()
+            .copied()
+            .unwrap_or_else(|| default_duration(&self.codec));
+        durations.push(last);
+        durations
+    }
+
+    fn write_stbl(&self, out: &mut Vec<u8>, durations: &[u32]) {
+        write_box(out, b"stbl", |out| {
+            write_stsd(out, &self.codec);
+
+            let stts = run_lengths(durations.iter().copied());
+            write_full_box(out, b"stts", 0, 0, |out| {
+                put_u32(out, stts.len() as u32);
+                for (count, duration) in stts {
+                    put_u32(out, count);
+                    put_u32(out, duration);
+                }
+            });
+
+            if self.samples.iter().any(|s| s.composition != 0) {
+                let negative = self.samples.iter().any(|s| s.composition < 0);
+                let ctts = run_lengths(self.samples.iter().map(|s| s.composition as i32));
+                write_full_box(out, b"ctts", u8::from(negative), 0, |out| {
+                    put_u32(out, ctts.len() as u32);
+                    for (count, offset) in ctts {
+                        put_u32(out, count);
+                        put_u32(out, offset as u32);
+                    }
+                });
+            }
+
+            if self.samples.iter().any(|s| !s.sync) {
+                let sync: Vec<u32> = (1..)
+                    .zip(&self.samples)
+                    .filter(|(_, s)| s.sync)
+                    .map(|(i, _)| i)
+                    .collect();
+                write_full_box(out, b"stss", 0, 0, |out| {
+                    put_u32(out, sync.len() as u32);
+                    for index in sync {
+                        put_u32(out, index);
+                    }
+                });
+            }
+
+            let stsc = run_lengths(self.chunks.iter().map(|&(_, count)| count));
+            write_full_box(out, b"stsc", 0, 0, |out| {
+                put_u32(out, stsc.len() as u32);
+                let mut first_chunk = 1;
+                for (chunks, samples_per_chunk) in stsc {
+                    put_u32(out, first_chunk);
+                    put_u32(out, samples_per_chunk);
+                    put_u32(out, 1);
+                    first_chunk += chunks;
+                }
+            });
+
+            write_full_box(out, b"stsz", 0, 0, |out| {
+                put_u32(out, 0);
+                put_u32(out, self.samples.len() as u32);
+                for sample in &self.samples {
+                    put_u32(out, sample.size);
+                }
+            });
+
+            let long = self
+                .chunks
+                .last()
+                .is_some_and(|&(offset, _)| offset > u64::from(u32::MAX));
+            let kind = if long { b"co64" } else { b"stco" };
+            write_full_box(out, kind, 0, 0, |out| {
+                put_u32(out, self.chunks.len() as u32);
+                for &(offset, _) in &self.chunks {
+                    if long {
+                        put_u64(out, offset);
+                    } else {
+                        put_u32(out, offset as u32);
+                    }
+                }
+            });
+        });
+    }
+}
+
+/// 相邻相同值合并为 (个数, 值)
+fn run_lengths<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+/// 普通 MP4 输出
+///
+/// 样本按到达顺序写入 `mdat`，同一轨道连续的样本合为一个块；
+/// `mdat` 使用 64 位长度，结束时回填长度并在文件末尾写入 `moov`。
+pub struct Mp4Writer<W: Write + Seek> {
+    out: W,
+    /// 按解复用轨道编号索引，没有样本的轨道为 `None`
+    tracks: Vec<Option<SampleTable>>,
+    position: u64,
+    mdat_start: u64,
+    last_track: Option<usize>,
+}
+
+fn write_error(e: &std::io::Error) -> DownloadError {
+    DownloadError::remux(format!("写入MP4失败: {e}"))
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+    /// `audio_only` 只影响文件品牌，调用方负责只写入音频样本
+    pub fn new(mut out: W, audio_only: bool) -> Result<Self> {
+        let mut header = Vec::new();
+        write_ftyp(&mut header, audio_only);
+        let mdat_start = header.len() as u64;
+        put_u32(&mut header, 1);
+        header.extend_from_slice(b"mdat");
+        put_u64(&mut header, 0);
+        out.write_all(&header).map_err(|e| write_error(&e))?;
+
+        Ok(Self {
+            out,
+            tracks: Vec::new(),
+            position: header.len() as u64,
+            mdat_start,
+            last_track: None,
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame, codec: &TrackCodec) -> Result<()> {
+        let size = u32::try_from(frame.data.len()).map_err(|_| DownloadError::remux("样本过大"))?;
+        self.out
+            .write_all(&frame.data)
+            .map_err(|e| write_error(&e))?;
+
+        if self.tracks.len() <= frame.track {
+            self.tracks.resize_with(frame.track + 1, || None);
+        }
+        let table = self.tracks[frame.track].get_or_insert_with(|| SampleTable {
+            codec: codec.clone(),
+            samples: Vec::new(),
+            chunks: Vec::new(),
+        });
+        table.samples.push(SampleEntry {
+            dts: frame.dts,
+            composition: frame.pts - frame.dts,
+            size,
+            sync: frame.keyframe,
+        });
+        match table.chunks.last_mut() {
+            Some((_, count)) if self.last_track == Some(frame.track) => *count += 1,
+            _ => table.chunks.push((self.position, 1)),
+        }
+
+        self.position += u64::from(size);
+        self.last_track = Some(frame.track);
+        Ok(())
+    }
+
+    /// 回填 `mdat` 长度并写入 `moov`，`title` 写入标题元数据
+    pub fn finish(mut self, title: Option<&str>) -> Result<W> {
+        let tracks: Vec<&SampleTable> = self.tracks.iter().flatten().collect();
+        if tracks.is_empty() {
+            return Err(DownloadError::remux("没有找到可转封装的音视频数据"));
+        }
+        let moov = build_moov(&tracks, title);
+
+        let mdat_size = self.position - self.mdat_start;
+        self.out
+            .seek(SeekFrom::Start(self.mdat_start + 8))
+            .and_then(|_| self.out.write_all(&mdat_size.to_be_bytes()))
+            .and_then(|()| self.out.seek(SeekFrom::End(0)))
+            .and_then(|_| self.out.write_all(&moov))
+            .and_then(|()| self.out.flush())
+            .map_err(|e| write_error(&e))?;
+        Ok(self.out)
+    }
+}
+
+/// 生成普通 MP4 的 `moov`
+///
+/// 各轨道按首个样本的显示时间对齐：开始较晚的轨道前插入空白编辑，
+/// 视频的首帧显示偏移（B 帧）通过编辑列表的 `media_time` 去掉。
+fn build_moov(tracks: &[&SampleTable], title: Option<&str>) -> Vec<u8> {
+    struct Layout {
+        durations: Vec<u32>,
+        media_duration: u64,
+        edits: Vec<Edit>,
+        movie_duration: u64,
+    }
+
+    // 以 90kHz 比较各轨道的开始时间
+    let starts: Vec<i64> = tracks
+        .iter()
+        .map(|t| {
+            let start = t
+                .samples
+                .iter()
+                .map(|s| s.dts + s.composition)
+                .min()
+                .unwrap_or(0);
+            start * i64::from(MPEG_TIMESCALE) / i64::from(t.codec.timescale())
+        })
+        .collect();
+    let origin = starts.iter().copied().min().unwrap_or(0);
+
+    let layouts: Vec<Layout> = tracks
+        .iter()
+        .zip(&starts)
+        .map(|(track, &start)| {
+            let timescale = track.codec.timescale();
+            let durations = track.durations();
+            let media_duration: u64 = durations.iter().map(|&d| u64::from(d)).sum();
+            let first_dts = track.samples.first().map_or(0, |s| s.dts);
+            let first_pts = track
+                .samples
+                .iter()
+                .map(|s| s.dts + s.composition)
+                .min()
+                .unwrap_or(first_dts);
+            let media_time = (first_pts - first_dts).max(0);
+
+            let mut edits = Vec::new();
+            let delay = rescale((start - origin) as u64, MPEG_TIMESCALE, MOVIE_TIMESCALE);
+            if delay > 0 {
+                edits.push(Edit {
+                    duration: delay,
+                    media_time: -1,
+                });
+            }
+            let presented = rescale(
+                media_duration.saturating_sub(media_time as u64),
+                timescale,
+                MOVIE_TIMESCALE,
+            );
+            edits.push(Edit {
+                duration: presented,
+                media_time,
+            });
+
+            Layout {
+                durations,
+                media_duration,
+                edits,
+                movie_duration: delay + presented,
+            }
+        })
+        .collect();
+
+    let movie_duration = layouts.iter().map(|l| l.movie_duration).max().unwrap_or(0);
+    let mut out = Vec::new();
+    write_box(&mut out, b"moov", |out| {
+        write_mvhd(out, movie_duration, tracks.len() as u32 + 1);
+        for ((track, layout), track_id) in tracks.iter().zip(&layouts).zip(1u32..) {
+            write_box(out, b"trak", |out| {
+                write_tkhd(out, track_id, layout.movie_duration, &track.codec);
+                write_edts(out, &layout.edits);
+                write_box(out, b"mdia", |out| {
+                    write_mdhd(out, track.codec.timescale(), layout.media_duration);
+                    write_hdlr(out, &track.codec);
+                    write_box(out, b"minf", |out| {
+                        write_media_header(out, &track.codec);
+                        track.write_stbl(out, &layout.durations);
+                    });
+                });
+            });
+        }
+        if let Some(title) = title {
+            write_udta(out, title);
+        }
+    });
+    out
+}
+
+/// 读取顶层 box 头，返回 (类型, 总长度)，文件结束时返回 `None`
+pub fn read_box_header(
+    reader: &mut impl Read,
+    remaining: u64,
+) -> std::io::Result<Option<([u8; 4], u64)>> {
+    if remaining < 8 {
+        return Ok(None);
+    }
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let kind = [header[4], header[5], header[6], header[7]];
+    let (header_len, size) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]])
+    {
+        0 => (8, remaining),
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            (16, u64::from_be_bytes(large))
+        }
+        size => (8, u64::from(size)),
+    };
+    if size < header_len || size > remaining {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("box 长度无效: {}", String::from_utf8_lossy(&kind)),
+        ));
+    }
+    Ok(Some((kind, size)))
+}
+
+/// 把 `moov` 中所有块偏移加上 `shift`
+fn shift_chunk_offsets(boxes: &mut [u8], shift: u64) -> Result<()> {
+    let mut pos = 0;
+    while pos + 8 <= boxes.len() {
+        let size = u32::from_be_bytes(boxes[pos..pos + 4].try_into().unwrap_or_default()) as usize;
+        if size < 8 || pos + size > boxes.len() {
+            return Err(DownloadError::remux("moov 结构无效"));
+        }
+        let kind: [u8; 4] = boxes[pos + 4..pos + 8].try_into().unwrap_or_default();
+        let content = &mut boxes[pos + 8..pos + size];
+        match &kind {
+            b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(content, shift)?,
+            b"stco" | b"co64" => {
+                let width = if &kind == b"stco" { 4 } else { 8 };
+                for entry in content
+                    .get_mut(8..)
+                    .unwrap_or_default()
+                    .chunks_exact_mut(width)
+                {
+                    if width == 4 {
+                        let offset =
+                            u64::from(u32::from_be_bytes(entry.try_into().unwrap_or_default()))
+                                + shift;
+                        let offset = u32::try_from(offset)
+                            .map_err(|_| DownloadError::remux("块偏移超出 32 位范围"))?;
+                        entry.copy_from_slice(&offset.to_be_bytes());
+                    } else {
+                        let offset =
+                            u64::from_be_bytes(entry.try_into().unwrap_or_default()) + shift;
+                        entry.copy_from_slice(&offset.to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+    Ok(())
+}
+
+/// 把普通 MP4 的 `moov` 移到 `mdat` 之前（faststart），块偏移随之后移
+pub fn move_moov_to_front(input: &Path, output: &Path) -> Result<()> {
+    let read_error = |e: std::io::Error| DownloadError::file(input, e.to_string());
+    let write_error = |e: std::io::Error| DownloadError::file(output, e.to_string());
+
+    let mut reader =
+        BufReader::with_capacity(WRITE_BUFFER_SIZE, File::open(input).map_err(read_error)?);
+    let file_len = reader.get_ref().metadata().map_err(read_error)?.len();
+
+    // 记录顶层 box 的位置，并读出 ftyp 与 moov
+    let mut boxes = Vec::new();
+    let (mut ftyp, mut moov) = (None, None);
+    let mut position = 0;
+    while let Some((kind, size)) =
+        read_box_header(&mut reader, file_len - position).map_err(read_error)?
+    {
+        if &kind == b"ftyp" || &kind == b"moov" {
+            let mut data =
+                vec![0u8; usize::try_from(size).map_err(|_| DownloadError::remux("box 过大"))?];
+            reader.seek(SeekFrom::Start(position)).map_err(read_error)?;
+            reader.read_exact(&mut data).map_err(read_error)?;
+            if &kind == b"ftyp" {
+                ftyp = Some(data);
+            } else {
+                moov = Some(data);
+            }
+        } else {
+            boxes.push((position, size));
+            reader
+                .seek(SeekFrom::Start(position + size))
+                .map_err(read_error)?;
+        }
+        position += size;
+    }
+    let mut moov = moov.ok_or_else(|| DownloadError::remux("没有找到 moov"))?;
+    let ftyp = ftyp.unwrap_or_default();
+
+    // 其余 box 整体后移 moov 的长度
+    let moved_from = boxes.first().map_or(0, |&(start, _)| start);
+    let shift = (ftyp.len() + moov.len()) as u64 - moved_from;
+    shift_chunk_offsets(&mut moov, shift)?;
+
+    let mut writer = BufWriter::with_capacity(
+        WRITE_BUFFER_SIZE,
+        File::create(output).map_err(write_error)?,
+    );
+    writer.write_all(&ftyp).map_err(write_error)?;
+    writer.write_all(&moov).map_err(write_error)?;
+    for (start, size) in boxes {
+        reader.seek(SeekFrom::Start(start)).map_err(read_error)?;
+        let copied =
+            std::io::copy(&mut (&mut reader).take(size), &mut writer).map_err(write_error)?;
+        if copied != size {
+            return Err(DownloadError::file(input, "文件被截断"));
+        }
+    }
+    writer.flush().map_err(write_error)
+}
+
+/// 分片 MP4 中的一条轨道
+struct FragmentTrack {
+    codec: TrackCodec,
+    /// 输出的 0 时刻对应的解码时间
+    origin: i64,
+    /// 尚未输出的样本
+    queued: Vec<Frame>,
+    /// 最近输出的样本时长，用作最后一个样本的时长
+    last_duration: u32,
+}
+
+/// 直传用的分片 MP4 输出
+pub struct FragmentWriter {
+    tracks: Vec<FragmentTrack>,
+    sequence: u32,
+}
+
+impl FragmentWriter {
+    /// `tracks` 为各输出轨道的编码参数及首个样本的解码时间，最早开始的轨道对应 0 时刻
+    pub fn new(tracks: Vec<(TrackCodec, i64)>) -> Self {
+        let origin = tracks
+            .iter()
+            .map(|(codec, dts)| dts * i64::from(MPEG_TIMESCALE) / i64::from(codec.timescale()))
+            .min()
+            .unwrap_or(0);
+        let tracks = tracks
+            .into_iter()
+            .map(|(codec, _)| {
+                let timescale = i64::from(codec.timescale());
+                FragmentTrack {
+                    origin: origin * timescale / i64::from(MPEG_TIMESCALE),
+                    last_duration: default_duration(&codec),
+                    codec,
+                    queued: Vec::new(),
+                }
+            })
+            .collect();
+        Self {
+            tracks,
+            sequence: 0,
+        }
+    }
+
+    /// 初始化段：`ftyp` + 不含样本的 `moov`
+    pub fn init_segment(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_ftyp(&mut out, false);
+        write_box(&mut out, b"moov", |out| {
+            write_mvhd(out, 0, self.tracks.len() as u32 + 1);
+            for (track, track_id) in self.tracks.iter().zip(1u32..) {
+                write_box(out, b"trak", |out| {
+                    write_tkhd(out, track_id, 0, &track.codec);
+                    write_box(out, b"mdia", |out| {
+                        write_mdhd(out, track.codec.timescale(), 0);
+                        write_hdlr(out, &track.codec);
+                        write_box(out, b"minf", |out| {
+                            write_media_header(out, &track.codec);
+                            write_box(out, b"stbl", |out| {
+                                write_stsd(out, &track.codec);
+                                for kind in [b"stts", b"stsc", b"stsz", b"stco"] {
+                                    write_full_box(out, kind, 0, 0, |out| {
+                                        if kind == b"stsz" {
+                                            put_u32(out, 0);
+                                        }
+                                        put_u32(out, 0);
+                                    });
+                                }
+                            });
+                        });
+                    });
+                });
+            }
+            write_box(out, b"mvex", |out| {
+                for track_id in 1..=self.tracks.len() as u32 {
+                    write_full_box(out, b"trex", 0, 0, |out| {
+                        put_u32(out, track_id);
+                        put_u32(out, 1);
+                        put_u32(out, 0);
+                        put_u32(out, 0);
+                        put_u32(out, 0);
+                    });
+                }
+            });
+        });
+        out
+    }
+
+    pub fn push(&mut self, track: usize, frame: Frame) {
+        if let Some(track) = self.tracks.get_mut(track) {
+            track.queued.push(frame);
+        }
+    }
+
+    /// 输出已排队的样本
+    ///
+    /// `last` 为假时每条轨道保留最后一个样本，等下一个样本到来后才能确定其时长。
+    /// 没有可输出的样本时返回空数据。
+    pub fn flush(&mut self, last: bool) -> Vec<u8> {
+        let mut batches = Vec::new();
+        for (track, track_id) in self.tracks.iter_mut().zip(1u32..) {
+            let keep = usize::from(!last);
+            if track.queued.len() <= keep {
+                continue;
+            }
+            let count = track.queued.len() - keep;
+            let mut durations = Vec::with_capacity(count);
+            for i in 0..count {
+                let duration = track.queued.get(i + 1).map_or(track.last_duration, |next| {
+                    u32::try_from(next.dts - track.queued[i].dts).unwrap_or(0)
+                });
+                durations.push(duration);
+            }
+            track.last_duration = durations.last().copied().unwrap_or(track.last_duration);
+            let frames: Vec<Frame> = track.queued.drain(..count).collect();
+            let base = u64::try_from(frames[0].dts - track.origin).unwrap_or(0);
+            batches.push((track_id, base, frames, durations));
+        }
+        if batches.is_empty() {
+            return Vec::new();
+        }
+
+        self.sequence += 1;
+        let sequence = self.sequence;
+        let mut out = Vec::new();
+        // 每条轨道 trun 中 data_offset 字段的位置，以及该轨道数据在 mdat 中的偏移
+        let mut offset_fields = Vec::new();
+        write_box(&mut out, b"moof", |out| {
+            write_full_box(out, b"mfhd", 0, 0, |out| put_u32(out, sequence));
+            let mut data_offset = 0usize;
+            for (track_id, base, frames, durations) in &batches {
+                write_box(out, b"traf", |out| {
+                    // default-base-is-moof
+                    write_full_box(out, b"tfhd", 0, 0x02_0000, |out| put_u32(out, *track_id));
+                    write_full_box(out, b"tfdt", 1, 0, |out| put_u64(out, *base));
+                    // 数据偏移及每个样本的时长、大小、标志与显示偏移
+                    write_full_box(out, b"trun", 1, 0x00_0F01, |out| {
+                        put_u32(out, frames.len() as u32);
+                        offset_fields.push((out.len(), data_offset));
+                        put_u32(out, 0);
+                        for (frame, duration) in frames.iter().zip(durations) {
+                            put_u32(out, *duration);
+                            put_u32(out, frame.data.len() as u32);
+                            put_u32(
+                                out,
+                                if frame.keyframe {
+                                    SAMPLE_FLAGS_SYNC
+                                } else {
+                                    SAMPLE_FLAGS_NON_SYNC
+                                },
+                            );
+                            put_u32(out, (frame.pts - frame.dts) as i32 as u32);
+                        }
+                    });
+                });
+                data_offset += frames.iter().map(|f| f.data.len()).sum::<usize>();
+            }
+        });
+
+        let moof_len = out.len();
+        for (position, data_offset) in offset_fields {
+            let offset = (moof_len + 8 + data_offset) as u32;
+            out[position..position + 4].copy_from_slice(&offset.to_be_bytes());
+        }
+
+        let data_len: usize = batches
+            .iter()
+            .flat_map(|(_, _, frames, _)| frames)
+            .map(|f| f.data.len())
+            .sum();
+        put_u32(&mut out, (8 + data_len) as u32);
+        out.extend_from_slice(b"mdat");
+        for (_, _, frames, _) in &batches {
+            for frame in frames {
+                out.extend_from_slice(&frame.data);
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,339 @@
+//! `EXT-X-DISCONTINUITY` 时段：按不连续点拆分片段列表，并按条件去掉整个时段（如插播广告）
+
+use crate::error::{DownloadError, Result};
+use m3u8_rs::MediaSegment;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// 两个不连续点之间的一段片段
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Period {
+    pub index: usize,
+    /// 第一个片段的序号
+    pub first_segment: usize,
+    pub segment_count: usize,
+    /// 片段 EXTINF 之和（秒）
+    pub duration: f64,
+}
+
+impl Period {
+    pub const fn segments(&self) -> Range<usize> {
+        self.first_segment..self.first_segment + self.segment_count
+    }
+}
+
+/// 按 `EXT-X-DISCONTINUITY` 拆分时段，没有片段时返回空列表
+pub fn split_periods(segments: &[MediaSegment]) -> Vec<Period> {
+    let mut periods: Vec<Period> = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        match periods.last_mut() {
+            Some(period) if !segment.discontinuity => {
+                period.segment_count += 1;
+                period.duration += f64::from(segment.duration);
+            }
+            _ => periods.push(Period {
+                index: periods.len(),
+                first_segment: i,
+                segment_count: 1,
+                duration: f64::from(segment.duration),
+            }),
+        }
+    }
+    periods
+}
+
+/// 时段过滤条件
+///
+/// 字符串形式用于命令行与 JSON：`all`、`index=0,2`、`skip=1,3`、
+/// `min-duration=60`、`longest`。时段序号从0开始。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PeriodFilter {
+    /// 保留全部时段
+    #[default]
+    All,
+    /// 只保留指定的时段
+    Only(Vec<usize>),
+    /// 去掉指定的时段
+    Skip(Vec<usize>),
+    /// 去掉时长短于指定秒数的时段（通常为广告）
+    MinDuration(f64),
+    /// 只保留时长最长的时段
+    Longest,
+}
+
+impl PeriodFilter {
+    fn keeps(&self, period: &Period, periods: &[Period]) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(indexes) => indexes.contains(&period.index),
+            Self::Skip(indexes) => !indexes.contains(&period.index),
+            Self::MinDuration(seconds) => period.duration >= *seconds,
+            Self::Longest => {
+                periods
+                    .iter()
+                    .max_by(|a, b| a.duration.total_cmp(&b.duration))
+                    .map(|p| p.index)
+                    == Some(period.index)
+            }
+        }
+    }
+
+    /// 需要保留的时段
+    pub fn select<'a>(&self, periods: &'a [Period]) -> Vec<&'a Period> {
+        periods.iter().filter(|p| self.keeps(p, periods)).collect()
+    }
+
+    /// 逐个片段判断所在的时段是否保留
+    pub fn kept_segments(&self, segments: &[MediaSegment]) -> Vec<bool> {
+        let mut keep = vec![false; segments.len()];
+        for period in self.select(&split_periods(segments)) {
+            keep[period.segments()].fill(true);
+        }
+        keep
+    }
+}
+
+impl FromStr for PeriodFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let value = s.trim().to_ascii_lowercase();
+        let parse_indexes = |v: &str| {
+            v.split(',')
+                .map(|i| i.trim().parse::<usize>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| format!("时段过滤参数无效: {s} - {e}"))
+        };
+
+        match value.split_once('=') {
+            None if value == "all" || value.is_empty() => Ok(Self::All),
+            None if value == "longest" => Ok(Self::Longest),
+            Some(("index", v)) => parse_indexes(v).map(Self::Only),
+            Some(("skip", v)) => parse_indexes(v).map(Self::Skip),
+            Some(("min-duration", v)) => v
+                .trim()
+                .parse::<f64>()
+                .map(Self::MinDuration)
+                .map_err(|e| format!("时段过滤参数无效: {s} - {e}")),
+            _ => Err(format!(
+                "未知的时段过滤条件: {s}（可选 all、index=N,M、skip=N,M、min-duration=秒、longest）"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for PeriodFilter {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PeriodFilter> for String {
+    fn from(filter: PeriodFilter) -> Self {
+        filter.to_string()
+    }
+}
+
+impl fmt::Display for PeriodFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |indexes: &[usize]| {
+            indexes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        match self {
+            Self::All => write!(f, "all"),
+            Self::Only(indexes) => write!(f, "index={}", join(indexes)),
+            Self::Skip(indexes) => write!(f, "skip={}", join(indexes)),
+            Self::MinDuration(seconds) => write!(f, "min-duration={seconds}"),
+            Self::Longest => write!(f, "longest"),
+        }
+    }
+}
+
+/// 只保留 `keep` 中为 `true` 的片段，`keys` 与片段一一对应（需在解析密钥之后调用）
+///
+/// 被去掉的片段上的 `EXT-X-MAP` 会移到之后第一个保留的片段上，保证初始化段仍然生效；
+/// 去掉片段处的时间戳不再连续，之后第一个保留的片段标记为不连续点。
+/// 全部片段都被去掉时返回错误。
+pub fn retain_segments<T>(
+    segments: Vec<MediaSegment>,
+    keys: Vec<T>,
+    keep: &[bool],
+) -> Result<(Vec<MediaSegment>, Vec<T>)> {
+    if !keep.contains(&true) {
+        return Err(DownloadError::parse(format!(
+            "过滤条件去掉了全部 {} 个片段",
+            segments.len()
+        )));
+    }
+
+    let mut current_map = None;
+    let mut kept_map = None;
+    let mut dropped = false;
+    let mut filtered = (Vec::new(), Vec::new());
+    for ((mut segment, key), &kept) in segments.into_iter().zip(keys).zip(keep) {
+        if segment.map.is_some() {
+            current_map.clone_from(&segment.map);
+        }
+        if !kept {
+            dropped = true;
+            continue;
+        }
+        if std::mem::take(&mut dropped) && !filtered.0.is_empty() {
+            segment.discontinuity = true;
+        }
+        if segment.map.is_none() && current_map != kept_map {
+            segment.map.clone_from(&current_map);
+        }
+        kept_map.clone_from(&current_map);
+        filtered.0.push(segment);
+        filtered.1.push(key);
+    }
+    Ok(filtered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use m3u8_rs::Map;
+
+    fn segment(uri: &str, duration: f32, discontinuity: bool) -> MediaSegment {
+        MediaSegment {
+            uri: uri.to_string(),
+            duration,
+            discontinuity,
+            ..Default::default()
+        }
+    }
+
+    fn map(uri: &str) -> Option<Map> {
+        Some(Map {
+            uri: uri.to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// 主内容 2 段、广告 1 段、主内容 2 段
+    fn playlist() -> Vec<MediaSegment> {
+        vec![
+            MediaSegment {
+                map: map("main.mp4"),
+                ..segment("m0", 10.0, false)
+            },
+            segment("m1", 10.0, false),
+            MediaSegment {
+                map: map("ad.mp4"),
+                ..segment("a0", 5.0, true)
+            },
+            MediaSegment {
+                map: map("main.mp4"),
+                ..segment("m2", 10.0, true)
+            },
+            segment("m3", 6.0, false),
+        ]
+    }
+
+    fn uris(segments: &[MediaSegment]) -> Vec<&str> {
+        segments.iter().map(|s| s.uri.as_str()).collect()
+    }
+
+    #[test]
+    fn splits_on_discontinuity() {
+        let periods = split_periods(&playlist());
+        assert_eq!(periods.len(), 3);
+        assert_eq!(periods[0].segments(), 0..2);
+        assert_eq!(periods[1].segments(), 2..3);
+        assert_eq!(periods[2].segments(), 3..5);
+        assert_eq!(periods[2].duration, 16.0);
+        assert!(split_periods(&[]).is_empty());
+    }
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!("all".parse(), Ok(PeriodFilter::All));
+        assert_eq!("".parse(), Ok(PeriodFilter::All));
+        assert_eq!("longest".parse(), Ok(PeriodFilter::Longest));
+        assert_eq!("index=0, 2".parse(), Ok(PeriodFilter::Only(vec![0, 2])));
+        assert_eq!("SKIP=1".parse(), Ok(PeriodFilter::Skip(vec![1])));
+        assert_eq!(
+            "min-duration=7.5".parse(),
+            Ok(PeriodFilter::MinDuration(7.5))
+        );
+        assert!("index=a".parse::<PeriodFilter>().is_err());
+        assert!("first".parse::<PeriodFilter>().is_err());
+        for filter in ["skip=1,3", "min-duration=60", "longest"] {
+            assert_eq!(filter.parse::<PeriodFilter>().unwrap().to_string(), filter);
+        }
+    }
+
+    #[test]
+    fn filters_select_periods() {
+        let segments = playlist();
+        let keep = |filter: PeriodFilter| filter.kept_segments(&segments);
+        assert_eq!(
+            keep(PeriodFilter::Skip(vec![1])),
+            [true, true, false, true, true]
+        );
+        assert_eq!(
+            keep(PeriodFilter::MinDuration(10.0)),
+            [true, true, false, true, true]
+        );
+        assert_eq!(
+            keep(PeriodFilter::Only(vec![1])),
+            [false, false, true, false, false]
+        );
+        assert_eq!(
+            keep(PeriodFilter::Longest),
+            [true, true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn dropped_period_inserts_discontinuity() {
+        let mut segments = playlist();
+        segments[3].discontinuity = false;
+        let keep = [true, true, false, true, true];
+        let (kept, keys) = retain_segments(segments, (0..5).collect(), &keep).unwrap();
+        assert_eq!(uris(&kept), ["m0", "m1", "m2", "m3"]);
+        assert_eq!(keys, [0, 1, 3, 4]);
+        let flags: Vec<bool> = kept.iter().map(|s| s.discontinuity).collect();
+        assert_eq!(flags, [false, false, true, false]);
+    }
+
+    #[test]
+    fn leading_dropped_segments_do_not_mark_discontinuity() {
+        let mut segments = playlist();
+        segments[3].discontinuity = false;
+        let keep = [false, false, false, true, true];
+        let (kept, _) = retain_segments(segments, vec![(); 5], &keep).unwrap();
+        assert_eq!(uris(&kept), ["m2", "m3"]);
+        assert!(!kept[0].discontinuity);
+    }
+
+    #[test]
+    fn map_is_carried_to_first_kept_segment() {
+        // 带 EXT-X-MAP 的首个片段被去掉，初始化段移到之后第一个保留的片段上
+        let mut segments = playlist();
+        segments[3].map = None;
+        let keep = [false, true, false, true, true];
+        let (kept, _) = retain_segments(segments, vec![(); 5], &keep).unwrap();
+        assert_eq!(uris(&kept), ["m1", "m2", "m3"]);
+        assert_eq!(kept[0].map, map("main.mp4"));
+        // 被去掉的片段上的 EXT-X-MAP 对之后的片段仍然生效
+        assert_eq!(kept[1].map, map("ad.mp4"));
+        assert_eq!(kept[2].map, None);
+    }
+
+    #[test]
+    fn dropping_everything_is_an_error() {
+        assert!(retain_segments(playlist(), vec![(); 5], &[false; 5]).is_err());
+    }
+}
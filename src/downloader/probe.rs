@@ -0,0 +1,168 @@
+//! 播放列表探测：只解析播放列表，不下载片段
+
+use crate::downloader::{
+    ContainerFormat, RenditionInfo, VariantInfo, VariantPolicy, active_keys, list_renditions,
+    list_variants, select_variant,
+};
+use crate::error::{DownloadError, Result};
+use crate::utils::download_segment::fetch_playlist_text;
+use crate::utils::http::{HttpOptions, build_http_client};
+use crate::utils::resolve_url;
+use crate::validation;
+use m3u8_rs::{MediaPlaylist, MediaPlaylistType};
+use serde::Serialize;
+use url::Url;
+
+/// 播放列表探测结果
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeReport {
+    pub url: String,
+    /// `master` 或 `media`
+    pub playlist_kind: &'static str,
+    pub variants: Vec<VariantInfo>,
+    pub renditions: Vec<RenditionInfo>,
+    /// 主播放列表按策略选中的子流序号
+    pub selected_variant: Option<usize>,
+    /// 实际解析的媒体播放列表
+    pub media: MediaReport,
+}
+
+/// 媒体播放列表概况
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaReport {
+    pub url: String,
+    pub segment_count: usize,
+    /// 所有片段 EXTINF 之和（秒）
+    pub total_duration: f64,
+    pub target_duration: u64,
+    pub media_sequence: u64,
+    /// 没有 `EXT-X-ENDLIST` 时视为直播
+    pub live: bool,
+    /// `EXT-X-PLAYLIST-TYPE`：VOD / EVENT
+    pub playlist_type: Option<String>,
+    /// 片段封装：`ts` 或 `fmp4`
+    pub container: &'static str,
+    pub discontinuity_count: usize,
+    pub byte_range: bool,
+    pub encryption: Vec<EncryptionInfo>,
+}
+
+/// 加密信息，按密钥分组
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptionInfo {
+    pub method: String,
+    pub uri: Option<String>,
+    pub iv: Option<String>,
+    pub keyformat: Option<String>,
+    /// 使用该密钥的片段数
+    pub segment_count: usize,
+}
+
+impl MediaReport {
+    pub fn from_playlist(url: &str, playlist: &MediaPlaylist) -> Self {
+        let base_url = Url::parse(url).ok();
+        let mut encryption: Vec<EncryptionInfo> = Vec::new();
+        for key in active_keys(&playlist.segments).into_iter().flatten() {
+            let method = key.method.to_string();
+            let uri = key.uri.map(|uri| {
+                base_url
+                    .as_ref()
+                    .and_then(|base| resolve_url(base, &uri).ok())
+                    .unwrap_or(uri)
+            });
+            if let Some(info) = encryption
+                .iter_mut()
+                .find(|e| e.method == method && e.uri == uri && e.iv == key.iv)
+            {
+                info.segment_count += 1;
+                continue;
+            }
+            encryption.push(EncryptionInfo {
+                method,
+                uri,
+                iv: key.iv,
+                keyformat: key.keyformat,
+                segment_count: 1,
+            });
+        }
+
+        let container = match ContainerFormat::detect(&playlist.segments) {
+            ContainerFormat::MpegTs => "ts",
+            ContainerFormat::Fmp4 => "fmp4",
+        };
+
+        Self {
+            url: url.to_string(),
+            segment_count: playlist.segments.len(),
+            total_duration: playlist
+                .segments
+                .iter()
+                .map(|s| f64::from(s.duration))
+                .sum(),
+            target_duration: playlist.target_duration,
+            media_sequence: playlist.media_sequence,
+            live: !playlist.end_list,
+            playlist_type: playlist.playlist_type.as_ref().map(|t| match t {
+                MediaPlaylistType::Event => "EVENT".to_string(),
+                MediaPlaylistType::Vod => "VOD".to_string(),
+                MediaPlaylistType::Other(other) => other.clone(),
+            }),
+            container,
+            discontinuity_count: playlist.segments.iter().filter(|s| s.discontinuity).count(),
+            byte_range: playlist.segments.iter().any(|s| s.byte_range.is_some()),
+            encryption,
+        }
+    }
+}
+
+fn parse_media(content: &str) -> Result<MediaPlaylist> {
+    m3u8_rs::parse_media_playlist(content.as_bytes())
+        .map(|(_, playlist)| playlist)
+        .map_err(|e| DownloadError::parse(format!("M3U8 解析失败: {e:?}")))
+}
+
+/// 获取并解析播放列表；主播放列表按策略选择子流后继续解析其媒体播放列表
+pub async fn probe_playlist(
+    url: &str,
+    policy: VariantPolicy,
+    codec: Option<&str>,
+    http: &HttpOptions,
+) -> Result<ProbeReport> {
+    validation::validate_url(url)?;
+    let base_url =
+        Url::parse(url).map_err(|e| DownloadError::parse(format!("URL解析失败: {e}")))?;
+    let client = build_http_client(&base_url, http)?;
+    let content = fetch_playlist_text(&client, url).await?;
+
+    if let Ok((_, master)) = m3u8_rs::parse_master_playlist(content.as_bytes())
+        && !master.variants.is_empty()
+    {
+        let selected = select_variant(&master.variants, policy, codec)?;
+        let selected_index = master
+            .variants
+            .iter()
+            .position(|v| std::ptr::eq(v, selected));
+        let media_url = resolve_url(&base_url, &selected.uri)?;
+        let media_content = fetch_playlist_text(&client, &media_url).await?;
+        let playlist = parse_media(&media_content)?;
+
+        return Ok(ProbeReport {
+            url: url.to_string(),
+            playlist_kind: "master",
+            variants: list_variants(&master.variants),
+            renditions: list_renditions(&master.alternatives),
+            selected_variant: selected_index,
+            media: MediaReport::from_playlist(&media_url, &playlist),
+        });
+    }
+
+    let playlist = parse_media(&content)?;
+    Ok(ProbeReport {
+        url: url.to_string(),
+        playlist_kind: "media",
+        variants: Vec::new(),
+        renditions: Vec::new(),
+        selected_variant: None,
+        media: MediaReport::from_playlist(url, &playlist),
+    })
+}
@@ -0,0 +1,800 @@
+//! 转封装：默认使用内置的 TS 解复用与 MP4 封装，FFmpeg 作为可选的后备方案
+
+use crate::config::WRITE_BUFFER_SIZE;
+use crate::downloader::demux::{Frame, TsDemuxer};
+use crate::downloader::mp4::{FragmentWriter, Mp4Writer, move_moov_to_front};
+use crate::downloader::{ContainerFormat, EncodePreset, OutputFormat, OutputOptions};
+use crate::error::{DownloadError, Result};
+use crate::utils::ffmpeg::ffmpeg_command;
+use bytes::Bytes;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// 直传输出的数据通道
+pub type StreamSender = mpsc::Sender<std::result::Result<Bytes, String>>;
+
+/// 直传模式下输出初始化段前最多暂存的样本数，超过后忽略仍未确定解码参数的轨道
+const INIT_PROBE_FRAMES: usize = 1000;
+
+/// 转封装方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Remuxer {
+    /// 优先使用内置转封装，遇到不支持的输入时改用 FFmpeg（默认）
+    #[default]
+    Auto,
+    /// 只使用内置转封装，不依赖 FFmpeg
+    Native,
+    /// 只使用 FFmpeg
+    Ffmpeg,
+}
+
+/// 内置转封装能否直接生成该输出（不含单独的音频/字幕轨道）
+///
+/// TS 片段可输出 MP4/M4A；只有一个初始化段且没有不连续点的 fMP4 原样输出为 MP4，无法写入标题。
+pub fn native_supports(
+    container: ContainerFormat,
+    init_count: usize,
+    period_count: usize,
+    output: &OutputOptions,
+) -> bool {
+    if output.preset != EncodePreset::Copy {
+        return false;
+    }
+    match container {
+        ContainerFormat::MpegTs => output.format.is_mp4_family(),
+        ContainerFormat::Fmp4 => {
+            output.format == OutputFormat::Mp4
+                && init_count <= 1
+                && period_count <= 1
+                && output.title.is_none()
+        }
+    }
+}
+
+/// 将 TS 文件转封装为普通 MP4/M4A，在阻塞线程中执行，`cancelled` 置位后尽快退出
+///
+/// `boundaries` 为各不连续时段（第一个除外）在输入中的起始位置，每个时段按自己的时间基准
+/// 解复用后接在上一时段之后。faststart 时先写入临时文件，再把 `moov` 移到文件开头。
+pub fn remux_ts_file(
+    input: &Path,
+    output: &Path,
+    options: &OutputOptions,
+    boundaries: &[u64],
+    cancelled: &AtomicBool,
+) -> Result<()> {
+    if !options.faststart {
+        return write_mp4_file(input, output, options, boundaries, cancelled);
+    }
+    let staging = output.with_extension(format!("{}.part", options.format.extension()));
+    let result = write_mp4_file(input, &staging, options, boundaries, cancelled)
+        .and_then(|()| move_moov_to_front(&staging, output));
+    let _ = std::fs::remove_file(&staging);
+    result
+}
+
+/// M4A 输出只写入音频样本
+fn write_mp4_file(
+    input: &Path,
+    output: &Path,
+    options: &OutputOptions,
+    boundaries: &[u64],
+    cancelled: &AtomicBool,
+) -> Result<()> {
+    let file = std::fs::File::open(input).map_err(|e| DownloadError::file(input, e.to_string()))?;
+    let mut reader = BufReader::with_capacity(WRITE_BUFFER_SIZE, file);
+    let file =
+        std::fs::File::create(output).map_err(|e| DownloadError::file(output, e.to_string()))?;
+    let audio_only = options.format.is_audio_only();
+    let mut writer = Mp4Writer::new(
+        BufWriter::with_capacity(WRITE_BUFFER_SIZE, file),
+        audio_only,
+    )?;
+
+    let mut demuxer = TsDemuxer::default();
+    let mut frames = Vec::new();
+    let mut buf = vec![0u8; WRITE_BUFFER_SIZE];
+    let mut boundaries = boundaries.iter().copied().peekable();
+    let mut position = 0u64;
+    let mut finished = false;
+    while !finished {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(DownloadError::remux("转封装已取消"));
+        }
+        while boundaries.next_if(|&b| b <= position).is_some() {
+            demuxer.discontinuity(&mut frames)?;
+        }
+        // 每次读取不跨越下一个时段的起点
+        let limit = boundaries.peek().map_or(buf.len(), |&b| {
+            usize::try_from(b - position).map_or(buf.len(), |rest| rest.min(buf.len()))
+        });
+        let n = reader
+            .read(&mut buf[..limit])
+            .map_err(|e| DownloadError::file(input, e.to_string()))?;
+        position += n as u64;
+        if n == 0 {
+            demuxer.finish(&mut frames)?;
+            finished = true;
+        } else {
+            demuxer.push(&buf[..n], &mut frames)?;
+        }
+        for frame in frames.drain(..) {
+            match demuxer.codec(frame.track) {
+                Some(codec) if !(audio_only && codec.is_video()) => {
+                    writer.write_frame(&frame, codec)?;
+                }
+                _ => {}
+            }
+        }
+    }
+    writer.finish(options.title.as_deref())?;
+    Ok(())
+}
+
+/// 将合并后的 TS 临时文件转封装为 MP4/M4A，失败时删除不完整的输出
+///
+/// 等待被中断（如任务暂停或取消）时通知阻塞线程停止写入
+pub async fn remux_ts_to_mp4(
+    temp_path: &Path,
+    output_path: &Path,
+    options: &OutputOptions,
+    boundaries: &[u64],
+) -> Result<()> {
+    let (input, output) = (temp_path.to_path_buf(), output_path.to_path_buf());
+    let (options, boundaries) = (options.clone(), boundaries.to_vec());
+    let guard = CancelOnDrop(Arc::default());
+    let cancelled = guard.0.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let result = remux_ts_file(&input, &output, &options, &boundaries, &cancelled);
+        if result.is_err() {
+            let _ = std::fs::remove_file(&output);
+        }
+        result
+    })
+    .await
+    .map_err(|e| DownloadError::remux(format!("转封装任务异常: {e}")))?;
+    drop(guard);
+    result
+}
+
+/// 离开作用域时置位取消标志
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// 增量地把 TS 数据转为分片 MP4
+#[derive(Default)]
+struct TsFragmenter {
+    demuxer: TsDemuxer,
+    frames: Vec<Frame>,
+    /// 输出初始化段之前暂存的样本
+    pending: Vec<Frame>,
+    writer: Option<FragmentWriter>,
+    /// 解复用轨道对应的输出轨道
+    mapping: Vec<Option<usize>>,
+}
+
+impl TsFragmenter {
+    fn push(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.demuxer.push(data, &mut self.frames)?;
+        self.drain(false)
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        self.demuxer.finish(&mut self.frames)?;
+        self.drain(true)
+    }
+
+    /// 不连续点之后的数据按新时段处理
+    fn discontinuity(&mut self) -> Result<()> {
+        self.demuxer.discontinuity(&mut self.frames)
+    }
+
+    fn drain(&mut self, last: bool) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        if self.writer.is_none() {
+            self.pending.append(&mut self.frames);
+            if !last && !self.ready() {
+                return Ok(out);
+            }
+            let writer = self.start()?;
+            out.extend_from_slice(&writer.init_segment());
+            self.writer = Some(writer);
+            self.frames = std::mem::take(&mut self.pending);
+        }
+
+        if let Some(writer) = self.writer.as_mut() {
+            for frame in self.frames.drain(..) {
+                if let Some(Some(track)) = self.mapping.get(frame.track) {
+                    writer.push(*track, frame);
+                }
+            }
+            out.extend_from_slice(&writer.flush(last));
+        }
+        Ok(out)
+    }
+
+    /// 所有轨道都已确定解码参数，或暂存的样本已经足够多
+    fn ready(&self) -> bool {
+        let configured = (0..self.demuxer.track_count()).all(|t| self.demuxer.codec(t).is_some());
+        (configured && !self.pending.is_empty()) || self.pending.len() >= INIT_PROBE_FRAMES
+    }
+
+    /// 按已确定解码参数的轨道创建分片输出
+    fn start(&mut self) -> Result<FragmentWriter> {
+        let mut tracks = Vec::new();
+        self.mapping = (0..self.demuxer.track_count())
+            .map(|t| {
+                let codec = self.demuxer.codec(t)?;
+                let first_dts = self
+                    .pending
+                    .iter()
+                    .filter(|f| f.track == t)
+                    .map(|f| f.dts)
+                    .min()?;
+                tracks.push((codec.clone(), first_dts));
+                Some(tracks.len() - 1)
+            })
+            .collect();
+        if tracks.is_empty() {
+            return Err(DownloadError::remux("没有找到可转封装的音视频数据"));
+        }
+        Ok(FragmentWriter::new(tracks))
+    }
+}
+
+/// 内置的直传输出
+enum NativeOutput {
+    Ts(Box<TsFragmenter>),
+    /// 只有一个初始化段的 fMP4 本身就是分片 MP4，原样输出
+    Passthrough,
+}
+
+impl NativeOutput {
+    fn push(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Ts(fragmenter) => fragmenter.push(data),
+            Self::Passthrough => Ok(data.to_vec()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Self::Ts(fragmenter) => fragmenter.finish(),
+            Self::Passthrough => Ok(Vec::new()),
+        }
+    }
+}
+
+/// 通过 FFmpeg 子进程转为分片 MP4，标准输出转发到直传通道
+struct FfmpegPipe {
+    child: Child,
+    stdin: ChildStdin,
+    stdout_task: JoinHandle<Result<()>>,
+    stderr_task: JoinHandle<Vec<u8>>,
+}
+
+impl FfmpegPipe {
+    fn spawn(container: ContainerFormat, tx: StreamSender) -> Result<Self> {
+        let mut child = ffmpeg_command()?
+            .args([
+                "-nostdin",
+                "-f",
+                container.ffmpeg_format(),
+                "-i",
+                "pipe:0",
+                "-c",
+                "copy",
+            ])
+            .args(container.audio_bitstream_filter())
+            .args([
+                "-movflags",
+                "frag_keyframe+empty_moov+default_base_moof",
+                "-f",
+                "mp4",
+                "pipe:1",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| DownloadError::ffmpeg(format!("启动FFmpeg失败: {e}")))?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准输入".to_string()))?;
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准输出".to_string()))?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准错误输出".to_string()))?;
+
+        let stdout_task = tokio::spawn(async move {
+            let mut buf = vec![0u8; WRITE_BUFFER_SIZE];
+            loop {
+                let n = stdout
+                    .read(&mut buf)
+                    .await
+                    .map_err(|e| DownloadError::ffmpeg(format!("读取FFmpeg输出失败: {e}")))?;
+                if n == 0 {
+                    break;
+                }
+                if tx
+                    .send(Ok(Bytes::copy_from_slice(&buf[..n])))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        let stderr_task = tokio::spawn(async move {
+            let mut stderr_buf = Vec::new();
+            let _ = stderr.read_to_end(&mut stderr_buf).await;
+            stderr_buf
+        });
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout_task,
+            stderr_task,
+        })
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.stdin
+            .write_all(data)
+            .await
+            .map_err(|e| DownloadError::ffmpeg(format!("写入FFmpeg失败: {e}")))
+    }
+
+    async fn finish(self) -> Result<()> {
+        let Self {
+            mut child,
+            mut stdin,
+            stdout_task,
+            stderr_task,
+        } = self;
+        stdin
+            .flush()
+            .await
+            .map_err(|e| DownloadError::ffmpeg(format!("刷新FFmpeg输入失败: {e}")))?;
+        drop(stdin);
+
+        let status = child
+            .wait()
+            .await
+            .map_err(|e| DownloadError::ffmpeg(format!("等待FFmpeg失败: {e}")))?;
+        stdout_task
+            .await
+            .map_err(|e| DownloadError::ffmpeg(format!("读取FFmpeg输出任务失败: {e}")))??;
+        let stderr_output = stderr_task.await.unwrap_or_default();
+
+        if !status.success() {
+            let stderr_text = String::from_utf8_lossy(&stderr_output).trim().to_string();
+            if stderr_text.is_empty() {
+                return Err(DownloadError::ffmpeg("FFmpeg转换失败".to_string()));
+            }
+            return Err(DownloadError::ffmpeg(format!(
+                "FFmpeg转换失败: {stderr_text}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+enum StreamState {
+    /// `replay` 保存产生输出之前送入的数据，内置转封装失败时交给 FFmpeg 重新处理
+    Native {
+        output: NativeOutput,
+        replay: Option<Vec<Vec<u8>>>,
+    },
+    Ffmpeg(FfmpegPipe),
+}
+
+/// 直传模式的分片 MP4 输出
+///
+/// 依次送入片段数据（fMP4 需在片段前送入初始化段），转封装结果写入直传通道。
+/// `Auto` 模式下内置转封装在产生任何输出之前失败时，改用 FFmpeg 重新处理已送入的数据。
+pub struct Mp4Stream {
+    tx: StreamSender,
+    container: ContainerFormat,
+    state: StreamState,
+}
+
+impl Mp4Stream {
+    /// `init_count` 为播放列表中不同初始化段的个数
+    pub fn new(
+        remuxer: Remuxer,
+        container: ContainerFormat,
+        init_count: usize,
+        tx: StreamSender,
+    ) -> Result<Self> {
+        let native = match (remuxer, container) {
+            (Remuxer::Ffmpeg, _) => None,
+            (_, ContainerFormat::MpegTs) => Some(NativeOutput::Ts(Box::default())),
+            (_, ContainerFormat::Fmp4) if init_count <= 1 => Some(NativeOutput::Passthrough),
+            (Remuxer::Native, ContainerFormat::Fmp4) => {
+                return Err(DownloadError::remux(
+                    "包含多个初始化段的fMP4需要使用FFmpeg转封装",
+                ));
+            }
+            (Remuxer::Auto, ContainerFormat::Fmp4) => None,
+        };
+        let state = match native {
+            Some(output) => StreamState::Native {
+                output,
+                replay: (remuxer == Remuxer::Auto).then(Vec::new),
+            },
+            None => StreamState::Ffmpeg(FfmpegPipe::spawn(container, tx.clone())?),
+        };
+        Ok(Self {
+            tx,
+            container,
+            state,
+        })
+    }
+
+    /// 输出时是否一定需要FFmpeg，与 [`Mp4Stream::new`] 的选择一致
+    pub fn requires_ffmpeg(
+        remuxer: Remuxer,
+        container: ContainerFormat,
+        init_count: usize,
+    ) -> bool {
+        match remuxer {
+            Remuxer::Ffmpeg => true,
+            Remuxer::Native => false,
+            Remuxer::Auto => container == ContainerFormat::Fmp4 && init_count > 1,
+        }
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        let result = match &mut self.state {
+            StreamState::Ffmpeg(pipe) => return pipe.write(data).await,
+            StreamState::Native { output, replay } => match output.push(data) {
+                Ok(out) if out.is_empty() => {
+                    if let Some(replay) = replay {
+                        replay.push(data.to_vec());
+                    }
+                    return Ok(());
+                }
+                Ok(out) => {
+                    *replay = None;
+                    Ok(out)
+                }
+                Err(e) => match replay.take() {
+                    Some(mut replay) => {
+                        replay.push(data.to_vec());
+                        Err((e, Some(replay)))
+                    }
+                    None => Err((e, None)),
+                },
+            },
+        };
+
+        match result {
+            Ok(out) => self.send(out).await,
+            Err((e, Some(replay))) => self.fall_back(&e, replay).await,
+            Err((e, None)) => Err(e),
+        }
+    }
+
+    /// 下一个片段之前有 `EXT-X-DISCONTINUITY`，内置转封装以连续的时间戳接上新时段
+    pub fn discontinuity(&mut self) -> Result<()> {
+        match &mut self.state {
+            StreamState::Native {
+                output: NativeOutput::Ts(fragmenter),
+                ..
+            } => fragmenter.discontinuity(),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn finish(mut self) -> Result<()> {
+        match &mut self.state {
+            StreamState::Ffmpeg(_) => {}
+            StreamState::Native { output, replay } => match output.finish() {
+                Ok(out) => return self.send(out).await,
+                Err(e) => match replay.take() {
+                    Some(replay) => self.fall_back(&e, replay).await?,
+                    None => return Err(e),
+                },
+            },
+        }
+        match self.state {
+            StreamState::Ffmpeg(pipe) => pipe.finish().await,
+            StreamState::Native { .. } => Ok(()),
+        }
+    }
+
+    async fn send(&self, data: Vec<u8>) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.tx
+            .send(Ok(Bytes::from(data)))
+            .await
+            .map_err(|_| DownloadError::task("直传输出", "输出通道已关闭"))
+    }
+
+    /// 内置转封装失败且尚未输出任何数据，改用 FFmpeg 处理已送入的全部数据
+    async fn fall_back(&mut self, error: &DownloadError, replay: Vec<Vec<u8>>) -> Result<()> {
+        warn!("内置转封装失败，改用FFmpeg: {error}");
+        let mut pipe = FfmpegPipe::spawn(self.container, self.tx.clone())?;
+        for data in replay {
+            pipe.write(&data).await?;
+        }
+        self.state = StreamState::Ffmpeg(pipe);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::demux::MPEG_TIMESCALE;
+    use crate::downloader::ts::{PAT_PID, packetize_pes, update_section_crc, write_packet};
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+    /// 第一帧的 DTS（90kHz）
+    const START: i64 = 126_000;
+    /// 25fps 的帧间隔（90kHz）
+    const FRAME_DURATION: i64 = 3600;
+    const VIDEO_FRAMES: i64 = 10;
+    /// 每个音频 PES 含 2 个 AAC 帧
+    const AUDIO_PES: i64 = 8;
+    /// Baseline 320x240 的 SPS 与 PPS
+    const SPS: [u8; 8] = [0x67, 0x42, 0x00, 0x28, 0xF4, 0x0A, 0x0F, 0xC8];
+    const PPS: [u8; 4] = [0x68, 0xCE, 0x38, 0x80];
+    /// 44.1kHz 双声道 AAC-LC 的 ADTS 帧（7 字节帧头 + 4 字节数据）
+    const ADTS_FRAME: [u8; 11] = [
+        0xFF, 0xF1, 0x50, 0x80, 0x01, 0x7F, 0xFC, 0x21, 0x10, 0x05, 0x40,
+    ];
+
+    fn pes_timestamp(prefix: u8, ts: i64) -> [u8; 5] {
+        [
+            (prefix << 4) | (((ts >> 30) & 0x07) as u8) << 1 | 1,
+            (ts >> 22) as u8,
+            (((ts >> 15) & 0x7F) as u8) << 1 | 1,
+            (ts >> 7) as u8,
+            ((ts & 0x7F) as u8) << 1 | 1,
+        ]
+    }
+
+    fn pes(stream_id: u8, pts: i64, dts: Option<i64>, payload: &[u8]) -> Vec<u8> {
+        let mut header = match dts {
+            Some(dts) => {
+                let mut header = vec![0x80, 0xC0, 10];
+                header.extend(pes_timestamp(3, pts));
+                header.extend(pes_timestamp(1, dts));
+                header
+            }
+            None => {
+                let mut header = vec![0x80, 0x80, 5];
+                header.extend(pes_timestamp(2, pts));
+                header
+            }
+        };
+        // 视频 PES 长度写 0（不限长度）
+        let len = if stream_id == 0xE0 {
+            0
+        } else {
+            header.len() + payload.len()
+        };
+        let mut pes = vec![0, 0, 1, stream_id, (len >> 8) as u8, len as u8];
+        pes.append(&mut header);
+        pes.extend_from_slice(payload);
+        pes
+    }
+
+    fn write_section(out: &mut Vec<u8>, pid: u16, mut section: Vec<u8>) {
+        update_section_crc(&mut section);
+        let payload: Vec<u8> = std::iter::once(0).chain(section).collect();
+        write_packet(out, pid, true, 0, None, &payload);
+    }
+
+    /// 一段 H.264 + AAC 的 TS：10 帧视频（首帧为 IDR）与 16 个 AAC 帧
+    fn fixture_ts() -> Vec<u8> {
+        let mut out = Vec::new();
+        // 节目 1 的 PMT 位于 PMT_PID
+        write_section(
+            &mut out,
+            PAT_PID,
+            vec![
+                0x00, 0xB0, 13, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00, 0, 0, 0, 0,
+            ],
+        );
+        // H.264 位于 VIDEO_PID，AAC 位于 AUDIO_PID
+        write_section(
+            &mut out,
+            PMT_PID,
+            vec![
+                0x02, 0xB0, 23, 0x00, 0x01, 0xC1, 0x00, 0x00, 0xE1, 0x00, 0xF0, 0x00, 0x1B, 0xE1,
+                0x00, 0xF0, 0x00, 0x0F, 0xE1, 0x01, 0xF0, 0x00, 0, 0, 0, 0,
+            ],
+        );
+
+        let (mut video_cc, mut audio_cc) = (0, 0);
+        for i in 0..VIDEO_FRAMES {
+            let dts = START + i * FRAME_DURATION;
+            let mut es = vec![0, 0, 0, 1, 0x09, 0xF0];
+            if i == 0 {
+                for nal in [&SPS[..], &PPS[..], &[0x65, 0x88, 0x84, 0x21, 0xA0]] {
+                    es.extend_from_slice(&[0, 0, 0, 1]);
+                    es.extend_from_slice(nal);
+                }
+            } else {
+                es.extend_from_slice(&[0, 0, 1, 0x41, 0x9A, 0x02, 0x0C, i as u8]);
+            }
+            let video = pes(0xE0, dts + 2 * FRAME_DURATION, Some(dts), &es);
+            video_cc = packetize_pes(&mut out, VIDEO_PID, video_cc, None, &video);
+
+            if i < AUDIO_PES {
+                let pts = START + i * 2 * 1024 * i64::from(MPEG_TIMESCALE) / 44100;
+                let audio = pes(0xC0, pts, None, &ADTS_FRAME.repeat(2));
+                audio_cc = packetize_pes(&mut out, AUDIO_PID, audio_cc, None, &audio);
+            }
+        }
+        out
+    }
+
+    /// 依次列出 `data` 中的 box，返回 (类型, 内容)
+    fn boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut result = Vec::new();
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let kind = data[pos + 4..pos + 8].try_into().unwrap();
+            let (header, size) = match u32_at(data, pos) {
+                1 => (
+                    16,
+                    u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()) as usize,
+                ),
+                size => (8, size as usize),
+            };
+            result.push((kind, &data[pos + header..pos + size]));
+            pos += size;
+        }
+        result
+    }
+
+    /// 按路径查找所有匹配的 box 内容
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Vec<&'a [u8]> {
+        let Some((first, rest)) = path.split_first() else {
+            return vec![data];
+        };
+        boxes(data)
+            .into_iter()
+            .filter(|(kind, _)| kind == *first)
+            .flat_map(|(_, content)| find(content, rest))
+            .collect()
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    /// stts 条目：(样本数, 时长)
+    type SttsEntries = Vec<(u32, u32)>;
+
+    /// 各轨道的 (handler, stts 条目)
+    fn track_stts(mp4: &[u8]) -> Vec<([u8; 4], SttsEntries)> {
+        find(mp4, &[b"moov", b"trak"])
+            .into_iter()
+            .map(|trak| {
+                let handler = find(trak, &[b"mdia", b"hdlr"])[0][8..12]
+                    .try_into()
+                    .unwrap();
+                let stts = find(trak, &[b"mdia", b"minf", b"stbl", b"stts"])[0];
+                let entries = (0..u32_at(stts, 4) as usize)
+                    .map(|i| (u32_at(stts, 8 + i * 8), u32_at(stts, 12 + i * 8)))
+                    .collect();
+                (handler, entries)
+            })
+            .collect()
+    }
+
+    fn remux_to_mp4(name: &str, ts: &[u8], boundaries: &[u64]) -> Vec<u8> {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("m3u8_remux_{}_{name}.ts", std::process::id()));
+        let output = input.with_extension("mp4");
+        std::fs::write(&input, ts).unwrap();
+        let result = remux_ts_file(
+            &input,
+            &output,
+            &OutputOptions::default(),
+            boundaries,
+            &AtomicBool::new(false),
+        );
+        let mp4 = std::fs::read(&output);
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+        result.unwrap();
+        mp4.unwrap()
+    }
+
+    #[test]
+    fn ts_remux_writes_sample_durations() {
+        let mp4 = remux_to_mp4("single", &fixture_ts(), &[]);
+        assert_eq!(
+            track_stts(&mp4),
+            vec![(*b"vide", vec![(10, 3600)]), (*b"soun", vec![(16, 1024)]),]
+        );
+    }
+
+    #[test]
+    fn ts_remux_continues_timeline_after_discontinuity() {
+        let fixture = fixture_ts();
+        let ts = fixture.repeat(2);
+        let mp4 = remux_to_mp4("periods", &ts, &[fixture.len() as u64]);
+        // 第二时段接在视频结尾（162000），音频在两个时段之间留出空隙
+        assert_eq!(
+            track_stts(&mp4),
+            vec![
+                (*b"vide", vec![(20, 3600)]),
+                (*b"soun", vec![(15, 1024), (1, 2280), (16, 1024)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ts_remux_resyncs_on_misaligned_input() {
+        let fixture = fixture_ts();
+        // 开头有不足一个包的残留数据，第一段末尾又缺 2 字节，之后的包都不再按 188 字节对齐
+        let mut ts = vec![0x00, 0x01, 0x02];
+        ts.extend_from_slice(&fixture[..fixture.len() - 2]);
+        ts.extend_from_slice(&fixture);
+        let mp4 = remux_to_mp4("misaligned", &ts, &[]);
+        assert_eq!(
+            track_stts(&mp4),
+            vec![(*b"vide", vec![(20, 3600)]), (*b"soun", vec![(32, 1024)])]
+        );
+    }
+
+    #[test]
+    fn ts_fragmenter_writes_trun_durations() {
+        let mut fragmenter = TsFragmenter::default();
+        let mut output = Vec::new();
+        for chunk in fixture_ts().chunks(1000) {
+            output.extend(fragmenter.push(chunk).unwrap());
+        }
+        output.extend(fragmenter.finish().unwrap());
+
+        let mut durations: Vec<Vec<u32>> = vec![Vec::new(); 2];
+        let mut base_times: Vec<Option<u64>> = vec![None; 2];
+        for traf in find(&output, &[b"moof", b"traf"]) {
+            let track = u32_at(find(traf, &[b"tfhd"])[0], 4) as usize - 1;
+            let tfdt = find(traf, &[b"tfdt"])[0];
+            let base = u64::from_be_bytes(tfdt[4..12].try_into().unwrap());
+            base_times[track].get_or_insert(base);
+            let trun = find(traf, &[b"trun"])[0];
+            for i in 0..u32_at(trun, 4) as usize {
+                durations[track].push(u32_at(trun, 12 + i * 16));
+            }
+        }
+
+        assert_eq!(durations, vec![vec![3600; 10], vec![1024; 16]]);
+        assert_eq!(base_times, vec![Some(0), Some(0)]);
+    }
+}
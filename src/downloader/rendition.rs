@@ -0,0 +1,271 @@
+//! 主播放列表中的备选音频/字幕（`EXT-X-MEDIA`）
+
+use crate::downloader::ContainerFormat;
+use log::{info, warn};
+use m3u8_rs::{AlternativeMedia, AlternativeMediaType, MediaSegment, VariantStream};
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// 选择全部语言的特殊取值
+pub const ALL_LANGUAGES: &str = "all";
+
+/// 备选媒体信息，供接口展示与选择
+#[derive(Debug, Clone, Serialize)]
+pub struct RenditionInfo {
+    pub media_type: String,
+    pub group_id: String,
+    pub name: String,
+    pub language: Option<String>,
+    pub uri: Option<String>,
+    pub default: bool,
+    pub autoselect: bool,
+    pub channels: Option<String>,
+}
+
+impl RenditionInfo {
+    pub fn from_media(media: &AlternativeMedia) -> Self {
+        Self {
+            media_type: media.media_type.to_string(),
+            group_id: media.group_id.clone(),
+            name: media.name.clone(),
+            language: media.language.clone(),
+            uri: media.uri.clone(),
+            default: media.default,
+            autoselect: media.autoselect,
+            channels: media.channels.clone(),
+        }
+    }
+}
+
+/// 列出主播放列表中的全部备选媒体
+pub fn list_renditions(alternatives: &[AlternativeMedia]) -> Vec<RenditionInfo> {
+    alternatives.iter().map(RenditionInfo::from_media).collect()
+}
+
+/// 备选媒体类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenditionKind {
+    Audio,
+    Subtitles,
+}
+
+impl RenditionKind {
+    /// FFmpeg 流说明符中的类型字母
+    pub const fn stream_specifier(self) -> &'static str {
+        match self {
+            Self::Audio => "a",
+            Self::Subtitles => "s",
+        }
+    }
+
+    /// 临时目录名前缀
+    pub const fn dir_prefix(self) -> &'static str {
+        match self {
+            Self::Audio => "audio",
+            Self::Subtitles => "subtitles",
+        }
+    }
+}
+
+/// 选中的需要单独下载的备选媒体
+#[derive(Debug, Clone)]
+pub struct SelectedRendition {
+    pub kind: RenditionKind,
+    /// 子播放列表地址（相对主播放列表）
+    pub uri: String,
+    pub name: String,
+    pub language: Option<String>,
+}
+
+/// 下载并合并完成的备选媒体轨道
+#[derive(Debug, Clone)]
+pub struct RenditionTrack {
+    pub kind: RenditionKind,
+    pub path: PathBuf,
+    /// 传给 `FFmpeg -f` 的输入格式名
+    pub ffmpeg_format: &'static str,
+    pub name: String,
+    pub language: Option<String>,
+}
+
+impl RenditionTrack {
+    /// 轨道是否需要 `aac_adtstoasc`（TS 或 ADTS 中的 AAC）
+    pub fn needs_adts_conversion(&self) -> bool {
+        self.kind == RenditionKind::Audio && matches!(self.ffmpeg_format, "mpegts" | "aac")
+    }
+}
+
+/// 语言偏好是否匹配（按主语言子标签比较，也可直接匹配 NAME）
+fn matches_language(media: &AlternativeMedia, wanted: &str) -> bool {
+    let wanted = wanted.trim().to_ascii_lowercase();
+    if media.name.to_ascii_lowercase() == wanted {
+        return true;
+    }
+    media.language.as_deref().is_some_and(|language| {
+        let language = language.to_ascii_lowercase();
+        language == wanted || primary_subtag(&language) == primary_subtag(&wanted)
+    })
+}
+
+fn primary_subtag(language: &str) -> &str {
+    language.split(['-', '_']).next().unwrap_or(language)
+}
+
+/// 从一组备选媒体中按语言偏好选择
+///
+/// 偏好为空时选择 DEFAULT=YES（或第一个 AUTOSELECT=YES）的一路；
+/// 偏好包含 `all` 时选择全部。
+fn pick<'a>(
+    candidates: &[&'a AlternativeMedia],
+    languages: &[String],
+    fallback_to_default: bool,
+) -> Vec<&'a AlternativeMedia> {
+    if languages
+        .iter()
+        .any(|l| l.eq_ignore_ascii_case(ALL_LANGUAGES))
+    {
+        return candidates.to_vec();
+    }
+
+    let default = || {
+        candidates
+            .iter()
+            .copied()
+            .find(|m| m.default)
+            .or_else(|| candidates.iter().copied().find(|m| m.autoselect))
+            .or_else(|| candidates.first().copied())
+    };
+
+    if languages.is_empty() {
+        return if fallback_to_default {
+            default().into_iter().collect()
+        } else {
+            Vec::new()
+        };
+    }
+
+    let mut selected: Vec<&AlternativeMedia> = Vec::new();
+    for language in languages {
+        match candidates.iter().find(|m| matches_language(m, language)) {
+            Some(media) if !selected.iter().any(|s| std::ptr::eq(*s, *media)) => {
+                selected.push(media);
+            }
+            Some(_) => {}
+            None => warn!("没有语言为 {language} 的备选媒体"),
+        }
+    }
+    if selected.is_empty() && fallback_to_default {
+        selected.extend(default());
+    }
+    selected
+}
+
+/// 按子流引用的 AUDIO/SUBTITLES 分组和语言偏好选择需要单独下载的备选媒体
+///
+/// 音频未指定语言时选择分组默认的一路；字幕未指定语言时不下载。
+/// 没有 URI 的备选媒体已复用在子流中，不单独下载。
+pub fn select_renditions(
+    alternatives: &[AlternativeMedia],
+    variant: &VariantStream,
+    audio_languages: &[String],
+    subtitle_languages: &[String],
+) -> Vec<SelectedRendition> {
+    let group = |media_type: &AlternativeMediaType, group_id: Option<&String>| {
+        alternatives
+            .iter()
+            .filter(|m| &m.media_type == media_type && Some(&m.group_id) == group_id)
+            .collect::<Vec<_>>()
+    };
+
+    let audio = group(&AlternativeMediaType::Audio, variant.audio.as_ref());
+    let subtitles = group(&AlternativeMediaType::Subtitles, variant.subtitles.as_ref());
+
+    let selected = pick(&audio, audio_languages, true)
+        .into_iter()
+        .map(|m| (RenditionKind::Audio, m))
+        .chain(
+            pick(&subtitles, subtitle_languages, false)
+                .into_iter()
+                .map(|m| (RenditionKind::Subtitles, m)),
+        );
+
+    selected
+        .filter_map(|(kind, media)| {
+            let Some(uri) = &media.uri else {
+                info!("备选媒体 {} 已包含在子流中", media.name);
+                return None;
+            };
+            info!(
+                "选择备选媒体: {} (类型: {}, 语言: {})",
+                media.name,
+                media.media_type,
+                media.language.as_deref().unwrap_or("未知")
+            );
+            Some(SelectedRendition {
+                kind,
+                uri: uri.clone(),
+                name: media.name.clone(),
+                language: media.language.clone(),
+            })
+        })
+        .collect()
+}
+
+/// 将 BCP-47 语言标签转换为 MP4/MKV 使用的 ISO 639-2 三字母代码
+pub fn iso639_2(language: &str) -> String {
+    let primary = primary_subtag(language.trim()).to_ascii_lowercase();
+    let code = match primary.as_str() {
+        "zh" => "zho",
+        "en" => "eng",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "fr" => "fra",
+        "de" => "deu",
+        "es" => "spa",
+        "pt" => "por",
+        "ru" => "rus",
+        "it" => "ita",
+        "ar" => "ara",
+        "hi" => "hin",
+        "th" => "tha",
+        "vi" => "vie",
+        "id" => "ind",
+        "ms" => "msa",
+        "tr" => "tur",
+        "nl" => "nld",
+        "pl" => "pol",
+        "sv" => "swe",
+        code if code.len() == 3 && code.chars().all(|c| c.is_ascii_lowercase()) => code,
+        _ => "und",
+    };
+    code.to_string()
+}
+
+/// 根据片段判断备选媒体轨道的 FFmpeg 输入格式
+///
+/// 字幕为 WebVTT；音频可能是 TS、fMP4 或不带封装的 AAC/MP3/AC-3 等独立音频。
+pub fn track_format(
+    kind: RenditionKind,
+    segments: &[MediaSegment],
+    container: ContainerFormat,
+) -> &'static str {
+    if kind == RenditionKind::Subtitles {
+        return "webvtt";
+    }
+    if container == ContainerFormat::Fmp4 {
+        return container.ffmpeg_format();
+    }
+
+    let extension = segments
+        .first()
+        .map(|s| s.uri.split(['?', '#']).next().unwrap_or(&s.uri))
+        .and_then(|path| path.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("aac") => "aac",
+        Some("mp3") => "mp3",
+        Some("ac3") => "ac3",
+        Some("ec3") => "eac3",
+        _ => container.ffmpeg_format(),
+    }
+}
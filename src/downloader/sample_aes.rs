@@ -0,0 +1,471 @@
+//! SAMPLE-AES 解密
+//!
+//! 按 Apple《MPEG-2 Stream Encryption Format for HTTP Live Streaming》处理
+//! MPEG-TS 封装的 H.264 视频与 ADTS AAC 音频：解出 PES 后按样本解密，
+//! 再重新打包为 TS，并把 PMT 中的加密流类型改回普通流类型。
+
+use crate::config::TS_PACKET_SIZE;
+use crate::downloader::ts::{
+    PAT_PID, STREAM_TYPE_AAC, STREAM_TYPE_H264, packetize_pes, parse_packet, parse_pat, parse_pmt,
+    psi_section_range, update_section_crc,
+};
+use crate::error::{DownloadError, Result};
+use aes::Aes128;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use std::collections::HashMap;
+
+type Aes128CbcDec = cbc::Decryptor<Aes128>;
+
+/// SAMPLE-AES 加密的 H.264 流类型
+const STREAM_TYPE_H264_ENCRYPTED: u8 = 0xDB;
+/// SAMPLE-AES 加密的 ADTS AAC 流类型
+const STREAM_TYPE_AAC_ENCRYPTED: u8 = 0xCF;
+
+/// 视频NAL单元前 32 字节不加密
+const NAL_CLEAR_LEADER: usize = 32;
+/// 只有长度超过 48 字节的 NAL 单元才会被加密
+const NAL_MIN_ENCRYPTED_LENGTH: usize = 48;
+/// 每 16 字节加密块之后跟随 144 字节明文
+const NAL_CLEAR_STRIDE: usize = 144;
+/// 音频帧头之后 16 字节不加密
+const AUDIO_CLEAR_LEADER: usize = 16;
+
+#[derive(Clone, Copy)]
+enum EncryptedCodec {
+    H264,
+    Aac,
+}
+
+struct PendingPes {
+    slot: usize,
+    adaptation: Option<Vec<u8>>,
+    data: Vec<u8>,
+}
+
+/// 解密 SAMPLE-AES 加密的 MPEG-TS 片段
+pub fn decrypt_sample_aes_ts(data: &[u8], key: &[u8], iv: &[u8; 16]) -> Result<Vec<u8>> {
+    if !data.len().is_multiple_of(TS_PACKET_SIZE) {
+        return Err(DownloadError::decryption(
+            "SAMPLE-AES 仅支持 MPEG-TS 片段，数据长度不是188的整数倍",
+        ));
+    }
+
+    let mut codecs: HashMap<u16, EncryptedCodec> = HashMap::new();
+    let mut pmt_pids: Vec<u16> = Vec::new();
+    let mut pending: HashMap<u16, PendingPes> = HashMap::new();
+    let mut counters: HashMap<u16, u8> = HashMap::new();
+    // 输出按槽位排列，PES 槽位在其起始包处预留，解密完成后再填充
+    let mut slots: Vec<Vec<u8>> = Vec::new();
+    let mut raw_slot_open = false;
+
+    for chunk in data.chunks(TS_PACKET_SIZE) {
+        let Some(packet) = parse_packet(chunk) else {
+            return Err(DownloadError::decryption("TS 同步字节错误"));
+        };
+        let pid = packet.pid;
+
+        if let Some(&codec) = codecs.get(&pid)
+            && !packet.payload.is_empty()
+        {
+            if packet.payload_unit_start {
+                if let Some(pes) = pending.remove(&pid) {
+                    finish_pes(pid, pes, codec, key, iv, &mut counters, &mut slots);
+                }
+                counters.entry(pid).or_insert(packet.continuity_counter);
+                slots.push(Vec::new());
+                raw_slot_open = false;
+                pending.insert(
+                    pid,
+                    PendingPes {
+                        slot: slots.len() - 1,
+                        adaptation: packet.adaptation.map(essential_adaptation),
+                        data: packet.payload.to_vec(),
+                    },
+                );
+                continue;
+            }
+            if let Some(pes) = pending.get_mut(&pid) {
+                pes.data.extend_from_slice(packet.payload);
+                continue;
+            }
+        }
+
+        let mut raw = chunk.to_vec();
+        if packet.payload_unit_start && pid == PAT_PID {
+            if let Some(range) = psi_section_range(packet.payload) {
+                pmt_pids = parse_pat(&packet.payload[range]);
+            }
+        } else if packet.payload_unit_start && pmt_pids.contains(&pid) {
+            rewrite_pmt(&mut raw, &mut codecs);
+        }
+
+        if !raw_slot_open {
+            slots.push(Vec::with_capacity(TS_PACKET_SIZE));
+            raw_slot_open = true;
+        }
+        if let Some(slot) = slots.last_mut() {
+            slot.extend_from_slice(&raw);
+        }
+    }
+
+    for (pid, pes) in pending {
+        if let Some(&codec) = codecs.get(&pid) {
+            finish_pes(pid, pes, codec, key, iv, &mut counters, &mut slots);
+        }
+    }
+
+    Ok(slots.concat())
+}
+
+/// 解密一个完整的 PES 并重新打包到预留槽位
+fn finish_pes(
+    pid: u16,
+    pes: PendingPes,
+    codec: EncryptedCodec,
+    key: &[u8],
+    iv: &[u8; 16],
+    counters: &mut HashMap<u16, u8>,
+    slots: &mut [Vec<u8>],
+) {
+    let decrypted = decrypt_pes(&pes.data, codec, key, iv);
+    let continuity_counter = counters.get(&pid).copied().unwrap_or(0);
+    let mut out = Vec::with_capacity(decrypted.len() + TS_PACKET_SIZE);
+    let next = packetize_pes(
+        &mut out,
+        pid,
+        continuity_counter,
+        pes.adaptation.as_deref(),
+        &decrypted,
+    );
+    counters.insert(pid, next);
+    slots[pes.slot] = out;
+}
+
+/// 仅保留适配域中的 PCR/OPCR/拼接点等必要字段，丢弃原有填充
+fn essential_adaptation(adaptation: &[u8]) -> Vec<u8> {
+    let Some(&flags) = adaptation.first() else {
+        return Vec::new();
+    };
+    let mut length = 1;
+    if flags & 0x10 != 0 {
+        length += 6;
+    }
+    if flags & 0x08 != 0 {
+        length += 6;
+    }
+    if flags & 0x04 != 0 {
+        length += 1;
+    }
+    let mut essential = adaptation[..length.min(adaptation.len())].to_vec();
+    // 私有数据与扩展字段不再保留
+    essential[0] &= !0x03;
+    essential
+}
+
+/// 把 PMT 中的加密流类型替换为普通流类型，并记录需要解密的 PID
+fn rewrite_pmt(packet: &mut [u8], codecs: &mut HashMap<u16, EncryptedCodec>) {
+    let Some(parsed) = parse_packet(packet) else {
+        return;
+    };
+    let payload_offset = TS_PACKET_SIZE - parsed.payload.len();
+    let Some(range) = psi_section_range(parsed.payload) else {
+        return;
+    };
+
+    let section = &mut packet[payload_offset + range.start..payload_offset + range.end];
+    let mut changed = false;
+    for stream in parse_pmt(section) {
+        let (codec, clear_type) = match stream.stream_type {
+            STREAM_TYPE_H264_ENCRYPTED => (EncryptedCodec::H264, STREAM_TYPE_H264),
+            STREAM_TYPE_AAC_ENCRYPTED => (EncryptedCodec::Aac, STREAM_TYPE_AAC),
+            _ => continue,
+        };
+        codecs.insert(stream.pid, codec);
+        section[stream.type_offset] = clear_type;
+        changed = true;
+    }
+    if changed {
+        update_section_crc(section);
+    }
+}
+
+/// 解密 PES 负载，PES 头保持不变
+fn decrypt_pes(pes: &[u8], codec: EncryptedCodec, key: &[u8], iv: &[u8; 16]) -> Vec<u8> {
+    if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+        return pes.to_vec();
+    }
+    let header_length = 9 + usize::from(pes[8]);
+    if header_length > pes.len() {
+        return pes.to_vec();
+    }
+
+    let elementary = &pes[header_length..];
+    let decrypted = match codec {
+        EncryptedCodec::H264 => decrypt_h264(elementary, key, iv),
+        EncryptedCodec::Aac => decrypt_adts(elementary, key, iv),
+    };
+
+    let mut out = Vec::with_capacity(header_length + decrypted.len());
+    out.extend_from_slice(&pes[..header_length]);
+    out.extend_from_slice(&decrypted);
+
+    // 视频 NAL 长度可能变化，需要同步 PES_packet_length（0 表示不定长）
+    if u16::from_be_bytes([pes[4], pes[5]]) != 0 {
+        let length = u16::try_from(out.len() - 6).unwrap_or(0);
+        out[4..6].copy_from_slice(&length.to_be_bytes());
+    }
+    out
+}
+
+/// 解密 Annex B 格式的 H.264 基本流
+fn decrypt_h264(es: &[u8], key: &[u8], iv: &[u8; 16]) -> Vec<u8> {
+    let starts = find_start_codes(es);
+    let Some(&(first_prefix, _)) = starts.first() else {
+        return es.to_vec();
+    };
+
+    let mut out = Vec::with_capacity(es.len());
+    out.extend_from_slice(&es[..first_prefix]);
+
+    for (i, &(prefix, nal_start)) in starts.iter().enumerate() {
+        let nal_end = starts.get(i + 1).map_or(es.len(), |&(next, _)| next);
+        out.extend_from_slice(&es[prefix..nal_start]);
+
+        let unit = &es[nal_start..nal_end];
+        // 末尾的 0 属于下一个起始码前的填充，不属于 NAL 单元
+        let body_len = unit.iter().rposition(|&b| b != 0).map_or(0, |p| p + 1);
+        let (body, trailing) = unit.split_at(body_len);
+
+        let nal_type = body.first().map_or(0, |b| b & 0x1F);
+        if (nal_type == 1 || nal_type == 5) && body.len() > NAL_MIN_ENCRYPTED_LENGTH {
+            out.extend_from_slice(&decrypt_nal_unit(body, key, iv));
+        } else {
+            out.extend_from_slice(body);
+        }
+        out.extend_from_slice(trailing);
+    }
+
+    out
+}
+
+/// 返回每个起始码的 (起始码位置, NAL 数据位置)
+pub fn find_start_codes(es: &[u8]) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= es.len() {
+        if es[i] == 0 && es[i + 1] == 0 && es[i + 2] == 1 {
+            let prefix = if i > 0 && es[i - 1] == 0 { i - 1 } else { i };
+            starts.push((prefix, i + 3));
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    starts
+}
+
+/// 解密单个视频 NAL 单元：前 32 字节明文，之后每 16 字节密文跟随 144 字节明文
+fn decrypt_nal_unit(nal: &[u8], key: &[u8], iv: &[u8; 16]) -> Vec<u8> {
+    let mut raw = remove_emulation_prevention(nal);
+    let mut cipher = Aes128CbcDec::new(key.into(), iv.into());
+
+    let mut pos = NAL_CLEAR_LEADER;
+    while pos < raw.len() {
+        if raw.len() - pos > 16 {
+            cipher.decrypt_block_mut((&mut raw[pos..pos + 16]).into());
+            pos += 16;
+        }
+        pos += NAL_CLEAR_STRIDE.min(raw.len() - pos);
+    }
+
+    add_emulation_prevention(&raw)
+}
+
+/// 解密 ADTS AAC 帧：帧头后 16 字节明文，其后的完整块全部加密，尾部不足一块保持明文
+fn decrypt_adts(es: &[u8], key: &[u8], iv: &[u8; 16]) -> Vec<u8> {
+    let mut out = es.to_vec();
+    let mut pos = 0;
+
+    while pos + 7 <= out.len() {
+        if out[pos] != 0xFF || out[pos + 1] & 0xF0 != 0xF0 {
+            break;
+        }
+        let header_length = if out[pos + 1] & 0x01 != 0 { 7 } else { 9 };
+        let frame_length = (usize::from(out[pos + 3] & 0x03) << 11)
+            | (usize::from(out[pos + 4]) << 3)
+            | (usize::from(out[pos + 5]) >> 5);
+        if frame_length < header_length || pos + frame_length > out.len() {
+            break;
+        }
+
+        let clear = header_length + AUDIO_CLEAR_LEADER;
+        if frame_length > clear {
+            let blocks = (frame_length - clear) / 16;
+            let start = pos + clear;
+            let mut cipher = Aes128CbcDec::new(key.into(), iv.into());
+            for block in out[start..start + blocks * 16].chunks_exact_mut(16) {
+                cipher.decrypt_block_mut(block.into());
+            }
+        }
+
+        pos += frame_length;
+    }
+
+    out
+}
+
+/// 去除防竞争字节（00 00 03 -> 00 00）
+pub fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// 重新插入防竞争字节，避免解密后的数据中出现伪起始码
+fn add_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 64);
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte <= 0x03 {
+            out.push(0x03);
+            zeros = 0;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+
+    type Aes128CbcEnc = cbc::Encryptor<Aes128>;
+
+    /// FIPS-197 附录 C.1 的 AES-128 测试向量
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+    const PLAIN_BLOCK: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+    const CIPHER_BLOCK: [u8; 16] = [
+        0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5,
+        0x5a,
+    ];
+    const IV: [u8; 16] = [0; 16];
+
+    /// 不含 0 字节的 NAL 明文，避免防竞争字节干扰预期结果
+    fn nal(header: u8, len: usize) -> Vec<u8> {
+        let mut data = vec![header];
+        data.extend((1..len).map(|i| (i % 251) as u8 + 1));
+        data
+    }
+
+    /// 按 1:9 模式加密 NAL：前 32 字节明文，剩余超过 16 字节时才加密下一块
+    fn encrypt_nal(plain: &[u8]) -> Vec<u8> {
+        let mut raw = plain.to_vec();
+        let mut cipher = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        let mut pos = NAL_CLEAR_LEADER;
+        while raw.len().saturating_sub(pos) > 16 {
+            cipher.encrypt_block_mut((&mut raw[pos..pos + 16]).into());
+            pos += 16 + NAL_CLEAR_STRIDE;
+        }
+        add_emulation_prevention(&raw)
+    }
+
+    /// 7 字节帧头（无 CRC）的 ADTS 帧
+    fn adts_frame(payload: &[u8]) -> Vec<u8> {
+        let length = 7 + payload.len();
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            0x50,
+            0x80 | (length >> 11) as u8,
+            (length >> 3) as u8,
+            ((length & 0x07) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn adts_frame_known_answer() {
+        let tail = [0x11, 0x12, 0x13, 0x14, 0x15];
+        let mut encrypted = vec![0xAA; AUDIO_CLEAR_LEADER];
+        encrypted.extend_from_slice(&CIPHER_BLOCK);
+        encrypted.extend_from_slice(&tail);
+        let mut plain = vec![0xAA; AUDIO_CLEAR_LEADER];
+        plain.extend_from_slice(&PLAIN_BLOCK);
+        plain.extend_from_slice(&tail);
+
+        // 每帧重新使用 IV，两帧解密结果相同；帧头、明文前导与不足一块的尾部保持不变
+        let es = [adts_frame(&encrypted), adts_frame(&encrypted)].concat();
+        let expected = [adts_frame(&plain), adts_frame(&plain)].concat();
+        assert_eq!(decrypt_adts(&es, &KEY, &IV), expected);
+    }
+
+    #[test]
+    fn h264_nal_known_answer() {
+        // 首个加密块位于 32 字节明文前导之后
+        let mut slice = nal(0x65, 369);
+        slice[NAL_CLEAR_LEADER..NAL_CLEAR_LEADER + 16].copy_from_slice(&PLAIN_BLOCK);
+        let encrypted = encrypt_nal(&slice);
+        assert_eq!(
+            remove_emulation_prevention(&encrypted)[NAL_CLEAR_LEADER..NAL_CLEAR_LEADER + 16],
+            CIPHER_BLOCK
+        );
+        // 加密块位于 32、192、352：最后剩余 17 字节，仍会加密一块
+        let raw = remove_emulation_prevention(&encrypted);
+        assert_ne!(raw[352..368], slice[352..368]);
+        assert_eq!(raw[368], slice[368]);
+
+        let es = [&[0, 0, 0, 1][..], &encrypted].concat();
+        let expected = [&[0, 0, 0, 1][..], &slice].concat();
+        assert_eq!(decrypt_h264(&es, &KEY, &IV), expected);
+    }
+
+    #[test]
+    fn h264_trailing_block_of_exactly_16_bytes_stays_clear() {
+        // 加密块位于 32、192；192 之后恰好剩 16 字节，不加密
+        let slice = nal(0x41, 208);
+        let encrypted = encrypt_nal(&slice);
+        let raw = remove_emulation_prevention(&encrypted);
+        assert_eq!(raw[192..], slice[192..]);
+
+        // SPS 与不超过 48 字节的片段不加密，原样保留
+        let sps = nal(0x67, 60);
+        let short = nal(0x65, NAL_MIN_ENCRYPTED_LENGTH);
+        let es = [
+            &[0, 0, 0, 1][..],
+            &sps,
+            &[0, 0, 1],
+            &short,
+            &[0, 0, 1],
+            &encrypted,
+        ]
+        .concat();
+        let expected = [
+            &[0, 0, 0, 1][..],
+            &sps,
+            &[0, 0, 1],
+            &short,
+            &[0, 0, 1],
+            &slice,
+        ]
+        .concat();
+        assert_eq!(decrypt_h264(&es, &KEY, &IV), expected);
+    }
+}
@@ -1,93 +1,553 @@
 ﻿use crate::config::WRITE_BUFFER_SIZE;
+use crate::downloader::{
+    ContainerFormat, EncodePreset, InitSections, Mp4Stream, OutputFormat, OutputOptions, Period,
+    Remuxer, RenditionKind, RenditionTrack, StreamSender, TrimWindow, iso639_2,
+    remux::native_supports, remux_ts_to_mp4, split_periods,
+};
 use crate::error::{DownloadError, Result};
+use log::{info, warn};
+use crate::utils::ffmpeg::ffmpeg_command;
 use crate::utils::get_segment_filename;
-use std::path::Path;
-// 使用FFmpeg将TS转换为MP4
-use std::process::Command;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader};
 
-/// 合并所有视频片段
+/// 合并所有视频片段，并混流单独下载的音频/字幕轨道
+///
+/// 没有单独轨道、不转码的 MP4/M4A 输出默认使用内置转封装，只有内置转封装不支持时才调用FFmpeg。
+/// 包含 `EXT-X-DISCONTINUITY` 时每个时段按自己的时间基准转封装，再以连续的时间戳拼接；
+/// TS 输出直接拼接，保留各时段的原始时间戳。指定 `trim` 时由FFmpeg按输出时间裁剪。
+#[allow(clippy::too_many_arguments)]
 pub async fn merge_segments(
     download_dir: &Path,
     segments: &[m3u8_rs::MediaSegment],
+    init_sections: &InitSections,
+    container: ContainerFormat,
+    tracks: &[RenditionTrack],
+    output: &OutputOptions,
+    remuxer: Remuxer,
+    trim: Option<TrimWindow>,
     output_path: &Path,
 ) -> Result<()> {
-    // 先合并为临时TS文件
-    let temp_ts_path = download_dir.join("temp.ts");
-    let temp_file = fs::File::create(&temp_ts_path)
+    let temp_path = download_dir.join(format!("temp.{}", container.extension()));
+    let periods = split_periods(segments);
+    if periods.len() > 1 {
+        info!("播放列表包含 {} 个不连续时段，将分别转封装后拼接", periods.len());
+    }
+
+    let format = output.format;
+    let mut has_audio_track = false;
+    let tracks: Vec<RenditionTrack> = tracks
+        .iter()
+        .filter(|t| {
+            let supported = match t.kind {
+                RenditionKind::Subtitles => format.supports_subtitles(),
+                // 纯音频输出只保留第一条音轨
+                RenditionKind::Audio => !format.is_audio_only() || !has_audio_track,
+            };
+            if !supported {
+                warn!(
+                    "输出格式 {} 不支持该轨道，已跳过 {}",
+                    format.extension(),
+                    t.name
+                );
+            }
+            has_audio_track |= t.kind == RenditionKind::Audio;
+            supported
+        })
+        .cloned()
+        .collect();
+    if output.faststart && !format.is_mp4_family() {
+        warn!("输出格式 {} 不支持 faststart，已忽略", format.extension());
+    }
+
+    if trim.is_none() && is_plain_ts_copy(container, !tracks.is_empty(), output) {
+        merge_segments_to_temp(download_dir, segments, init_sections, &temp_path).await?;
+        return move_file(&temp_path, output_path).await;
+    }
+
+    let init_count = init_sections.maps.len();
+    let mut temp_merged = false;
+    if tracks.is_empty()
+        && trim.is_none()
+        && remuxer != Remuxer::Ffmpeg
+        && native_supports(container, init_count, periods.len(), output)
+    {
+        let offsets = merge_range_to_temp(
+            download_dir,
+            segments,
+            0..segments.len(),
+            init_sections,
+            &temp_path,
+        )
+        .await?;
+        temp_merged = true;
+        let boundaries: Vec<u64> = periods
+            .iter()
+            .skip(1)
+            .map(|p| offsets[p.first_segment])
+            .collect();
+        let result = match container {
+            ContainerFormat::MpegTs => {
+                remux_ts_to_mp4(&temp_path, output_path, output, &boundaries).await
+            }
+            // 只有一个初始化段时，拼接后的 fMP4 本身就是完整的分片 MP4，moov 已在文件开头
+            ContainerFormat::Fmp4 => move_file(&temp_path, output_path).await,
+        };
+        match result {
+            Ok(()) => {
+                let _ = fs::remove_file(&temp_path).await;
+                return Ok(());
+            }
+            Err(e) if remuxer == Remuxer::Native => return Err(e),
+            Err(e) => warn!("内置转封装失败，改用FFmpeg: {e}"),
+        }
+    } else if remuxer == Remuxer::Native
+        && let Some(e) = native_merge_error(
+            container,
+            init_count,
+            periods.len(),
+            !tracks.is_empty(),
+            output,
+            trim.is_some(),
+        )
+    {
+        return Err(e);
+    }
+
+    // 多个时段分别写入单独的文件，由 concat 分离器依次读取并调整为连续的时间戳
+    let (input, temp_files) = if periods.len() > 1 {
+        if temp_merged {
+            let _ = fs::remove_file(&temp_path).await;
+        }
+        let (list, files) =
+            write_period_files(download_dir, segments, init_sections, container, &periods).await?;
+        (MergeInput::Concat(list), files)
+    } else {
+        if !temp_merged {
+            merge_segments_to_temp(download_dir, segments, init_sections, &temp_path).await?;
+        }
+        (MergeInput::Single(temp_path.clone()), vec![temp_path])
+    };
+
+    let args = ffmpeg_merge_args(&input, container, &tracks, output, trim, output_path);
+    // 任务被取消时合并过程随之中断，FFmpeg 进程也一并结束
+    let output = ffmpeg_command()?
+        .args(&args)
+        .kill_on_drop(true)
+        .output()
         .await
-        .map_err(|e| DownloadError::file(&temp_ts_path, e.to_string()))?;
-    let mut writer = tokio::io::BufWriter::with_capacity(WRITE_BUFFER_SIZE, temp_file);
+        .map_err(|e| DownloadError::ffmpeg(format!("执行FFmpeg失败: {e}")))?;
 
-    for (index, segment) in segments.iter().enumerate() {
-        let segment_filename = get_segment_filename(&segment.uri, index);
-        let segment_path = download_dir.join(&segment_filename);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DownloadError::ffmpeg(format!("FFmpeg转换失败: {stderr}")));
+    }
 
-        if !segment_path.exists() {
-            return Err(DownloadError::file(
-                &segment_path,
-                format!("片段文件不存在: {}", segment_path.display()),
-            ));
+    // 清理临时文件
+    for file in temp_files {
+        let _ = fs::remove_file(file).await;
+    }
+
+    Ok(())
+}
+
+/// FFmpeg 合并的主输入
+enum MergeInput {
+    /// 合并后的临时文件
+    Single(PathBuf),
+    /// 按时段拆分的文件列表（concat 分离器格式）
+    Concat(PathBuf),
+}
+
+/// 每个时段合并为单独的文件（fMP4 时段以各自的初始化段开头），并生成 concat 列表
+///
+/// 返回列表路径与需要清理的全部文件。
+async fn write_period_files(
+    download_dir: &Path,
+    segments: &[m3u8_rs::MediaSegment],
+    init_sections: &InitSections,
+    container: ContainerFormat,
+    periods: &[Period],
+) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let mut list = String::new();
+    let mut files = Vec::with_capacity(periods.len() + 1);
+    for period in periods {
+        // 列表中的相对路径相对于列表文件所在目录
+        let file_name = format!("period_{}.{}", period.index, container.extension());
+        let path = download_dir.join(&file_name);
+        merge_range_to_temp(download_dir, segments, period.segments(), init_sections, &path)
+            .await?;
+        list.push_str(&format!("file '{file_name}'\n"));
+        files.push(path);
+    }
+
+    let list_path = download_dir.join("periods.txt");
+    fs::write(&list_path, list)
+        .await
+        .map_err(|e| DownloadError::file(&list_path, e.to_string()))?;
+    files.push(list_path.clone());
+    Ok((list_path, files))
+}
+
+/// 合并时是否一定需要FFmpeg，用于在下载片段前提前检查
+///
+/// `Auto` 模式下内置转封装失败后改用FFmpeg的情况不计入。
+pub fn merge_requires_ffmpeg(
+    container: ContainerFormat,
+    init_count: usize,
+    period_count: usize,
+    has_tracks: bool,
+    output: &OutputOptions,
+    remuxer: Remuxer,
+    trim: bool,
+) -> bool {
+    if remuxer == Remuxer::Native || (!trim && is_plain_ts_copy(container, has_tracks, output)) {
+        return false;
+    }
+    trim || has_tracks
+        || remuxer == Remuxer::Ffmpeg
+        || !native_supports(container, init_count, period_count, output)
+}
+
+/// 只使用内置转封装（`Remuxer::Native`）时无法完成合并的原因，可以合并时返回 `None`
+///
+/// 下载片段前用它提前失败，合并时返回同样的错误。
+pub fn native_merge_error(
+    container: ContainerFormat,
+    init_count: usize,
+    period_count: usize,
+    has_tracks: bool,
+    output: &OutputOptions,
+    trim: bool,
+) -> Option<DownloadError> {
+    if !trim
+        && (is_plain_ts_copy(container, has_tracks, output)
+            || (!has_tracks && native_supports(container, init_count, period_count, output)))
+    {
+        return None;
+    }
+    Some(DownloadError::remux(if trim {
+        "精确裁剪需要使用FFmpeg"
+    } else if container == ContainerFormat::Fmp4 && (init_count > 1 || period_count > 1) {
+        "包含多个初始化段或不连续点的fMP4需要使用FFmpeg转封装"
+    } else {
+        "内置转封装只支持输出不含单独音频/字幕轨道、不转码的MP4/M4A"
+    }))
+}
+
+/// TS 片段直接拼接即为完整的 TS 文件，无需FFmpeg（不写入标题）
+fn is_plain_ts_copy(container: ContainerFormat, has_tracks: bool, output: &OutputOptions) -> bool {
+    output.format == OutputFormat::Ts
+        && output.preset == EncodePreset::Copy
+        && container == ContainerFormat::MpegTs
+        && !has_tracks
+}
+
+/// 移动文件，跨文件系统时改为复制后删除
+async fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).await.is_err() {
+        fs::copy(from, to)
+            .await
+            .map_err(|e| DownloadError::file(to, e.to_string()))?;
+        let _ = fs::remove_file(from).await;
+    }
+    Ok(())
+}
+
+/// 构造合并用的FFmpeg参数
+///
+/// 有单独的音频轨道时只保留主流中的视频；纯音频输出只保留一条音轨。
+/// 字幕转为输出格式支持的编码，并按轨道写入语言与名称。
+fn ffmpeg_merge_args(
+    input: &MergeInput,
+    container: ContainerFormat,
+    tracks: &[RenditionTrack],
+    output: &OutputOptions,
+    trim: Option<TrimWindow>,
+    output_path: &Path,
+) -> Vec<String> {
+    let format = output.format;
+    let mut args: Vec<String> = match input {
+        MergeInput::Single(path) => vec![
+            "-f".into(),
+            container.ffmpeg_format().into(),
+            "-i".into(),
+            path.display().to_string(),
+        ],
+        MergeInput::Concat(list) => vec![
+            "-f".into(),
+            "concat".into(),
+            "-safe".into(),
+            "0".into(),
+            "-i".into(),
+            list.display().to_string(),
+        ],
+    };
+    for track in tracks {
+        args.extend([
+            "-f".into(),
+            track.ffmpeg_format.into(),
+            "-i".into(),
+            track.path.display().to_string(),
+        ]);
+    }
+
+    let has_audio_track = tracks.iter().any(|t| t.kind == RenditionKind::Audio);
+    if format.is_audio_only() {
+        let input = if has_audio_track { "1:a:0" } else { "0:a:0" };
+        args.extend(["-map".into(), input.into()]);
+    } else if !tracks.is_empty() {
+        args.extend(["-map".into(), "0:v?".into()]);
+        if !has_audio_track {
+            args.extend(["-map".into(), "0:a?".into()]);
+        }
+        for (i, track) in tracks.iter().enumerate() {
+            args.extend([
+                "-map".into(),
+                format!("{}:{}", i + 1, track.kind.stream_specifier()),
+            ]);
+        }
+    }
+
+    // 作为输出选项按解码后的时间裁剪；不转码时开头可能从关键帧之前的画面开始
+    if let Some(trim) = trim {
+        args.extend(["-ss".into(), format!("{:.3}", trim.offset)]);
+        if let Some(duration) = trim.duration {
+            args.extend(["-t".into(), format!("{duration:.3}")]);
+        }
+    }
+
+    args.extend(["-c".into(), "copy".into()]);
+    if !format.is_audio_only() {
+        args.extend(output.preset.video_args().iter().map(|a| (*a).to_string()));
+    }
+    let audio_args: &[&str] = if format == OutputFormat::Mp3 {
+        &["-c:a", "libmp3lame", "-q:a", "2"]
+    } else {
+        output.preset.audio_args()
+    };
+    args.extend(audio_args.iter().map(|a| (*a).to_string()));
+    if tracks.iter().any(|t| t.kind == RenditionKind::Subtitles) {
+        let codec = if format == OutputFormat::Mkv {
+            "srt"
+        } else {
+            "mov_text"
+        };
+        args.extend(["-c:s".into(), codec.into()]);
+    }
+    // 只有直接复制到 MP4/M4A 的音频需要把 ADTS 转换为 ASC
+    if format.is_mp4_family()
+        && audio_args.is_empty()
+        && (!container.audio_bitstream_filter().is_empty()
+            || tracks.iter().any(RenditionTrack::needs_adts_conversion))
+    {
+        args.extend(["-bsf:a".into(), "aac_adtstoasc".into()]);
+    }
+
+    for kind in [RenditionKind::Audio, RenditionKind::Subtitles] {
+        let spec = kind.stream_specifier();
+        for (k, track) in tracks.iter().filter(|t| t.kind == kind).enumerate() {
+            let language = iso639_2(track.language.as_deref().unwrap_or(""));
+            args.extend([
+                format!("-metadata:s:{spec}:{k}"),
+                format!("language={language}"),
+                format!("-metadata:s:{spec}:{k}"),
+                format!("title={}", track.name),
+            ]);
         }
+    }
+    if has_audio_track {
+        args.extend(["-disposition:a:0".into(), "default".into()]);
+    }
+    if let Some(title) = &output.title {
+        args.extend(["-metadata".into(), format!("title={title}")]);
+    }
+    if output.faststart && format.is_mp4_family() {
+        args.extend(["-movflags".into(), "+faststart".into()]);
+    }
 
+    args.extend([
+        "-f".into(),
+        format.ffmpeg_muxer().into(),
+        "-y".into(),
+        output_path.display().to_string(),
+    ]);
+    args
+}
+
+/// 合并单独下载的音频/字幕轨道片段
+///
+/// WebVTT 只保留第一个片段的文件头；独立 AAC/MP3 等音频去掉每个片段开头的 ID3 标签；
+/// 其余格式与视频片段的合并方式相同。
+pub async fn merge_track_segments(
+    download_dir: &Path,
+    segments: &[m3u8_rs::MediaSegment],
+    init_sections: &InitSections,
+    ffmpeg_format: &str,
+    output_path: &Path,
+) -> Result<()> {
+    if matches!(ffmpeg_format, "mpegts" | "mp4") {
+        return merge_segments_to_temp(download_dir, segments, init_sections, output_path).await;
+    }
+
+    if ffmpeg_format == "webvtt" {
+        return merge_webvtt_segments(download_dir, segments, output_path).await;
+    }
+
+    let output = fs::File::create(output_path)
+        .await
+        .map_err(|e| DownloadError::file(output_path, e.to_string()))?;
+    let mut writer = tokio::io::BufWriter::with_capacity(WRITE_BUFFER_SIZE, output);
+    for segment in segments {
+        let segment_path = download_dir.join(get_segment_filename(segment));
         let mut segment_file = fs::File::open(&segment_path)
             .await
             .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
-        let mut buffer = Vec::new();
-        segment_file
-            .read_to_end(&mut buffer)
+
+        // 只读取文件头判断 ID3 标签长度，其余内容直接复制
+        let mut header = [0u8; ID3_HEADER_LEN];
+        let header_len = read_up_to(&mut segment_file, &mut header)
             .await
             .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
-        writer
-            .write_all(&buffer)
-            .await
-            .map_err(|e| DownloadError::file(&temp_ts_path, e.to_string()))?;
+        let tag_len = id3_tag_len(&header[..header_len]);
+        if tag_len == 0 {
+            writer
+                .write_all(&header[..header_len])
+                .await
+                .map_err(|e| DownloadError::file(output_path, e.to_string()))?;
+        } else {
+            segment_file
+                .seek(SeekFrom::Start(tag_len as u64))
+                .await
+                .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
+        }
+        copy_segment(segment_file, &mut writer, &segment_path).await?;
     }
 
     writer
         .flush()
         .await
-        .map_err(|e| DownloadError::file(&temp_ts_path, e.to_string()))?;
-
-    let output = Command::new("ffmpeg")
-        .args([
-            "-i",
-            temp_ts_path.to_str().unwrap(),
-            "-c",
-            "copy",
-            "-bsf:a",
-            "aac_adtstoasc",
-            "-y",
-            output_path.to_str().unwrap(),
-        ])
-        .output()
-        .map_err(|e| DownloadError::ffmpeg(format!("执行FFmpeg失败: {e}")))?;
+        .map_err(|e| DownloadError::file(output_path, e.to_string()))
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(DownloadError::ffmpeg(format!("FFmpeg转换失败: {stderr}")));
+/// 合并 WebVTT 字幕片段，只保留第一个片段的文件头
+async fn merge_webvtt_segments(
+    download_dir: &Path,
+    segments: &[m3u8_rs::MediaSegment],
+    output_path: &Path,
+) -> Result<()> {
+    let mut merged = String::new();
+    for segment in segments {
+        let segment_path = download_dir.join(get_segment_filename(segment));
+        let data = fs::read(&segment_path)
+            .await
+            .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
+
+        let text = String::from_utf8_lossy(&data);
+        let text = text.trim_start_matches('\u{feff}');
+        if merged.is_empty() {
+            merged.push_str(text);
+        } else {
+            // 跳过 WEBVTT 文件头（第一个空行之前的部分）
+            let body = text
+                .replace("\r\n", "\n")
+                .split_once("\n\n")
+                .map(|(_, body)| body.to_string())
+                .unwrap_or_default();
+            merged.push_str("\n\n");
+            merged.push_str(&body);
+        }
     }
 
-    // 清理临时文件
-    let _ = fs::remove_file(&temp_ts_path).await;
+    fs::write(output_path, merged)
+        .await
+        .map_err(|e| DownloadError::file(output_path, e.to_string()))
+}
 
-    Ok(())
+/// ID3v2 标签头长度
+const ID3_HEADER_LEN: usize = 10;
+
+/// 尽量读满 `buf`，文件较短时返回实际读取的长度
+async fn read_up_to(file: &mut fs::File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = file.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
 }
 
-/// 合并所有TS片段到临时文件
-pub async fn merge_segments_to_temp_ts(
+/// 数据开头 ID3v2 标签的总长度（独立音频片段用于携带时间戳），没有标签时为 0
+fn id3_tag_len(header: &[u8]) -> usize {
+    if header.len() < ID3_HEADER_LEN || &header[..3] != b"ID3" {
+        return 0;
+    }
+    // 标签大小为 synchsafe 整数，每字节只使用低7位
+    let size = header[6..10]
+        .iter()
+        .fold(0usize, |acc, b| (acc << 7) | usize::from(b & 0x7f));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    ID3_HEADER_LEN + size + footer
+}
+
+/// 合并所有片段到临时文件，fMP4 片段前会写入对应的初始化段
+pub async fn merge_segments_to_temp(
     download_dir: &Path,
     segments: &[m3u8_rs::MediaSegment],
-    temp_ts_path: &Path,
+    init_sections: &InitSections,
+    temp_path: &Path,
 ) -> Result<()> {
-    let temp_file = fs::File::create(temp_ts_path)
+    merge_range_to_temp(
+        download_dir,
+        segments,
+        0..segments.len(),
+        init_sections,
+        temp_path,
+    )
+    .await
+    .map(|_| ())
+}
+
+/// 合并 `range` 内的片段，返回每个片段在输出中的起始位置
+///
+/// 第一个片段之前总是写入其初始化段，使输出可以单独解析。
+async fn merge_range_to_temp(
+    download_dir: &Path,
+    segments: &[m3u8_rs::MediaSegment],
+    range: Range<usize>,
+    init_sections: &InitSections,
+    temp_path: &Path,
+) -> Result<Vec<u64>> {
+    let temp_file = fs::File::create(temp_path)
         .await
-        .map_err(|e| DownloadError::file(temp_ts_path, e.to_string()))?;
+        .map_err(|e| DownloadError::file(temp_path, e.to_string()))?;
     let mut writer = tokio::io::BufWriter::with_capacity(WRITE_BUFFER_SIZE, temp_file);
+    let mut offsets = Vec::with_capacity(range.len());
+    let mut written = 0u64;
+
+    let first = range.start;
+    for (index, segment) in segments.iter().enumerate().take(range.end).skip(first) {
+        offsets.push(written);
+        let init = if index == first {
+            init_sections.init_for(index)
+        } else {
+            init_sections.init_before(index)
+        };
+        if let Some(init) = init {
+            written += init.len() as u64;
+            writer
+                .write_all(init)
+                .await
+                .map_err(|e| DownloadError::file(temp_path, e.to_string()))?;
+        }
 
-    for (index, segment) in segments.iter().enumerate() {
-        let segment_filename = get_segment_filename(&segment.uri, index);
+        let segment_filename = get_segment_filename(segment);
         let segment_path = download_dir.join(&segment_filename);
 
         if !segment_path.exists() {
@@ -97,96 +557,54 @@ pub async fn merge_segments_to_temp_ts(
             ));
         }
 
-        let mut segment_file = fs::File::open(&segment_path)
+        let segment_file = fs::File::open(&segment_path)
             .await
             .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
-        let mut buffer = Vec::new();
-        segment_file
-            .read_to_end(&mut buffer)
-            .await
-            .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
-        writer
-            .write_all(&buffer)
-            .await
-            .map_err(|e| DownloadError::file(temp_ts_path, e.to_string()))?;
+        written += copy_segment(segment_file, &mut writer, &segment_path).await?;
     }
 
     writer
         .flush()
         .await
-        .map_err(|e| DownloadError::file(temp_ts_path, e.to_string()))?;
+        .map_err(|e| DownloadError::file(temp_path, e.to_string()))?;
 
-    Ok(())
+    Ok(offsets)
 }
 
-/// 通过ffmpeg pipe将TS转为MP4并流式输出
+/// 以固定大小的缓冲区将片段文件追加到输出，不把整个片段读入内存，返回复制的字节数
+async fn copy_segment<W: AsyncWrite + Unpin>(
+    segment_file: fs::File,
+    writer: &mut W,
+    segment_path: &Path,
+) -> Result<u64> {
+    let mut reader = BufReader::with_capacity(WRITE_BUFFER_SIZE, segment_file);
+    tokio::io::copy_buf(&mut reader, writer)
+        .await
+        .map_err(|e| DownloadError::file(segment_path, format!("合并片段失败: {e}")))
+}
+
+/// 将合并后的临时文件转为分片MP4并流式输出
 pub async fn merge_to_mp4_stream(
-    temp_ts_path: &Path,
-    tx: &tokio::sync::mpsc::Sender<std::result::Result<bytes::Bytes, String>>,
+    temp_path: &Path,
+    container: ContainerFormat,
+    init_count: usize,
+    remuxer: Remuxer,
+    tx: &StreamSender,
 ) -> Result<()> {
-    use std::process::Stdio;
-    use tokio::io::AsyncReadExt;
-    use tokio::process::Command;
-
-    let mut child = Command::new("ffmpeg")
-        .args([
-            "-nostdin",
-            "-i",
-            temp_ts_path.to_str().unwrap(),
-            "-c",
-            "copy",
-            "-bsf:a",
-            "aac_adtstoasc",
-            "-movflags",
-            "frag_keyframe+empty_moov+default_base_moof",
-            "-f",
-            "mp4",
-            "pipe:1",
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| DownloadError::ffmpeg(format!("启动FFmpeg失败: {e}")))?;
-
-    let mut stdout = child
-        .stdout
-        .take()
-        .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准输出".to_string()))?;
-    let mut stderr = child
-        .stderr
-        .take()
-        .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准错误输出".to_string()))?;
-    let mut buf = vec![0u8; 65536];
-    let stderr_task = tokio::spawn(async move {
-        let mut stderr_buf = Vec::new();
-        let _ = stderr.read_to_end(&mut stderr_buf).await;
-        stderr_buf
-    });
-
+    let mut stream = Mp4Stream::new(remuxer, container, init_count, tx.clone())?;
+    let mut file = fs::File::open(temp_path)
+        .await
+        .map_err(|e| DownloadError::file(temp_path, e.to_string()))?;
+    let mut buf = vec![0u8; WRITE_BUFFER_SIZE];
     loop {
-        let n = stdout
+        let n = file
             .read(&mut buf)
             .await
-            .map_err(|e| DownloadError::ffmpeg(format!("读取FFmpeg输出失败: {e}")))?;
+            .map_err(|e| DownloadError::file(temp_path, e.to_string()))?;
         if n == 0 {
             break;
         }
-        let _ = tx.send(Ok(bytes::Bytes::copy_from_slice(&buf[..n]))).await;
-    }
-
-    let status = child
-        .wait()
-        .await
-        .map_err(|e| DownloadError::ffmpeg(format!("等待FFmpeg失败: {e}")))?;
-    let stderr_output = stderr_task.await.unwrap_or_default();
-
-    if !status.success() {
-        let stderr_text = String::from_utf8_lossy(&stderr_output).trim().to_string();
-        if stderr_text.is_empty() {
-            return Err(DownloadError::ffmpeg("FFmpeg转换失败".to_string()));
-        }
-        return Err(DownloadError::ffmpeg(format!("FFmpeg转换失败: {stderr_text}")));
+        stream.write(&buf[..n]).await?;
     }
-
-    Ok(())
+    stream.finish().await
 }
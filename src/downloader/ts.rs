@@ -0,0 +1,279 @@
+//! MPEG-TS 包与 PSI 表的基础解析
+
+use crate::config::{TS_PACKET_SIZE, TS_SYNC_BYTE};
+use std::collections::HashMap;
+
+/// PAT 的 PID
+pub const PAT_PID: u16 = 0x0000;
+/// 空包的 PID
+pub const NULL_PID: u16 = 0x1FFF;
+
+/// MPEG-1 音频流类型
+pub const STREAM_TYPE_MPEG1_AUDIO: u8 = 0x03;
+/// MPEG-2 音频流类型
+pub const STREAM_TYPE_MPEG2_AUDIO: u8 = 0x04;
+/// 私有数据段流类型
+pub const STREAM_TYPE_PRIVATE_SECTION: u8 = 0x05;
+/// ADTS AAC 流类型
+pub const STREAM_TYPE_AAC: u8 = 0x0F;
+/// ID3 时间元数据流类型
+pub const STREAM_TYPE_METADATA: u8 = 0x15;
+/// H.264 流类型
+pub const STREAM_TYPE_H264: u8 = 0x1B;
+/// H.265 流类型
+pub const STREAM_TYPE_H265: u8 = 0x24;
+/// SCTE-35 广告插入信令流类型
+pub const STREAM_TYPE_SCTE35: u8 = 0x86;
+
+/// 解析后的单个 TS 包
+pub struct TsPacket<'a> {
+    pub pid: u16,
+    pub payload_unit_start: bool,
+    pub continuity_counter: u8,
+    /// 适配域内容（不含长度字节）
+    pub adaptation: Option<&'a [u8]>,
+    pub payload: &'a [u8],
+}
+
+/// 解析一个 188 字节的 TS 包，同步字节或长度不正确时返回 `None`
+pub fn parse_packet(buf: &[u8]) -> Option<TsPacket<'_>> {
+    if buf.len() != TS_PACKET_SIZE || buf[0] != TS_SYNC_BYTE {
+        return None;
+    }
+
+    let pid = (u16::from(buf[1] & 0x1F) << 8) | u16::from(buf[2]);
+    let payload_unit_start = buf[1] & 0x40 != 0;
+    let adaptation_control = (buf[3] >> 4) & 0x03;
+    let continuity_counter = buf[3] & 0x0F;
+
+    let mut offset = 4;
+    let mut adaptation = None;
+    if adaptation_control & 0x02 != 0 {
+        let length = usize::from(buf[4]);
+        if 5 + length > TS_PACKET_SIZE {
+            return None;
+        }
+        adaptation = Some(&buf[5..5 + length]);
+        offset = 5 + length;
+    }
+
+    let payload = if adaptation_control & 0x01 != 0 {
+        &buf[offset..]
+    } else {
+        &[][..]
+    };
+
+    Some(TsPacket {
+        pid,
+        payload_unit_start,
+        continuity_counter,
+        adaptation,
+        payload,
+    })
+}
+
+/// 按 PID 检查连续计数器
+///
+/// 带负载的包依次加1（模16），允许重复发送一次；适配域中带不连续标志时重新开始计数。
+#[derive(Debug, Default)]
+pub struct ContinuityCheck {
+    /// PID -> (上一个计数器, 是否已重复)
+    last: HashMap<u16, (u8, bool)>,
+    pub packets: usize,
+    pub errors: usize,
+}
+
+impl ContinuityCheck {
+    pub fn push(&mut self, packet: &TsPacket<'_>) {
+        self.packets += 1;
+        if packet.pid == NULL_PID || packet.payload.is_empty() {
+            return;
+        }
+        let counter = packet.continuity_counter;
+        let discontinuity = packet
+            .adaptation
+            .and_then(|a| a.first())
+            .is_some_and(|flags| flags & 0x80 != 0);
+        let Some((last, repeated)) = self.last.get_mut(&packet.pid) else {
+            self.last.insert(packet.pid, (counter, false));
+            return;
+        };
+        if discontinuity || counter == (*last + 1) & 0x0F {
+            *repeated = false;
+        } else if counter == *last && !*repeated {
+            *repeated = true;
+        } else {
+            self.errors += 1;
+            *repeated = false;
+        }
+        *last = counter;
+    }
+}
+
+/// 写入一个 TS 包，不足 184 字节的部分用适配域填充
+///
+/// `adaptation` 为适配域内容（不含长度字节），`payload` 必须能放入剩余空间。
+pub fn write_packet(
+    out: &mut Vec<u8>,
+    pid: u16,
+    payload_unit_start: bool,
+    continuity_counter: u8,
+    adaptation: Option<&[u8]>,
+    payload: &[u8],
+) {
+    let adaptation = adaptation.filter(|a| !a.is_empty());
+    let header_room = TS_PACKET_SIZE - 4;
+    let adaptation_len = adaptation.map_or(0, |a| a.len() + 1);
+    let stuffing = header_room - adaptation_len - payload.len();
+    let has_adaptation = adaptation.is_some() || stuffing > 0;
+
+    let mut control = 0x10;
+    if has_adaptation {
+        control |= 0x20;
+    }
+
+    out.push(TS_SYNC_BYTE);
+    out.push(((pid >> 8) as u8 & 0x1F) | if payload_unit_start { 0x40 } else { 0 });
+    out.push((pid & 0xFF) as u8);
+    out.push(control | (continuity_counter & 0x0F));
+
+    match adaptation {
+        Some(content) => {
+            out.push((content.len() + stuffing) as u8);
+            out.extend_from_slice(content);
+            out.extend(std::iter::repeat_n(0xFF, stuffing));
+        }
+        None if stuffing == 1 => out.push(0),
+        None if stuffing > 1 => {
+            out.push((stuffing - 1) as u8);
+            out.push(0x00);
+            out.extend(std::iter::repeat_n(0xFF, stuffing - 2));
+        }
+        None => {}
+    }
+
+    out.extend_from_slice(payload);
+}
+
+/// 将 PES 数据重新打包为连续的 TS 包，返回下一个连续计数器
+///
+/// 第一个包携带 `adaptation`（例如 PCR），其余包只在末尾填充时才带适配域。
+pub fn packetize_pes(
+    out: &mut Vec<u8>,
+    pid: u16,
+    mut continuity_counter: u8,
+    adaptation: Option<&[u8]>,
+    pes: &[u8],
+) -> u8 {
+    let adaptation = adaptation.filter(|a| !a.is_empty());
+    let mut remaining = pes;
+    let mut first = true;
+
+    while first || !remaining.is_empty() {
+        let adaptation = if first { adaptation } else { None };
+        let capacity = TS_PACKET_SIZE - 4 - adaptation.map_or(0, |a| a.len() + 1);
+        let take = remaining.len().min(capacity);
+        write_packet(
+            out,
+            pid,
+            first,
+            continuity_counter,
+            adaptation,
+            &remaining[..take],
+        );
+        remaining = &remaining[take..];
+        continuity_counter = (continuity_counter + 1) & 0x0F;
+        first = false;
+    }
+
+    continuity_counter
+}
+
+/// 返回 PSI 段（从 `table_id` 开始，含 CRC）在负载中的范围
+pub fn psi_section_range(payload: &[u8]) -> Option<std::ops::Range<usize>> {
+    let pointer = usize::from(*payload.first()?);
+    let start = 1 + pointer;
+    if payload.len() < start + 3 {
+        return None;
+    }
+    let section_length =
+        (usize::from(payload[start + 1] & 0x0F) << 8) | usize::from(payload[start + 2]);
+    let end = start + 3 + section_length;
+    (end <= payload.len() && section_length >= 9).then_some(start..end)
+}
+
+/// 从 PAT 段中提取所有 PMT 的 PID
+pub fn parse_pat(section: &[u8]) -> Vec<u16> {
+    let mut pids = Vec::new();
+    if section.len() < 12 {
+        return pids;
+    }
+    let mut offset = 8;
+    while offset + 4 <= section.len() - 4 {
+        let program_number = u16::from_be_bytes([section[offset], section[offset + 1]]);
+        let pid = (u16::from(section[offset + 2] & 0x1F) << 8) | u16::from(section[offset + 3]);
+        if program_number != 0 {
+            pids.push(pid);
+        }
+        offset += 4;
+    }
+    pids
+}
+
+/// PMT 中的一路基本流
+pub struct PmtStream {
+    pub stream_type: u8,
+    pub pid: u16,
+    /// `stream_type` 字节在段内的偏移
+    pub type_offset: usize,
+}
+
+/// 解析 PMT 段中的基本流列表
+pub fn parse_pmt(section: &[u8]) -> Vec<PmtStream> {
+    let mut streams = Vec::new();
+    if section.len() < 16 {
+        return streams;
+    }
+    let program_info_length = (usize::from(section[10] & 0x0F) << 8) | usize::from(section[11]);
+    let mut offset = 12 + program_info_length;
+    let end = section.len() - 4;
+    while offset + 5 <= end {
+        let stream_type = section[offset];
+        let pid = (u16::from(section[offset + 1] & 0x1F) << 8) | u16::from(section[offset + 2]);
+        let es_info_length =
+            (usize::from(section[offset + 3] & 0x0F) << 8) | usize::from(section[offset + 4]);
+        streams.push(PmtStream {
+            stream_type,
+            pid,
+            type_offset: offset,
+        });
+        offset += 5 + es_info_length;
+    }
+    streams
+}
+
+/// 重新计算 PSI 段末尾的 CRC32
+pub fn update_section_crc(section: &mut [u8]) {
+    let len = section.len();
+    if len < 4 {
+        return;
+    }
+    let crc = crc32_mpeg2(&section[..len - 4]);
+    section[len - 4..].copy_from_slice(&crc.to_be_bytes());
+}
+
+/// MPEG-2 CRC32（多项式 0x04C11DB7，不反转）
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,242 @@
+//! 主播放列表的子流选择
+
+use crate::error::{DownloadError, Result};
+use log::warn;
+use m3u8_rs::VariantStream;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// 子流选择策略
+///
+/// 字符串形式用于命令行与 JSON：`highest`、`lowest`、`max-height=720`、
+/// `max-bandwidth=3000000`、`index=2`。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum VariantPolicy {
+    /// 最高带宽
+    #[default]
+    Highest,
+    /// 最低带宽
+    Lowest,
+    /// 分辨率高度不超过指定值的最高带宽流
+    MaxHeight(u64),
+    /// 带宽不超过指定值（bit/s）的最高带宽流
+    MaxBandwidth(u64),
+    /// 按主播放列表中的顺序（从0开始）直接指定
+    Index(usize),
+}
+
+impl FromStr for VariantPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let value = s.trim().to_ascii_lowercase();
+        let parse_number = |v: &str| {
+            v.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("子流选择参数无效: {s} - {e}"))
+        };
+
+        match value.split_once('=') {
+            None if value == "highest" || value == "best" => Ok(Self::Highest),
+            None if value == "lowest" || value == "worst" => Ok(Self::Lowest),
+            None if value.ends_with('p') => {
+                parse_number(&value[..value.len() - 1]).map(Self::MaxHeight)
+            }
+            Some(("max-height", v)) => parse_number(v).map(Self::MaxHeight),
+            Some(("max-bandwidth", v)) => parse_number(v).map(Self::MaxBandwidth),
+            Some(("index", v)) => v
+                .trim()
+                .parse::<usize>()
+                .map(Self::Index)
+                .map_err(|e| format!("子流选择参数无效: {s} - {e}")),
+            _ => Err(format!(
+                "未知的子流选择策略: {s}（可选 highest、lowest、max-height=N、max-bandwidth=N、index=N）"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for VariantPolicy {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<VariantPolicy> for String {
+    fn from(policy: VariantPolicy) -> Self {
+        policy.to_string()
+    }
+}
+
+impl fmt::Display for VariantPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Highest => write!(f, "highest"),
+            Self::Lowest => write!(f, "lowest"),
+            Self::MaxHeight(height) => write!(f, "max-height={height}"),
+            Self::MaxBandwidth(bandwidth) => write!(f, "max-bandwidth={bandwidth}"),
+            Self::Index(index) => write!(f, "index={index}"),
+        }
+    }
+}
+
+/// 子流信息，供接口展示与选择
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantInfo {
+    pub index: usize,
+    pub uri: String,
+    pub bandwidth: u64,
+    pub average_bandwidth: Option<u64>,
+    pub resolution: Option<String>,
+    pub height: Option<u64>,
+    pub frame_rate: Option<f64>,
+    pub codecs: Option<String>,
+    pub audio: Option<String>,
+    pub subtitles: Option<String>,
+}
+
+impl VariantInfo {
+    pub fn from_variant(index: usize, variant: &VariantStream) -> Self {
+        Self {
+            index,
+            uri: variant.uri.clone(),
+            bandwidth: variant.bandwidth,
+            average_bandwidth: variant.average_bandwidth,
+            resolution: variant
+                .resolution
+                .map(|r| format!("{}x{}", r.width, r.height)),
+            height: variant.resolution.map(|r| r.height),
+            frame_rate: variant.frame_rate,
+            codecs: variant.codecs.clone(),
+            audio: variant.audio.clone(),
+            subtitles: variant.subtitles.clone(),
+        }
+    }
+}
+
+/// 列出主播放列表中可下载的子流（跳过 I 帧流）
+pub fn list_variants(variants: &[VariantStream]) -> Vec<VariantInfo> {
+    variants
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| !v.is_i_frame)
+        .map(|(i, v)| VariantInfo::from_variant(i, v))
+        .collect()
+}
+
+/// 子流的 CODECS 是否包含指定编码（按前缀匹配，如 `avc1`、`hvc1`）
+fn matches_codec(variant: &VariantStream, codec: &str) -> bool {
+    variant.codecs.as_deref().is_some_and(|codecs| {
+        codecs
+            .split(',')
+            .any(|c| c.trim().to_ascii_lowercase().starts_with(codec))
+    })
+}
+
+/// 按策略与编码偏好选择子流
+///
+/// `index` 策略忽略编码偏好；编码偏好没有匹配项时忽略该偏好；`max-height`/`max-bandwidth`
+/// 没有满足上限的流时选择最小的一路。
+pub fn select_variant<'a>(
+    variants: &'a [VariantStream],
+    policy: VariantPolicy,
+    codec: Option<&str>,
+) -> Result<&'a VariantStream> {
+    let mut candidates: Vec<&VariantStream> = variants.iter().filter(|v| !v.is_i_frame).collect();
+    if let Some(codec) = codec.map(str::to_ascii_lowercase) {
+        let matched: Vec<&VariantStream> = candidates
+            .iter()
+            .copied()
+            .filter(|v| matches_codec(v, &codec))
+            .collect();
+        if matched.is_empty() {
+            warn!("没有编码为 {codec} 的子流，忽略编码偏好");
+        } else {
+            candidates = matched;
+        }
+    }
+
+    let height = |v: &&VariantStream| v.resolution.map_or(0, |r| r.height);
+    let selected = match policy {
+        VariantPolicy::Highest => candidates.iter().copied().max_by_key(|v| v.bandwidth),
+        VariantPolicy::Lowest => candidates.iter().copied().min_by_key(|v| v.bandwidth),
+        VariantPolicy::MaxHeight(max) => candidates
+            .iter()
+            .copied()
+            .filter(|v| v.resolution.is_some_and(|r| r.height <= max))
+            .max_by_key(|v| (height(v), v.bandwidth))
+            .or_else(|| {
+                candidates
+                    .iter()
+                    .copied()
+                    .min_by_key(|v| (height(v), v.bandwidth))
+            }),
+        VariantPolicy::MaxBandwidth(max) => candidates
+            .iter()
+            .copied()
+            .filter(|v| v.bandwidth <= max)
+            .max_by_key(|v| v.bandwidth)
+            .or_else(|| candidates.iter().copied().min_by_key(|v| v.bandwidth)),
+        VariantPolicy::Index(index) => {
+            let variant = variants.get(index).ok_or_else(|| {
+                DownloadError::validation(
+                    "variant",
+                    format!("子流序号 {index} 超出范围（共 {} 个）", variants.len()),
+                )
+            })?;
+            // I 帧子流只含关键帧，不能作为正常播放的子流下载
+            if variant.is_i_frame {
+                return Err(DownloadError::validation(
+                    "variant",
+                    format!("子流序号 {index} 是 I 帧子流，无法下载"),
+                ));
+            }
+            return Ok(variant);
+        }
+    };
+
+    selected.ok_or_else(|| DownloadError::parse("主播放列表中没有可用的子流"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(uri: &str, bandwidth: u64, is_i_frame: bool) -> VariantStream {
+        VariantStream {
+            uri: uri.to_string(),
+            bandwidth,
+            is_i_frame,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn index_rejects_i_frame_variant() {
+        let variants = [
+            variant("low.m3u8", 500_000, false),
+            variant("iframe.m3u8", 100_000, true),
+        ];
+        let selected = select_variant(&variants, VariantPolicy::Index(0), None).unwrap();
+        assert_eq!(selected.uri, "low.m3u8");
+        assert!(select_variant(&variants, VariantPolicy::Index(1), None).is_err());
+        assert!(select_variant(&variants, VariantPolicy::Index(2), None).is_err());
+    }
+
+    #[test]
+    fn bandwidth_policies_skip_i_frame_variants() {
+        let variants = [
+            variant("low.m3u8", 500_000, false),
+            variant("high.m3u8", 2_000_000, false),
+            variant("iframe.m3u8", 100_000, true),
+        ];
+        let lowest = select_variant(&variants, VariantPolicy::Lowest, None).unwrap();
+        assert_eq!(lowest.uri, "low.m3u8");
+        let highest = select_variant(&variants, VariantPolicy::Highest, None).unwrap();
+        assert_eq!(highest.uri, "high.m3u8");
+    }
+}
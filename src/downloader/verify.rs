@@ -0,0 +1,848 @@
+//! 合并后的完整性校验：比较输出时长与 EXTINF 之和，检查时间戳间断并定位缺失的片段
+
+use crate::config::{
+    DEFAULT_VERIFY_TOLERANCE, TS_PACKET_SIZE, VERIFY_GAP_SECONDS, VERIFY_MIN_SLACK_SECONDS,
+    WRITE_BUFFER_SIZE,
+};
+use crate::downloader::demux::{MPEG_TIMESCALE, read_timestamp, unwrap_timestamp};
+use crate::downloader::mp4::read_box_header;
+use crate::downloader::ts::parse_packet;
+use crate::downloader::{OutputFormat, TrimWindow};
+use crate::error::{DownloadError, Result};
+use crate::utils::ffmpeg::ffmpeg_command;
+use m3u8_rs::MediaSegment;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// 校验方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyMode {
+    /// 不校验
+    Off,
+    /// 校验并记录结果，不通过时只给出警告（默认）
+    #[default]
+    Warn,
+    /// 校验不通过时任务失败
+    Strict,
+}
+
+/// 完整性校验选项
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, clap::Args)]
+pub struct VerifyOptions {
+    /// 合并后的完整性校验：off、warn（默认，只记录）、strict（不通过时任务失败）
+    #[arg(long = "verify", value_enum, default_value_t = VerifyMode::Warn)]
+    #[serde(default, rename = "verify")]
+    pub mode: VerifyMode,
+    /// 允许的时长误差比例（0.02 表示 2%）
+    #[arg(long, default_value_t = DEFAULT_VERIFY_TOLERANCE)]
+    #[serde(default = "default_tolerance")]
+    pub verify_tolerance: f64,
+}
+
+const fn default_tolerance() -> f64 {
+    DEFAULT_VERIFY_TOLERANCE
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            mode: VerifyMode::default(),
+            verify_tolerance: DEFAULT_VERIFY_TOLERANCE,
+        }
+    }
+}
+
+/// 输出中时间戳不连续的位置（秒，相对于输出开头）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeGap {
+    pub start: f64,
+    pub duration: f64,
+}
+
+/// 完整性校验结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerificationReport {
+    /// 按 EXTINF 计算的预期时长（秒）
+    pub expected_duration: f64,
+    /// 输出文件的实际时长，无法读取时为 `None`
+    pub actual_duration: Option<f64>,
+    pub gaps: Vec<TimeGap>,
+    /// 落在间断处或输出结尾之后的片段（原播放列表中的序号）
+    pub missing_segments: Vec<usize>,
+    pub passed: bool,
+    /// 不通过的原因（含无法读取输出）
+    pub message: Option<String>,
+}
+
+/// 输出文件中主轨道（优先视频）的时间线
+#[derive(Debug, Default)]
+struct OutputTimeline {
+    /// 有内容的时长，不含间断
+    duration: f64,
+    gaps: Vec<TimeGap>,
+}
+
+impl OutputTimeline {
+    /// 输出结尾的时间（含间断）
+    fn end(&self) -> f64 {
+        self.duration + self.gaps.iter().map(|g| g.duration).sum::<f64>()
+    }
+}
+
+/// 校验合并后的输出
+///
+/// `segments` 为实际合并的片段，`indices` 为它们在原播放列表中的序号；
+/// `allow_jumps` 时（保留原始时间戳的多时段 TS 输出）不把时间戳跳变视为间断。
+pub async fn verify_output(
+    output_path: &Path,
+    format: OutputFormat,
+    segments: &[MediaSegment],
+    indices: &[usize],
+    trim: Option<TrimWindow>,
+    allow_jumps: bool,
+    options: &VerifyOptions,
+) -> VerificationReport {
+    let (spans, expected_duration) = expected_spans(segments, indices, trim);
+    let path = output_path.to_path_buf();
+    let timeline = match format {
+        OutputFormat::Ts | OutputFormat::Mp4 | OutputFormat::M4a => {
+            tokio::task::spawn_blocking(move || probe_native(&path, format, allow_jumps))
+                .await
+                .unwrap_or_else(|e| Err(DownloadError::Unknown(e.to_string())))
+        }
+        OutputFormat::Mkv | OutputFormat::Mp3 => probe_ffmpeg(&path).await,
+    };
+    match timeline {
+        Ok(timeline) => compare_timeline(&spans, expected_duration, timeline, options),
+        // 无法读取的输出可能已损坏或被截断，不能视为通过
+        Err(e) => VerificationReport {
+            expected_duration,
+            actual_duration: None,
+            gaps: Vec::new(),
+            missing_segments: Vec::new(),
+            passed: false,
+            message: Some(format!("无法读取输出时长: {e}")),
+        },
+    }
+}
+
+/// 按 EXTINF 计算每个片段在输出中的预期位置（原序号, 开始, 结束）与预期总时长
+fn expected_spans(
+    segments: &[MediaSegment],
+    indices: &[usize],
+    trim: Option<TrimWindow>,
+) -> (Vec<(usize, f64, f64)>, f64) {
+    let mut spans = Vec::with_capacity(segments.len());
+    let mut position = -trim.map_or(0.0, |t| t.offset);
+    for (segment, &index) in segments.iter().zip(indices) {
+        let duration = f64::from(segment.duration);
+        spans.push((index, position, position + duration));
+        position += duration;
+    }
+    let expected_duration = match trim.and_then(|t| t.duration) {
+        Some(duration) => duration.min(position),
+        None => position,
+    }
+    .max(0.0);
+    (spans, expected_duration)
+}
+
+/// 比较输出时间线与各片段的预期位置 `spans`（原序号, 开始, 结束）
+fn compare_timeline(
+    spans: &[(usize, f64, f64)],
+    expected_duration: f64,
+    timeline: OutputTimeline,
+    options: &VerifyOptions,
+) -> VerificationReport {
+    let slack = (expected_duration * options.verify_tolerance).max(VERIFY_MIN_SLACK_SECONDS);
+    let missing_segments: Vec<usize> = spans
+        .iter()
+        .filter(|&&(_, start, end)| {
+            let half = (end - start) / 2.0;
+            start >= timeline.end() + slack
+                || timeline.gaps.iter().any(|gap| {
+                    let overlap =
+                        (end.min(gap.start + gap.duration) - start.max(gap.start)).max(0.0);
+                    overlap > 0.0 && overlap >= half.min(gap.duration / 2.0)
+                })
+        })
+        .map(|&(index, _, _)| index)
+        .collect();
+
+    let difference = timeline.duration - expected_duration;
+    let mut problems = Vec::new();
+    if difference.abs() > slack {
+        problems.push(format!(
+            "实际时长 {:.1}s 与预期 {expected_duration:.1}s 相差 {difference:+.1}s",
+            timeline.duration
+        ));
+    }
+    if !timeline.gaps.is_empty() {
+        let total: f64 = timeline.gaps.iter().map(|g| g.duration).sum();
+        problems.push(format!(
+            "{} 处时间戳间断，共 {total:.1}s",
+            timeline.gaps.len()
+        ));
+    }
+
+    VerificationReport {
+        expected_duration,
+        actual_duration: Some(timeline.duration),
+        gaps: timeline.gaps,
+        missing_segments,
+        passed: problems.is_empty(),
+        message: (!problems.is_empty()).then(|| problems.join("；")),
+    }
+}
+
+fn probe_native(path: &Path, format: OutputFormat, allow_jumps: bool) -> Result<OutputTimeline> {
+    let file = File::open(path).map_err(|e| DownloadError::file(path, e.to_string()))?;
+    if format == OutputFormat::Ts {
+        probe_ts(
+            BufReader::with_capacity(WRITE_BUFFER_SIZE, file),
+            allow_jumps,
+        )
+        .map_err(|e| DownloadError::file(path, e.to_string()))
+    } else {
+        probe_mp4(file).map_err(|e| DownloadError::file(path, e.to_string()))
+    }
+}
+
+/// 单条轨道按解码顺序排列的时间戳（秒）
+#[derive(Debug, Default)]
+struct TrackScan {
+    video: bool,
+    samples: usize,
+    last: Option<f64>,
+    last_delta: f64,
+    timeline: OutputTimeline,
+}
+
+impl TrackScan {
+    /// 加入下一个样本的解码时间：正常间隔计入时长，过大的间隔记为间断，
+    /// 时间戳回退（或 `allow_jumps` 时的跳变）视为新的时段，不计入时长
+    fn push(&mut self, time: f64, allow_jumps: bool) {
+        self.samples += 1;
+        if let Some(last) = self.last.replace(time) {
+            let delta = time - last;
+            if (0.0..=VERIFY_GAP_SECONDS).contains(&delta) {
+                self.timeline.duration += delta;
+                self.last_delta = delta;
+            } else {
+                // 间断处按正常样本时长计入，保证之后的位置与输出时间一致
+                self.timeline.duration += self.last_delta;
+                if delta > 0.0 && !allow_jumps {
+                    self.timeline.gaps.push(TimeGap {
+                        start: self.timeline.end(),
+                        duration: delta - self.last_delta,
+                    });
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> OutputTimeline {
+        // 最后一个样本的时长按前一个间隔估计
+        self.timeline.duration += self.last_delta;
+        self.timeline
+    }
+}
+
+/// 按 PES 头中的时间戳扫描 TS 文件，优先使用视频轨道
+fn probe_ts(mut reader: impl Read, allow_jumps: bool) -> std::io::Result<OutputTimeline> {
+    let mut tracks: HashMap<u16, TrackScan> = HashMap::new();
+    let mut raw_last: HashMap<u16, i64> = HashMap::new();
+    let mut buf = vec![0u8; TS_PACKET_SIZE * (WRITE_BUFFER_SIZE / TS_PACKET_SIZE)];
+    let mut filled = 0;
+    loop {
+        let n = reader.read(&mut buf[filled..])?;
+        filled += n;
+        let usable = filled - filled % TS_PACKET_SIZE;
+        for chunk in buf[..usable].chunks_exact(TS_PACKET_SIZE) {
+            let Some(packet) = parse_packet(chunk) else {
+                continue;
+            };
+            let pes = packet.payload;
+            if !packet.payload_unit_start || pes.len() < 14 || pes[..3] != [0, 0, 1] {
+                continue;
+            }
+            let stream_id = pes[3];
+            let video = (0xE0..=0xEF).contains(&stream_id);
+            if !video && !(0xC0..=0xDF).contains(&stream_id) {
+                continue;
+            }
+            let flags = pes[7] >> 6;
+            if flags & 0x02 == 0 {
+                continue;
+            }
+            let raw = if flags == 0x03 && pes.len() >= 19 {
+                read_timestamp(&pes[14..19])
+            } else {
+                read_timestamp(&pes[9..14])
+            };
+            let last = raw_last.entry(packet.pid).or_insert(raw);
+            let unwrapped = unwrap_timestamp(raw, *last);
+            *last = unwrapped;
+            #[allow(clippy::cast_precision_loss)]
+            let time = unwrapped as f64 / f64::from(MPEG_TIMESCALE);
+            let track = tracks.entry(packet.pid).or_default();
+            track.video |= video;
+            track.push(time, allow_jumps);
+        }
+        buf.copy_within(usable..filled, 0);
+        filled -= usable;
+        if n == 0 {
+            break;
+        }
+    }
+    tracks
+        .into_values()
+        .max_by_key(|t| (t.video, t.samples))
+        .map(TrackScan::finish)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "没有找到音视频时间戳"))
+}
+
+/// 依次返回 `data` 中的子 box（类型, 内容）
+fn child_boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 8)?;
+        let kind: [u8; 4] = header[4..8].try_into().ok()?;
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().ok()?) {
+            0 => (8, data.len() - pos),
+            1 => {
+                let large = data.get(pos + 8..pos + 16)?;
+                (
+                    16,
+                    usize::try_from(u64::from_be_bytes(large.try_into().ok()?)).ok()?,
+                )
+            }
+            size => (8, size as usize),
+        };
+        if size < header_len || pos + size > data.len() {
+            return None;
+        }
+        let content = &data[pos + header_len..pos + size];
+        pos += size;
+        Some((kind, content))
+    })
+}
+
+fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    child_boxes(data).find(|(k, _)| k == kind).map(|(_, c)| c)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// MP4 中的一条轨道
+#[derive(Debug, Default)]
+struct Mp4Track {
+    video: bool,
+    timescale: u32,
+    /// `stts` 中的 (样本数, 样本时长)
+    stts: Vec<(u32, u32)>,
+    /// `trex` 中的默认样本时长
+    default_duration: u32,
+    /// 分片中的 (基准解码时间, 样本时长)
+    fragments: Vec<(Option<u64>, Vec<u32>)>,
+    /// 编辑列表从媒体开头跳过的时长（秒）
+    edit_start: f64,
+    /// 编辑列表呈现的时长（秒），没有编辑列表或时长为 0（分片 MP4）时为 `None`
+    edit_duration: Option<f64>,
+}
+
+impl Mp4Track {
+    fn parse(trak: &[u8], movie_timescale: u32) -> Option<(u32, Self)> {
+        let tkhd = find_box(trak, b"tkhd")?;
+        let track_id = read_u32(tkhd, if tkhd.first()? == &1 { 20 } else { 12 })?;
+        let mdia = find_box(trak, b"mdia")?;
+        let mdhd = find_box(mdia, b"mdhd")?;
+        let timescale = read_u32(mdhd, if mdhd.first()? == &1 { 20 } else { 12 })?;
+        let video = find_box(mdia, b"hdlr").and_then(|h| h.get(8..12)) == Some(b"vide");
+        let stts = find_box(mdia, b"minf")
+            .and_then(|minf| find_box(minf, b"stbl"))
+            .and_then(|stbl| find_box(stbl, b"stts"))
+            .map(|stts| {
+                let count = read_u32(stts, 4).unwrap_or(0) as usize;
+                (0..count)
+                    .map_while(|i| Some((read_u32(stts, 8 + i * 8)?, read_u32(stts, 12 + i * 8)?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let (edit_start, edit_duration) = find_box(trak, b"edts")
+            .and_then(|edts| find_box(edts, b"elst"))
+            .map_or((0.0, None), |elst| {
+                parse_edit_list(elst, movie_timescale, timescale)
+            });
+        Some((
+            track_id,
+            Self {
+                video,
+                timescale,
+                stts,
+                edit_start,
+                edit_duration,
+                ..Self::default()
+            },
+        ))
+    }
+
+    /// 读取一个 `traf` 中的样本时长
+    fn push_fragment(&mut self, traf: &[u8]) {
+        let Some(tfhd) = find_box(traf, b"tfhd") else {
+            return;
+        };
+        let tfhd_flags = read_u32(tfhd, 0).unwrap_or(0) & 0x00FF_FFFF;
+        // tfhd 中可选字段依次为 base-data-offset、sample-description-index、默认样本时长
+        let mut offset = 8;
+        if tfhd_flags & 0x01 != 0 {
+            offset += 8;
+        }
+        if tfhd_flags & 0x02 != 0 {
+            offset += 4;
+        }
+        let default_duration = if tfhd_flags & 0x08 != 0 {
+            read_u32(tfhd, offset).unwrap_or(self.default_duration)
+        } else {
+            self.default_duration
+        };
+        let base_time = find_box(traf, b"tfdt").and_then(|tfdt| {
+            if tfdt.first()? == &1 {
+                read_u64(tfdt, 4)
+            } else {
+                read_u32(tfdt, 4).map(u64::from)
+            }
+        });
+
+        let mut durations = Vec::new();
+        for (_, trun) in child_boxes(traf).filter(|(k, _)| k == b"trun") {
+            let flags = read_u32(trun, 0).unwrap_or(0) & 0x00FF_FFFF;
+            let count = read_u32(trun, 4).unwrap_or(0) as usize;
+            let mut pos = 8;
+            if flags & 0x01 != 0 {
+                pos += 4;
+            }
+            if flags & 0x04 != 0 {
+                pos += 4;
+            }
+            let fields = [0x100, 0x200, 0x400, 0x800]
+                .iter()
+                .filter(|&&f| flags & f != 0)
+                .count();
+            for _ in 0..count {
+                let duration = if flags & 0x100 != 0 {
+                    read_u32(trun, pos)
+                } else {
+                    Some(default_duration)
+                };
+                let Some(duration) = duration else {
+                    break;
+                };
+                durations.push(duration);
+                pos += fields * 4;
+            }
+        }
+        self.fragments.push((base_time, durations));
+    }
+
+    fn timeline(&self) -> OutputTimeline {
+        let timescale = f64::from(self.timescale.max(1));
+        let mut scan = TrackScan::default();
+        let mut time = 0u64;
+        for &(count, delta) in &self.stts {
+            for _ in 0..count {
+                #[allow(clippy::cast_precision_loss)]
+                scan.push(time as f64 / timescale, false);
+                time += u64::from(delta);
+            }
+        }
+        for (base_time, durations) in &self.fragments {
+            if let Some(base_time) = base_time {
+                time = *base_time;
+            }
+            for &duration in durations {
+                #[allow(clippy::cast_precision_loss)]
+                scan.push(time as f64 / timescale, false);
+                time += u64::from(duration);
+            }
+        }
+        let mut timeline = scan.finish();
+        if self.edit_start > 0.0 {
+            timeline.duration = (timeline.duration - self.edit_start).max(0.0);
+            for gap in &mut timeline.gaps {
+                gap.start = (gap.start - self.edit_start).max(0.0);
+            }
+        }
+        if let Some(limit) = self.edit_duration {
+            timeline.duration = timeline.duration.min(limit);
+        }
+        timeline
+    }
+}
+
+/// 读取 `elst`，返回 (跳过的媒体时长, 呈现时长)，单位为秒
+///
+/// 只处理播放器实际使用的情形：开头的空白编辑（延迟开始）不计入时长，
+/// 跳过的时长取第一个非空编辑的 `media_time`，呈现时长为各非空编辑时长之和。
+fn parse_edit_list(elst: &[u8], movie_timescale: u32, timescale: u32) -> (f64, Option<f64>) {
+    let long = elst.first() == Some(&1);
+    let entry_len = if long { 20 } else { 12 };
+    let count = read_u32(elst, 4).unwrap_or(0) as usize;
+    let mut media_start = None;
+    let mut presented = 0u64;
+    for i in 0..count {
+        let pos = 8 + i * entry_len;
+        let entry = if long {
+            read_u64(elst, pos).zip(read_u64(elst, pos + 8).map(|t| t as i64))
+        } else {
+            read_u32(elst, pos)
+                .map(u64::from)
+                .zip(read_u32(elst, pos + 4).map(|t| i64::from(t as i32)))
+        };
+        let Some((duration, media_time)) = entry else {
+            break;
+        };
+        if media_time < 0 {
+            continue;
+        }
+        media_start.get_or_insert(media_time);
+        presented += duration;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let start = media_start.unwrap_or(0) as f64 / f64::from(timescale.max(1));
+    #[allow(clippy::cast_precision_loss)]
+    let duration = (presented > 0).then(|| presented as f64 / f64::from(movie_timescale.max(1)));
+    (start, duration)
+}
+
+/// 读取 MP4 的 `moov`（普通 MP4 的 `stts`）与各 `moof`（分片 MP4 的 `trun`），优先使用视频轨道
+///
+/// 时长按 `elst` 去掉开头跳过的媒体（如 B 帧的显示偏移）并限定为呈现时长。
+fn probe_mp4(file: File) -> std::io::Result<OutputTimeline> {
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::with_capacity(WRITE_BUFFER_SIZE, file);
+    let mut tracks: HashMap<u32, Mp4Track> = HashMap::new();
+    let mut position = 0;
+    while let Some((kind, size)) = read_box_header(&mut reader, file_len - position)? {
+        if &kind == b"moov" || &kind == b"moof" {
+            reader.seek(SeekFrom::Start(position))?;
+            let mut data = vec![0u8; usize::try_from(size).unwrap_or(usize::MAX)];
+            reader.read_exact(&mut data)?;
+            let content = child_boxes(&data).next().map_or(&[][..], |(_, c)| c);
+            if &kind == b"moov" {
+                let movie_timescale = find_box(content, b"mvhd")
+                    .and_then(|mvhd| read_u32(mvhd, if mvhd.first()? == &1 { 20 } else { 12 }))
+                    .unwrap_or(0);
+                for (_, trak) in child_boxes(content).filter(|(k, _)| k == b"trak") {
+                    if let Some((id, track)) = Mp4Track::parse(trak, movie_timescale) {
+                        tracks.insert(id, track);
+                    }
+                }
+                let mvex = find_box(content, b"mvex").unwrap_or_default();
+                for (_, trex) in child_boxes(mvex).filter(|(k, _)| k == b"trex") {
+                    if let (Some(id), Some(duration)) = (read_u32(trex, 4), read_u32(trex, 12))
+                        && let Some(track) = tracks.get_mut(&id)
+                    {
+                        track.default_duration = duration;
+                    }
+                }
+            } else {
+                for (_, traf) in child_boxes(content).filter(|(k, _)| k == b"traf") {
+                    let id = find_box(traf, b"tfhd").and_then(|tfhd| read_u32(tfhd, 4));
+                    if let Some(track) = id.and_then(|id| tracks.get_mut(&id)) {
+                        track.push_fragment(traf);
+                    }
+                }
+            }
+        }
+        position += size;
+        reader.seek(SeekFrom::Start(position))?;
+    }
+    tracks
+        .values()
+        .max_by_key(|t| (t.video, t.stts.len() + t.fragments.len()))
+        .map(Mp4Track::timeline)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "没有找到 moov"))
+}
+
+/// 其他格式读取 FFmpeg 报告的时长（不检查间断）
+async fn probe_ffmpeg(path: &Path) -> Result<OutputTimeline> {
+    let output = ffmpeg_command()?
+        .args(["-hide_banner", "-i"])
+        .arg(path)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| DownloadError::ffmpeg(format!("执行FFmpeg失败: {e}")))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let duration = stderr
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Duration:"))
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|value| {
+            value.trim().split(':').try_fold(0.0, |total, part| {
+                part.parse::<f64>().ok().map(|v| total * 60.0 + v)
+            })
+        })
+        .ok_or_else(|| DownloadError::ffmpeg("FFmpeg 没有报告输出时长"))?;
+    Ok(OutputTimeline {
+        duration,
+        gaps: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::ts::packetize_pes;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+    }
+
+    /// 每个片段 10 秒时的预期位置
+    fn spans(count: usize) -> Vec<(usize, f64, f64)> {
+        (0..count)
+            .map(|i| (i, i as f64 * 10.0, (i + 1) as f64 * 10.0))
+            .collect()
+    }
+
+    fn timeline(duration: f64, gaps: &[(f64, f64)]) -> OutputTimeline {
+        OutputTimeline {
+            duration,
+            gaps: gaps
+                .iter()
+                .map(|&(start, duration)| TimeGap { start, duration })
+                .collect(),
+        }
+    }
+
+    fn segments(durations: &[f32]) -> Vec<MediaSegment> {
+        durations
+            .iter()
+            .map(|&duration| MediaSegment {
+                duration,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expected_duration_sums_extinf() {
+        let segments = segments(&[10.0, 10.0, 6.5]);
+        let (spans, expected) = expected_spans(&segments, &[0, 2, 5], None);
+        assert_close(expected, 26.5);
+        assert_eq!(spans, [(0, 0.0, 10.0), (2, 10.0, 20.0), (5, 20.0, 26.5)]);
+
+        // 精确裁剪时按裁剪窗口计算，位置相对于裁剪后的开头
+        let trim = TrimWindow {
+            offset: 5.0,
+            duration: Some(15.0),
+        };
+        let (spans, expected) = expected_spans(&segments, &[0, 2, 5], Some(trim));
+        assert_close(expected, 15.0);
+        assert_eq!(spans[0], (0, -5.0, 5.0));
+        let trim = TrimWindow {
+            offset: 5.0,
+            duration: None,
+        };
+        assert_close(expected_spans(&segments, &[0, 2, 5], Some(trim)).1, 21.5);
+    }
+
+    #[test]
+    fn probed_duration_is_compared_with_extinf_sum() {
+        let segments = segments(&[10.0, 10.0, 10.0]);
+        let (spans, expected) = expected_spans(&segments, &[0, 1, 2], None);
+        let options = VerifyOptions::default();
+        let report = compare_timeline(&spans, expected, timeline(29.4, &[]), &options);
+        assert!(report.passed, "{report:?}");
+        assert_eq!(report.expected_duration, 30.0);
+        let report = compare_timeline(&spans, expected, timeline(25.0, &[]), &options);
+        assert!(!report.passed);
+        assert!(report.message.unwrap().contains("-5.0s"));
+    }
+
+    #[test]
+    fn duration_within_tolerance_passes() {
+        let options = VerifyOptions::default();
+        // 100 秒的 2% 为 2 秒
+        let report = compare_timeline(&spans(10), 100.0, timeline(98.5, &[]), &options);
+        assert!(report.passed, "{report:?}");
+        assert_eq!(report.actual_duration, Some(98.5));
+        let report = compare_timeline(&spans(10), 100.0, timeline(97.0, &[]), &options);
+        assert!(!report.passed);
+        assert!(report.message.unwrap().contains("-3.0s"));
+        // 时长较短时至少允许 1 秒误差
+        let report = compare_timeline(&spans(1), 10.0, timeline(9.2, &[]), &options);
+        assert!(report.passed, "{report:?}");
+    }
+
+    #[test]
+    fn short_output_reports_trailing_segments() {
+        let report = compare_timeline(
+            &spans(3),
+            30.0,
+            timeline(18.0, &[]),
+            &VerifyOptions::default(),
+        );
+        assert!(!report.passed);
+        assert_eq!(report.missing_segments, [2]);
+    }
+
+    #[test]
+    fn gap_reports_covered_segments() {
+        let report = compare_timeline(
+            &spans(3),
+            30.0,
+            timeline(20.0, &[(10.0, 10.0)]),
+            &VerifyOptions::default(),
+        );
+        assert!(!report.passed);
+        assert_eq!(report.missing_segments, [1]);
+        assert_eq!(report.gaps.len(), 1);
+    }
+
+    fn pes_timestamp(ts: i64) -> [u8; 5] {
+        [
+            0x20 | (((ts >> 30) & 0x07) as u8) << 1 | 1,
+            (ts >> 22) as u8,
+            (((ts >> 15) & 0x7F) as u8) << 1 | 1,
+            (ts >> 7) as u8,
+            ((ts & 0x7F) as u8) << 1 | 1,
+        ]
+    }
+
+    /// 只带 PTS 的视频 PES 组成的 TS
+    fn video_ts(timestamps: impl IntoIterator<Item = i64>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut cc = 0;
+        for pts in timestamps {
+            let mut pes = vec![0, 0, 1, 0xE0, 0, 0, 0x80, 0x80, 5];
+            pes.extend(pes_timestamp(pts));
+            pes.extend_from_slice(&[0, 0, 0, 1, 0x09, 0xF0]);
+            cc = packetize_pes(&mut out, 0x100, cc, None, &pes);
+        }
+        out
+    }
+
+    #[test]
+    fn ts_probe_detects_gaps() {
+        // 25 帧（1 秒），之后跳过 4 秒再有 25 帧
+        let times = (0..25).chain(125..150).map(|i| 90_000 + i * 3600);
+        let ts = video_ts(times);
+        let scanned = probe_ts(ts.as_slice(), false).unwrap();
+        assert_close(scanned.duration, 2.0);
+        assert_eq!(scanned.gaps.len(), 1);
+        assert_close(scanned.gaps[0].start, 1.0);
+        assert_close(scanned.gaps[0].duration, 4.0);
+
+        // 多时段 TS 保留原始时间戳，跳变不算间断
+        let scanned = probe_ts(ts.as_slice(), true).unwrap();
+        assert_close(scanned.duration, 2.0);
+        assert!(scanned.gaps.is_empty());
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    fn mp4_box(kind: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+        let content = children.concat();
+        let mut out = words(&[(content.len() + 8) as u32]);
+        out.extend_from_slice(kind);
+        out.extend(content);
+        out
+    }
+
+    fn handler(kind: &[u8; 4]) -> Vec<u8> {
+        mp4_box(
+            b"hdlr",
+            &[words(&[0, 0, u32::from_be_bytes(*kind), 0, 0, 0])],
+        )
+    }
+
+    /// 90kHz 视频轨道，`stts` 为空时用于分片 MP4
+    fn video_trak(stts: &[u32], elst: Option<&[u32]>) -> Vec<u8> {
+        let mut children = vec![mp4_box(b"tkhd", &[words(&[0, 0, 0, 1, 0])])];
+        if let Some(entries) = elst {
+            let count = (entries.len() / 3) as u32;
+            let elst = mp4_box(b"elst", &[words(&[0, count]), words(entries)]);
+            children.push(mp4_box(b"edts", &[elst]));
+        }
+        let stts = mp4_box(
+            b"stts",
+            &[words(&[0, (stts.len() / 2) as u32]), words(stts)],
+        );
+        let minf = mp4_box(b"minf", &[mp4_box(b"stbl", &[stts])]);
+        let mdhd = mp4_box(b"mdhd", &[words(&[0, 0, 0, 90_000, 0, 0])]);
+        children.push(mp4_box(b"mdia", &[mdhd, handler(b"vide"), minf]));
+        mp4_box(b"trak", &children)
+    }
+
+    fn moov(children: &[Vec<u8>]) -> Vec<u8> {
+        let mvhd = mp4_box(b"mvhd", &[words(&[0, 0, 0, 1000, 0])]);
+        mp4_box(b"moov", &[&[mvhd][..], children].concat())
+    }
+
+    fn probe_mp4_bytes(name: &str, data: &[u8]) -> OutputTimeline {
+        let path =
+            std::env::temp_dir().join(format!("m3u8_verify_{}_{name}.mp4", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let result = probe_mp4(File::open(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+        result.unwrap()
+    }
+
+    #[test]
+    fn mp4_probe_reads_stts() {
+        // 100 个 0.04 秒的样本
+        let mp4 = moov(&[video_trak(&[100, 3600], None)]);
+        assert_close(probe_mp4_bytes("stts", &mp4).duration, 4.0);
+    }
+
+    #[test]
+    fn mp4_probe_applies_edit_list() {
+        // 去掉开头 0.08 秒的显示偏移，呈现时长与样本时长一致
+        let elst = [3920, 7200, 0x0001_0000];
+        let mp4 = moov(&[video_trak(&[100, 3600], Some(&elst))]);
+        assert_close(probe_mp4_bytes("offset", &mp4).duration, 3.92);
+
+        // 空白编辑不计入时长；从 1 秒处开始只呈现 2 秒
+        let elst = [500, u32::MAX, 0x0001_0000, 2000, 90_000, 0x0001_0000];
+        let mp4 = moov(&[video_trak(&[100, 3600], Some(&elst))]);
+        assert_close(probe_mp4_bytes("trimmed", &mp4).duration, 2.0);
+    }
+
+    #[test]
+    fn fragmented_mp4_probe_detects_gaps() {
+        let trex = mp4_box(b"trex", &[words(&[0, 1, 1, 3600, 0, 0])]);
+        let init = moov(&[video_trak(&[], None), mp4_box(b"mvex", &[trex])]);
+        // 每个分片 25 个样本（1 秒），第二个分片从 5 秒开始
+        let fragment = |base: u32| {
+            let tfhd = mp4_box(b"tfhd", &[words(&[0, 1])]);
+            let tfdt = mp4_box(b"tfdt", &[words(&[0, base])]);
+            let trun = mp4_box(b"trun", &[words(&[0, 25])]);
+            mp4_box(b"moof", &[mp4_box(b"traf", &[tfhd, tfdt, trun])])
+        };
+        let mp4 = [init, fragment(0), fragment(450_000)].concat();
+        let scanned = probe_mp4_bytes("fragmented", &mp4);
+        assert_close(scanned.duration, 2.0);
+        assert_eq!(scanned.gaps.len(), 1);
+        assert_close(scanned.gaps[0].start, 1.0);
+        assert_close(scanned.gaps[0].duration, 4.0);
+    }
+}
@@ -14,9 +14,12 @@
 //! - `TaskError`: 任务执行错误
 //! - `KeyError`: 密钥错误
 //! - `FfmpegError`: `FFmpeg` 执行错误
+//! - `RemuxError`: 内置转封装错误
+//! - `VerificationError`: 输出完整性校验不通过
 //! - `Unknown`: 未知错误
 //! - `UrlValidationError`: URL 验证错误
 //! - `ValidationError`: 配置验证错误
+//! - `Cancelled`: 任务被暂停或取消
 //!
 //! # 示例
 //!
@@ -98,6 +101,17 @@ pub enum DownloadError {
     #[error("FFmpeg执行失败: {error}")]
     FfmpegError { error: String },
 
+    /// 内置转封装错误
+    ///
+    /// 当内置的 TS 解复用或 MP4 封装遇到不支持或无法解析的数据时产生。
+    #[error("转封装失败: {reason}")]
+    RemuxError { reason: String },
+    /// 完整性校验错误
+    ///
+    /// 当合并后的输出时长与播放列表不符或存在时间戳间断、且要求严格校验时产生。
+    #[error("完整性校验未通过: {reason}")]
+    VerificationError { reason: String },
+
     /// 未知错误
     ///
     /// 当发生未预期的错误时产生。
@@ -115,6 +129,12 @@ pub enum DownloadError {
     /// 当配置值无效或超出范围时产生。
     #[error("配置验证失败: {field} - {error}")]
     ValidationError { field: String, error: String },
+
+    /// 任务已取消
+    ///
+    /// 当任务被暂停或取消、下载被中断时产生。
+    #[error("任务已取消")]
+    Cancelled,
 }
 
 /// Result 类型别名
@@ -232,6 +252,20 @@ impl DownloadError {
         }
     }
 
+    /// 创建转封装错误
+    pub fn remux(reason: impl Into<String>) -> Self {
+        Self::RemuxError {
+            reason: reason.into(),
+        }
+    }
+
+    /// 创建完整性校验错误
+    pub fn verification(reason: impl Into<String>) -> Self {
+        Self::VerificationError {
+            reason: reason.into(),
+        }
+    }
+
     /// 创建 URL 验证错误
     pub fn url_validation(url: impl Into<String>, reason: impl Into<String>) -> Self {
         Self::UrlValidationError {
@@ -240,6 +274,11 @@ impl DownloadError {
         }
     }
 
+    /// 是否为任务取消导致的错误
+    pub const fn is_cancelled(&self) -> bool {
+        matches!(self, Self::Cancelled)
+    }
+
     /// 创建配置验证错误
     pub fn validation(field: impl Into<String>, error: impl Into<String>) -> Self {
         Self::ValidationError {
@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use std::path::Path;
 
 mod config;
 mod downloader;
@@ -7,7 +8,15 @@ mod server;
 mod utils;
 mod validation;
 
+use config::{DEFAULT_CONCURRENT_DOWNLOADS, DEFAULT_RETRY_COUNT};
+use downloader::{
+    AdFilter, Args, ClipOptions, M3u8Downloader, OutputOptions, PeriodFilter, Remuxer,
+    VariantPolicy, VerifyOptions,
+};
 use error::Result;
+use utils::{HttpOptions, SegmentCheck};
+use utils::ffmpeg::detect_ffmpeg;
+use utils::rate_limit::{global_rate_limiter, parse_rate};
 
 #[derive(Parser)]
 #[command(name = "m3u8-downloader")]
@@ -18,6 +27,7 @@ struct Cli {
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// 启动Web服务模式
     Serve {
@@ -43,7 +53,158 @@ enum Commands {
         /// 最大并发下载数
         #[arg(short, long, default_value = "8")]
         concurrent: usize,
+
+        /// 所有任务共享的总限速（字节/秒，支持 `500K`、`2M`）
+        #[arg(long = "limit-rate", value_parser = parse_rate)]
+        rate_limit: Option<u64>,
+
+        /// FFmpeg 可执行文件或所在目录，默认从 PATH 查找
+        #[arg(long)]
+        ffmpeg_path: Option<String>,
     },
+
+    /// 下载单个M3U8链接
+    Download(DownloadCommand),
+
+    /// 探测播放列表内容（不下载），以JSON输出
+    Inspect {
+        /// M3U8 播放列表 URL
+        url: String,
+
+        /// 主播放列表的子流选择策略
+        #[arg(long, default_value = "highest")]
+        variant: VariantPolicy,
+
+        /// 优先选择的视频编码
+        #[arg(long)]
+        codec: Option<String>,
+
+        /// 请求头、Cookie、Referer 与 User-Agent
+        #[command(flatten)]
+        http: HttpOptions,
+    },
+}
+
+#[derive(clap::Args)]
+struct DownloadCommand {
+    /// M3U8 播放列表 URL
+    url: String,
+
+    /// 输出文件名（不包含扩展名）
+    #[arg(short, long)]
+    output: String,
+
+    /// 输出目录
+    #[arg(short = 'd', long, default_value = "./output")]
+    output_dir: String,
+
+    /// 临时目录（存放分段文件）
+    #[arg(short, long, default_value = "./downloads")]
+    temp_dir: String,
+
+    /// 并发下载数
+    #[arg(short, long, default_value_t = DEFAULT_CONCURRENT_DOWNLOADS)]
+    concurrent: usize,
+
+    /// 重试次数
+    #[arg(short, long, default_value_t = DEFAULT_RETRY_COUNT)]
+    retry: usize,
+
+    /// 请求头、Cookie、Referer 与 User-Agent
+    #[command(flatten)]
+    http: HttpOptions,
+
+    /// 主播放列表的子流选择策略
+    #[arg(long, default_value = "highest")]
+    variant: VariantPolicy,
+
+    /// 优先选择的视频编码
+    #[arg(long)]
+    codec: Option<String>,
+
+    /// 需要下载的音频语言（逗号分隔）
+    #[arg(long = "audio-lang", value_delimiter = ',')]
+    audio_languages: Vec<String>,
+
+    /// 需要下载的字幕语言（逗号分隔）
+    #[arg(long = "sub-lang", value_delimiter = ',')]
+    subtitle_languages: Vec<String>,
+
+    /// 输出格式、faststart、标题与转码预设
+    #[command(flatten)]
+    output_options: OutputOptions,
+
+    /// 直播录制模式
+    #[arg(long)]
+    live: bool,
+
+    /// 直播录制的最长时长（秒）
+    #[arg(long)]
+    max_duration: Option<u64>,
+
+    /// 限速（字节/秒，支持 `500K`、`2M`）
+    #[arg(long = "limit-rate", value_parser = parse_rate)]
+    rate_limit: Option<u64>,
+
+    /// 转封装方式：auto 优先使用内置转封装，native 不依赖FFmpeg，ffmpeg 始终使用FFmpeg
+    #[arg(long, value_enum, default_value_t = Remuxer::Auto)]
+    remuxer: Remuxer,
+
+    /// FFmpeg 可执行文件或所在目录，默认从 PATH 查找
+    #[arg(long)]
+    ffmpeg_path: Option<String>,
+
+    /// 按不连续时段过滤：all、index=0,2、skip=1、min-duration=60、longest
+    #[arg(long, default_value = "all")]
+    periods: PeriodFilter,
+
+    /// 广告/填充片段过滤：URI 正则、主机不一致、短时段与 CUE/SCTE-35 标记
+    #[command(flatten)]
+    ads: AdFilter,
+
+    /// 只下载指定的时间范围：--start/--end，--trim 精确裁剪
+    #[command(flatten)]
+    clip: ClipOptions,
+
+    /// 合并后的完整性校验：--verify off/warn/strict，--verify-tolerance 允许的时长误差比例
+    #[command(flatten)]
+    verify: VerifyOptions,
+
+    /// 继续下载时对已存在片段的校验：fast 检查长度与首尾同步字节，thorough 逐包检查同步字节与连续计数器
+    #[arg(long, value_enum, default_value_t = SegmentCheck::Fast)]
+    segment_check: SegmentCheck,
+}
+
+impl DownloadCommand {
+    fn into_args(self) -> Result<Args> {
+        validation::validate_path_safe(Path::new(&self.output_dir), &self.output)?;
+        validation::validate_path_safe(Path::new(&self.temp_dir), &self.output)?;
+
+        Ok(Args {
+            download_dir: format!("{}/{}", self.temp_dir, self.output),
+            url: self.url,
+            output_name: self.output,
+            concurrent: self.concurrent,
+            retry: self.retry,
+            output_dir: self.output_dir,
+            index: 1,
+            live: self.live,
+            max_duration: self.max_duration,
+            variant: self.variant,
+            codec: self.codec,
+            audio_languages: self.audio_languages,
+            subtitle_languages: self.subtitle_languages,
+            http: self.http,
+            output: self.output_options,
+            rate_limit: self.rate_limit,
+            remuxer: self.remuxer,
+            periods: self.periods,
+            ads: self.ads,
+            clip: self.clip,
+            verify: self.verify,
+            segment_check: self.segment_check,
+        })
+    }
 }
 
 #[tokio::main]
@@ -69,13 +230,23 @@ async fn main() -> Result<()> {
 
             server::start_server(&host, port).await?;
         }
-        Some(Commands::Batch { file, concurrent }) => {
+        Some(Commands::Batch {
+            file,
+            concurrent,
+            rate_limit,
+            ffmpeg_path,
+        }) => {
             // 验证并发数
             validation::validate_concurrent(concurrent)?;
             
             log::info!("📦 启动批量下载模式...");
             log::info!("📄 任务文件: {file}");
             log::info!("⚡ 最大并发数: {concurrent}");
+            if let Some(rate) = rate_limit {
+                log::info!("🐢 总限速: {rate} 字节/秒");
+                global_rate_limiter().set_rate(rate);
+            }
+            detect_ffmpeg(ffmpeg_path.as_deref().unwrap_or_default()).await;
 
             match utils::download_segment::load_and_process_download_tasks(&file, concurrent).await
             {
@@ -83,6 +254,28 @@ async fn main() -> Result<()> {
                 Err(e) => log::error!("❌ 批量下载失败: {e}"),
             }
         }
+        Some(Commands::Download(command)) => {
+            detect_ffmpeg(command.ffmpeg_path.as_deref().unwrap_or_default()).await;
+            let args = command.into_args()?;
+            log::info!("⬇️ 开始下载: {}", args.url);
+            log::info!("📁 输出目录: {}, 临时目录: {}", args.output_dir, args.download_dir);
+
+            let output_name = args.output_name.clone();
+            M3u8Downloader::new(args)?.download().await?;
+            log::info!("✅ 下载完成: {output_name}");
+        }
+        Some(Commands::Inspect {
+            url,
+            variant,
+            codec,
+            http,
+        }) => {
+            let report =
+                downloader::probe_playlist(&url, variant, codec.as_deref(), &http).await?;
+            let json = serde_json::to_string_pretty(&report)
+                .map_err(|e| error::DownloadError::parse(format!("序列化探测结果失败: {e}")))?;
+            println!("{json}");
+        }
         None => {
             log::info!("🚀 启动Web服务模式（默认）...");
             log::info!("📡 主机: 0.0.0.0:8080");
@@ -21,9 +21,18 @@ use bytes::Bytes;
 
 use crate::downloader::M3u8Downloader;
 use crate::downloader::Args as DownloadArgs;
-
-use crate::config::WS_UPDATE_INTERVAL_MS;
-use crate::server::state::{AppSettings, AppState, DownloadRequest, TaskStatus};
+use crate::downloader::{OutputOptions, Remuxer, VariantPolicy};
+use crate::error::DownloadError;
+use crate::utils::HttpOptions;
+use crate::utils::ffmpeg::{detect_ffmpeg, ffmpeg_info};
+use crate::utils::rate_limit::BandwidthRule;
+
+use crate::config::{BANDWIDTH_SCHEDULE_INTERVAL_SECONDS, WS_UPDATE_INTERVAL_MS};
+use crate::server::queue::TaskPriority;
+use crate::server::state::{
+    AppSettings, AppState, DownloadRequest, RecoveryPolicy, TaskInfo, TaskStatus,
+};
+use crate::validation;
 
 #[derive(Serialize)]
 pub struct DirEntry {
@@ -37,6 +46,24 @@ pub struct BrowseQuery {
     pub path: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct VariantsRequest {
+    pub url: String,
+    #[serde(default, flatten)]
+    pub http: HttpOptions,
+}
+
+#[derive(Deserialize)]
+pub struct ProbeRequest {
+    pub url: String,
+    #[serde(default)]
+    pub variant: VariantPolicy,
+    #[serde(default)]
+    pub codec: Option<String>,
+    #[serde(default, flatten)]
+    pub http: HttpOptions,
+}
+
 #[derive(RustEmbed)]
 #[folder = "static/"]
 struct StaticFiles;
@@ -46,6 +73,13 @@ pub struct SearchQuery {
     pub q: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct SystemQuery {
+    /// 重新探测 FFmpeg（如刚安装 FFmpeg 后）
+    #[serde(default)]
+    pub refresh: bool,
+}
+
 pub async fn index() -> impl IntoResponse {
     let html = StaticFiles::get("index.html").map_or_else(
         || b"<!DOCTYPE html><html><body><h1>M3U8 Downloader Service</h1></body></html>".to_vec(),
@@ -82,16 +116,17 @@ pub async fn start_download(
     State(state): State<AppState>,
     Json(request): Json<DownloadRequest>,
 ) -> impl IntoResponse {
+    if let Some(rejection) = reject_http_options(&request.http) {
+        return rejection;
+    }
     match state.add_task(request.clone()).await {
         Ok(task_id) => {
-            let state_clone = state.clone();
-            let task_id_clone = task_id.clone();
-
-            tokio::spawn(async move {
-                if let Err(e) = run_download_task(state_clone, task_id_clone, request).await {
-                    log::error!("下载任务执行失败: {e}");
-                }
-            });
+            // 直播录制不能等待，不经过队列直接开始
+            if request.live {
+                spawn_download_task(state.clone(), task_id.clone(), request);
+            } else {
+                state.enqueue_task(&task_id, request.priority).await;
+            }
 
             (
                 StatusCode::CREATED,
@@ -115,7 +150,10 @@ pub async fn init_stream_download(
     State(state): State<AppState>,
     Json(request): Json<DownloadRequest>,
 ) -> impl IntoResponse {
-    match state.add_task(request).await {
+    if let Some(rejection) = reject_http_options(&request.http) {
+        return rejection.into_response();
+    }
+    match state.add_stream_task(request).await {
         Ok(task_id) => {
             log::info!("📝 直传任务已创建: {task_id}");
             (
@@ -139,6 +177,16 @@ pub async fn init_stream_download(
     }
 }
 
+/// 拒绝 Web 接口不支持的请求选项，返回 400 响应
+fn reject_http_options(http: &HttpOptions) -> Option<(StatusCode, Json<serde_json::Value>)> {
+    let e = http.validate_remote().err()?;
+    log::warn!("⚠️ 请求参数无效: {e}");
+    Some((
+        StatusCode::BAD_REQUEST,
+        Json(json!({"error": e.to_string()})),
+    ))
+}
+
 fn create_task_callbacks(
     state: &AppState,
     task_id: &str,
@@ -178,14 +226,131 @@ fn create_task_callbacks(
     (callback, status_callback)
 }
 
+/// 任务的片段临时目录
+fn task_download_dir(settings: &AppSettings, name: &str) -> String {
+    format!("{}/{}", settings.temp_dir, name)
+}
+
+/// 任务被暂停或取消后更新状态；取消时清理临时目录
+async fn finish_interrupted_task(
+    state: &AppState,
+    task_id: &str,
+    status: TaskStatus,
+    download_dir: &str,
+) {
+    if status == TaskStatus::Cancelled {
+        if let Err(e) = tokio::fs::remove_dir_all(download_dir).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            log::error!("删除临时目录失败 {download_dir}: {e}");
+        }
+        log::info!("⏹️ 任务 {task_id} 已取消");
+    } else {
+        log::info!("⏸️ 任务 {task_id} 已暂停，已下载的片段保留在 {download_dir}");
+    }
+    let _ = state.update_task_status(task_id, status, None).await;
+}
+
+/// 服务启动时处理上次运行中断的任务
+///
+/// 普通任务按设置自动继续（复用临时目录中已下载的片段）、标记为暂停或失败；
+/// 直传任务的客户端连接已断开，直播录制无法接续，二者总是标记为失败。
+pub async fn recover_interrupted_tasks(state: &AppState) {
+    let tasks = state.interrupted_tasks().await;
+    if tasks.is_empty() {
+        return;
+    }
+    let policy = state.get_settings().await.recovery;
+    log::info!(
+        "🔄 发现 {} 个上次未完成的任务，处理方式: {policy:?}",
+        tasks.len()
+    );
+
+    for task in tasks {
+        let error = if task.stream {
+            Some("服务重启，直传连接已断开")
+        } else if task.live {
+            Some("服务重启，直播录制已中断")
+        } else {
+            match policy {
+                RecoveryPolicy::Resume => None,
+                RecoveryPolicy::Pause => {
+                    let _ = state
+                        .update_task_status(&task.id, TaskStatus::Paused, None)
+                        .await;
+                    log::info!("⏸️ 任务 {} ({}) 已标记为暂停", task.id, task.name);
+                    continue;
+                }
+                RecoveryPolicy::Fail => Some("服务重启，任务已中断"),
+            }
+        };
+
+        if let Some(error) = error {
+            let _ = state
+                .update_task_status(&task.id, TaskStatus::Failed, Some(error.to_string()))
+                .await;
+            log::warn!("任务 {} ({}) 已标记为失败: {error}", task.id, task.name);
+            continue;
+        }
+
+        let _ = state
+            .update_task_status(&task.id, TaskStatus::Pending, None)
+            .await;
+        log::info!("▶️ 恢复任务 {} ({})", task.id, task.name);
+        state.enqueue_task(&task.id, task.priority).await;
+    }
+}
+
+/// 下载队列调度：有空闲名额时按队列顺序启动任务
+pub async fn run_queue_dispatcher(state: AppState) {
+    loop {
+        state.queue_notify.notified().await;
+        while let Some(task_id) = state.next_queued_task().await {
+            match state.get_task(&task_id).await {
+                Some(task) => spawn_download_task(state.clone(), task_id, task.to_request()),
+                None => state.release_task_slot(&task_id).await,
+            }
+        }
+    }
+}
+
+/// 按时段规则定时刷新全局限速
+pub async fn run_bandwidth_scheduler(state: AppState) {
+    let mut ticker = interval(Duration::from_secs(BANDWIDTH_SCHEDULE_INTERVAL_SECONDS));
+    loop {
+        ticker.tick().await;
+        state.apply_bandwidth_limit().await;
+    }
+}
+
+/// 在后台执行下载任务，结束后释放队列名额
+fn spawn_download_task(state: AppState, task_id: String, request: DownloadRequest) {
+    tokio::spawn(async move {
+        if let Err(e) = run_download_task(state.clone(), task_id.clone(), request).await {
+            log::error!("下载任务执行失败: {e}");
+            let _ = state
+                .update_task_status(&task_id, TaskStatus::Failed, Some(e))
+                .await;
+        }
+        state.release_task_slot(&task_id).await;
+    });
+}
+
 async fn run_download_task(
     state: AppState,
     task_id: String,
     request: DownloadRequest,
 ) -> Result<(), String> {
-    let _ = state
-        .update_task_status(&task_id, TaskStatus::Downloading, None)
-        .await;
+    let running = state.register_running_task(&task_id, request.rate_limit).await;
+    // 排队期间任务可能已被暂停、取消或删除
+    if state
+        .transition_task(&task_id, &[TaskStatus::Pending], TaskStatus::Downloading)
+        .await
+        .is_none()
+    {
+        state.finish_running_task(&task_id).await;
+        return Ok(());
+    }
 
     let settings = state.get_settings().await;
     log::info!(
@@ -199,45 +364,87 @@ async fn run_download_task(
     let output_dir = request
         .output_dir
         .unwrap_or_else(|| settings.download_dir.clone());
-    let download_dir = format!("{}/{}", settings.temp_dir, request.name);
+    let download_dir = task_download_dir(&settings, &request.name);
 
-    tokio::fs::create_dir_all(&output_dir)
-        .await
-        .map_err(|e| e.to_string())?;
-    tokio::fs::create_dir_all(&download_dir)
-        .await
-        .map_err(|e| e.to_string())?;
+    let prepared = async {
+        tokio::fs::create_dir_all(&output_dir).await?;
+        tokio::fs::create_dir_all(&download_dir).await
+    }
+    .await;
+    if let Err(e) = prepared {
+        state.finish_running_task(&task_id).await;
+        return Err(format!("创建目录失败: {e}"));
+    }
 
     log::info!("📁 输出目录: {output_dir}, 临时目录: {download_dir}");
 
-    let output_name = request.name.clone();
+    let stop_signal = if request.live {
+        Some(state.register_stop_signal(&task_id).await)
+    } else {
+        None
+    };
     let args = crate::downloader::Args {
         url: request.url,
         output_name: request.name,
         concurrent: settings.concurrent,
         retry: settings.retry,
-        download_dir,
+        download_dir: download_dir.clone(),
         output_dir: output_dir.clone(),
         index: 1,
+        live: request.live,
+        max_duration: request.max_duration,
+        variant: request.variant,
+        codec: request.codec,
+        audio_languages: request.audio_languages,
+        subtitle_languages: request.subtitle_languages,
+        http: request.http.with_default_proxy(&settings.proxy),
+        output: request.output,
+        // 任务限速由运行中任务的限速器负责，以便运行时调整
+        rate_limit: None,
+        remuxer: Remuxer::default(),
+        periods: request.periods,
+        ads: request.ads,
+        clip: request.clip,
+        verify: request.verify,
+        segment_check: request.segment_check,
     };
 
     let (callback, status_callback) = create_task_callbacks(&state, &task_id);
 
     match crate::utils::download_segment::M3u8Downloader::new(args) {
         Ok(downloader) => {
-            let downloader = downloader
+            let output_file = downloader.output_path();
+            let mut downloader = downloader
                 .with_progress_callback(callback)
-                .with_status_callback(status_callback);
-            match downloader.download().await {
+                .with_status_callback(status_callback)
+                .with_cancel_token(running.token)
+                .with_rate_limiter(running.rate_limiter);
+            if let Some(token) = stop_signal {
+                downloader = downloader.with_stop_signal(token);
+            }
+            let result = downloader.download().await;
+            state.remove_stop_signal(&task_id).await;
+            let interrupted_as = state.finish_running_task(&task_id).await;
+            // 严格校验不通过时任务失败，校验结果同样需要记录
+            let verification = downloader.stats.lock().await.verification.clone();
+            if let Some(report) = verification {
+                let _ = state.update_task_verification(&task_id, report).await;
+            }
+            match result {
                 Ok(()) => {
                     let _ = state
                         .update_task_status(&task_id, TaskStatus::Completed, None)
                         .await;
                     let _ = state.update_task_progress(&task_id, 100.0).await;
 
+                    let skipped = downloader.stats.lock().await.skipped.clone();
+                    if !skipped.is_empty() {
+                        let _ = state.update_task_skipped(&task_id, skipped).await;
+                    }
+
                     // 获取输出文件信息
-                    let output_file = format!("{output_dir}/{output_name}.mp4");
                     if let Ok(metadata) = tokio::fs::metadata(&output_file).await {
+                        let output_file = output_file.display().to_string();
                         let _ = state
                             .update_task_output(&task_id, output_file, metadata.len())
                             .await;
@@ -245,6 +452,10 @@ async fn run_download_task(
 
                     log::info!("✅ 任务 {task_id} 下载完成");
                 }
+                Err(e) if e.is_cancelled() || interrupted_as.is_some() => {
+                    let status = interrupted_as.unwrap_or(TaskStatus::Cancelled);
+                    finish_interrupted_task(&state, &task_id, status, &download_dir).await;
+                }
                 Err(e) => {
                     let _ = state
                         .update_task_status(&task_id, TaskStatus::Failed, Some(e.to_string()))
@@ -254,6 +465,8 @@ async fn run_download_task(
             }
         }
         Err(e) => {
+            state.remove_stop_signal(&task_id).await;
+            state.finish_running_task(&task_id).await;
             let _ = state
                 .update_task_status(&task_id, TaskStatus::Failed, Some(e.to_string()))
                 .await;
@@ -304,6 +517,7 @@ async fn build_stream_download_response(
     }
 
     let (tx, rx) = mpsc::channel::<std::result::Result<Bytes, String>>(32);
+    let running = state.register_running_task(&task_id, request.rate_limit).await;
 
     let args = DownloadArgs {
         url: request.url.clone(),
@@ -313,6 +527,23 @@ async fn build_stream_download_response(
         download_dir: download_dir.clone(),
         output_dir: output_dir.clone(),
         index: 1,
+        live: request.live,
+        max_duration: request.max_duration,
+        variant: request.variant,
+        codec: request.codec,
+        audio_languages: request.audio_languages,
+        subtitle_languages: request.subtitle_languages,
+        http: request.http.with_default_proxy(&settings.proxy),
+        // 直传始终输出分片 MP4
+        output: OutputOptions::default(),
+        // 任务限速由运行中任务的限速器负责，以便运行时调整
+        rate_limit: None,
+        remuxer: Remuxer::default(),
+        periods: request.periods,
+        ads: request.ads,
+        clip: request.clip,
+        verify: request.verify,
+        segment_check: request.segment_check,
     };
 
     let (callback, status_callback) = create_task_callbacks(&state, &task_id);
@@ -324,15 +555,21 @@ async fn build_stream_download_response(
             let download_dir_clone = download_dir.clone();
             let output_dir_clone = output_dir.clone();
             let error_tx = tx.clone();
+            // 响应体被丢弃（客户端断开）时取消后台下载
+            let disconnect_guard = running.token.clone().drop_guard();
 
             tokio::spawn(async move {
                 log::info!("▶️ 直传任务开始后台下载: {task_id_clone}");
                 let downloader = downloader
                     .with_progress_callback(callback)
                     .with_status_callback(status_callback)
+                    .with_cancel_token(running.token)
+                    .with_rate_limiter(running.rate_limiter)
                     .with_stream_output(tx);
 
-                match downloader.download().await {
+                let result = downloader.download().await;
+                let interrupted_as = state_clone.finish_running_task(&task_id_clone).await;
+                match result {
                     Ok(()) => {
                         let _ = state_clone.update_task_progress(&task_id_clone, 100.0).await;
                         let _ = state_clone
@@ -340,6 +577,23 @@ async fn build_stream_download_response(
                             .await;
                         log::info!("✅ 直传任务 {task_id_clone} 下载完成");
                     }
+                    Err(_) if interrupted_as.is_some() => {
+                        let _ = error_tx.send(Err("任务已取消".to_string())).await;
+                        let _ = state_clone
+                            .update_task_status(&task_id_clone, TaskStatus::Cancelled, None)
+                            .await;
+                        log::info!("⏹️ 直传任务 {task_id_clone} 已取消");
+                    }
+                    Err(e) if e.is_cancelled() => {
+                        let _ = state_clone
+                            .update_task_status(
+                                &task_id_clone,
+                                TaskStatus::Failed,
+                                Some("客户端已断开连接".to_string()),
+                            )
+                            .await;
+                        log::warn!("⚠️ 直传任务 {task_id_clone} 的客户端已断开连接，下载已停止");
+                    }
                     Err(e) => {
                         let message = e.to_string();
                         let _ = state_clone
@@ -358,8 +612,8 @@ async fn build_stream_download_response(
                 let _ = std::fs::remove_dir_all(&output_dir_clone);
             });
 
-            let stream = stream::unfold(rx, |mut rx| async {
-                rx.recv().await.map(|item| (item, rx))
+            let stream = stream::unfold((rx, disconnect_guard), |(mut rx, guard)| async {
+                rx.recv().await.map(|item| (item, (rx, guard)))
             });
 
             let filename = format!("{}.mp4", request.name);
@@ -374,6 +628,7 @@ async fn build_stream_download_response(
                 .unwrap()
         }
         Err(e) => {
+            state.finish_running_task(&task_id).await;
             let message = format!("创建下载器失败: {}", e);
             let _ = std::fs::remove_dir_all(&download_dir);
             let _ = std::fs::remove_dir_all(&output_dir);
@@ -402,7 +657,12 @@ pub async fn get_all_tasks(
     } else {
         state.get_all_tasks().await
     };
-    Json(tasks)
+    Json(redact_tasks(&tasks))
+}
+
+/// 接口返回的任务列表隐去敏感信息
+fn redact_tasks(tasks: &[TaskInfo]) -> Vec<TaskInfo> {
+    tasks.iter().map(TaskInfo::redacted).collect()
 }
 
 pub async fn get_task(
@@ -418,7 +678,7 @@ pub async fn get_task(
                 })),
             )
         },
-        |task| (StatusCode::OK, Json(json!(task))),
+        |task| (StatusCode::OK, Json(json!(task.redacted()))),
     )
 }
 
@@ -426,6 +686,9 @@ pub async fn delete_task(
     AxumPath(id): AxumPath<String>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
+    // 先中断仍在运行的下载，避免删除记录后继续写入磁盘
+    state.interrupt_task(&id, TaskStatus::Cancelled).await;
+    state.dequeue_task(&id).await;
     match state.delete_task(&id).await {
         Ok(true) => (StatusCode::OK, Json(json!({"message": "任务已删除"}))),
         Ok(false) => (StatusCode::NOT_FOUND, Json(json!({"error": "任务不存在"}))),
@@ -436,6 +699,261 @@ pub async fn delete_task(
     }
 }
 
+pub async fn stop_recording(
+    AxumPath(id): AxumPath<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    if state.stop_recording(&id).await {
+        (
+            StatusCode::OK,
+            Json(json!({"message": "已发送停止录制信号，正在合并已录制的片段"})),
+        )
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "任务不存在或不在直播录制中"})),
+        )
+    }
+}
+
+pub async fn pause_task(
+    AxumPath(id): AxumPath<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(task) = state.get_task(&id).await else {
+        return (StatusCode::NOT_FOUND, Json(json!({"error": "任务不存在"})));
+    };
+    if task.live {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "直播录制任务不支持暂停，请使用停止录制或取消"})),
+        );
+    }
+    if task.stream {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "直传任务不支持暂停，请使用取消"})),
+        );
+    }
+    if !task.status.is_active() {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "只能暂停等待中或下载中的任务"})),
+        );
+    }
+
+    if state.interrupt_task(&id, TaskStatus::Paused).await
+        || state
+            .transition_task(&id, &[TaskStatus::Pending], TaskStatus::Paused)
+            .await
+            .is_some()
+    {
+        state.dequeue_task(&id).await;
+        (StatusCode::OK, Json(json!({"message": "任务已暂停"})))
+    } else {
+        (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "任务状态已变化，请刷新后重试"})),
+        )
+    }
+}
+
+pub async fn resume_task(
+    AxumPath(id): AxumPath<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    if state.get_task(&id).await.is_none() {
+        return (StatusCode::NOT_FOUND, Json(json!({"error": "任务不存在"})));
+    }
+    // 暂停信号发出后，下载任务可能还未完全退出
+    if state.is_task_running(&id).await {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "任务正在暂停中，请稍后重试"})),
+        );
+    }
+    let Some(task) = state
+        .transition_task(&id, &[TaskStatus::Paused], TaskStatus::Pending)
+        .await
+    else {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "只能继续已暂停的任务"})),
+        );
+    };
+
+    state.enqueue_task(&id, task.priority).await;
+    log::info!("▶️ 任务 {id} 重新加入下载队列");
+    (StatusCode::OK, Json(json!({"message": "任务已继续，等待下载"})))
+}
+
+pub async fn get_queue(State(state): State<AppState>) -> impl IntoResponse {
+    let (pending, active) = state.queued_tasks().await;
+    Json(json!({
+        "max_active_tasks": state.get_settings().await.max_active_tasks,
+        "active": redact_tasks(&active),
+        "pending": redact_tasks(&pending),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ReorderQueueRequest {
+    /// 希望排在最前的任务，按顺序列出；未列出的任务保持原有顺序
+    pub ids: Vec<String>,
+}
+
+pub async fn reorder_queue(
+    State(state): State<AppState>,
+    Json(request): Json<ReorderQueueRequest>,
+) -> impl IntoResponse {
+    match state.reorder_queue(&request.ids).await {
+        Ok(()) => (StatusCode::OK, Json(json!({"message": "队列已重新排序"}))),
+        Err(id) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": format!("任务不在等待队列中: {id}")})),
+        ),
+    }
+}
+
+pub async fn move_task_to_top(
+    AxumPath(id): AxumPath<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    if state.move_task_to_top(&id).await {
+        (StatusCode::OK, Json(json!({"message": "任务已移到队首"})))
+    } else {
+        (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "任务不存在或不在等待队列中"})),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PriorityRequest {
+    pub priority: TaskPriority,
+}
+
+pub async fn set_task_priority(
+    AxumPath(id): AxumPath<String>,
+    State(state): State<AppState>,
+    Json(request): Json<PriorityRequest>,
+) -> impl IntoResponse {
+    if state.set_task_priority(&id, request.priority).await {
+        (StatusCode::OK, Json(json!({"message": "优先级已更新"})))
+    } else {
+        (StatusCode::NOT_FOUND, Json(json!({"error": "任务不存在"})))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RateLimitRequest {
+    /// 字节/秒，`null` 或 0 表示不限速
+    pub rate_limit: Option<u64>,
+}
+
+pub async fn set_task_rate_limit(
+    AxumPath(id): AxumPath<String>,
+    State(state): State<AppState>,
+    Json(request): Json<RateLimitRequest>,
+) -> impl IntoResponse {
+    let rate_limit = request.rate_limit.filter(|&rate| rate > 0);
+    if state.set_task_rate_limit(&id, rate_limit).await {
+        (StatusCode::OK, Json(json!({"message": "限速已更新"})))
+    } else {
+        (StatusCode::NOT_FOUND, Json(json!({"error": "任务不存在"})))
+    }
+}
+
+pub async fn cancel_task(
+    AxumPath(id): AxumPath<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let Some(task) = state.get_task(&id).await else {
+        return (StatusCode::NOT_FOUND, Json(json!({"error": "任务不存在"})));
+    };
+
+    if state.interrupt_task(&id, TaskStatus::Cancelled).await {
+        return (StatusCode::OK, Json(json!({"message": "正在取消任务"})));
+    }
+
+    // 未在运行的等待中或已暂停任务直接标记为取消，并清理已下载的片段
+    if state
+        .transition_task(
+            &id,
+            &[TaskStatus::Pending, TaskStatus::Paused],
+            TaskStatus::Cancelled,
+        )
+        .await
+        .is_some()
+    {
+        state.dequeue_task(&id).await;
+        let settings = state.get_settings().await;
+        let download_dir = task_download_dir(&settings, &task.name);
+        finish_interrupted_task(&state, &id, TaskStatus::Cancelled, &download_dir).await;
+        return (StatusCode::OK, Json(json!({"message": "任务已取消"})));
+    }
+
+    (
+        StatusCode::CONFLICT,
+        Json(json!({"error": "任务已结束，无法取消"})),
+    )
+}
+
+pub async fn list_variants(
+    State(state): State<AppState>,
+    Json(request): Json<VariantsRequest>,
+) -> impl IntoResponse {
+    if let Some(rejection) = reject_http_options(&request.http) {
+        return rejection;
+    }
+    let http = request
+        .http
+        .with_default_proxy(&state.get_settings().await.proxy);
+    match crate::utils::download_segment::fetch_variants(&request.url, &http).await {
+        Ok((variants, renditions)) => (
+            StatusCode::OK,
+            Json(json!({ "variants": variants, "renditions": renditions })),
+        ),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({"error": format!("获取子流列表失败: {e}")})),
+        ),
+    }
+}
+
+pub async fn probe_playlist(
+    State(state): State<AppState>,
+    Json(request): Json<ProbeRequest>,
+) -> impl IntoResponse {
+    if let Some(rejection) = reject_http_options(&request.http) {
+        return rejection;
+    }
+    let http = request
+        .http
+        .with_default_proxy(&state.get_settings().await.proxy);
+    match crate::downloader::probe_playlist(
+        &request.url,
+        request.variant,
+        request.codec.as_deref(),
+        &http,
+    )
+    .await
+    {
+        Ok(report) => (StatusCode::OK, Json(json!(report))),
+        Err(
+            e @ (DownloadError::UrlValidationError { .. } | DownloadError::ValidationError { .. }),
+        ) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        ),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({"error": format!("探测播放列表失败: {e}")})),
+        ),
+    }
+}
+
 pub async fn download_task_file(
     AxumPath(id): AxumPath<String>,
     State(state): State<AppState>,
@@ -509,6 +1027,21 @@ pub async fn stream_download_by_task(
         name: task.name,
         url: task.url,
         output_dir: None,
+        live: false,
+        max_duration: None,
+        variant: task.variant,
+        codec: task.codec,
+        audio_languages: task.audio_languages,
+        subtitle_languages: task.subtitle_languages,
+        http: task.http,
+        priority: task.priority,
+        rate_limit: task.rate_limit,
+        output: OutputOptions::default(),
+        periods: task.periods,
+        ads: task.ads,
+        clip: task.clip,
+        verify: task.verify,
+        segment_check: task.segment_check,
     };
 
     build_stream_download_response(state, id, request).await
@@ -516,17 +1049,17 @@ pub async fn stream_download_by_task(
 
 pub async fn get_pending_tasks(State(state): State<AppState>) -> impl IntoResponse {
     let tasks = state.get_tasks_by_status(TaskStatus::Pending).await;
-    Json(tasks)
+    Json(redact_tasks(&tasks))
 }
 
 pub async fn get_completed_tasks(State(state): State<AppState>) -> impl IntoResponse {
     let tasks = state.get_tasks_by_status(TaskStatus::Completed).await;
-    Json(tasks)
+    Json(redact_tasks(&tasks))
 }
 
 pub async fn get_failed_tasks(State(state): State<AppState>) -> impl IntoResponse {
     let tasks = state.get_tasks_by_status(TaskStatus::Failed).await;
-    Json(tasks)
+    Json(redact_tasks(&tasks))
 }
 
 pub async fn get_stats(State(app_state): State<AppState>) -> impl IntoResponse {
@@ -549,12 +1082,18 @@ async fn handle_websocket(mut socket: WebSocket, task_id: String, state: AppStat
         tokio::select! {
             _ = interval.tick() => {
                 if let Some(task) = state.get_task(&task_id).await {
-                    let msg = serde_json::to_string(&task).unwrap();
+                    let msg = serde_json::to_string(&task.redacted()).unwrap();
                     if socket.send(Message::Text(msg)).await.is_err() {
                         break;
                     }
 
-                    if task.status == TaskStatus::Completed || task.status == TaskStatus::Failed {
+                    if matches!(
+                        task.status,
+                        TaskStatus::Completed
+                            | TaskStatus::Failed
+                            | TaskStatus::Paused
+                            | TaskStatus::Cancelled
+                    ) {
                         break;
                     }
                 } else {
@@ -577,13 +1116,35 @@ pub async fn get_settings(State(state): State<AppState>) -> impl IntoResponse {
         settings.download_dir,
         settings.concurrent
     );
-    Json(settings)
+    Json(settings.redacted())
+}
+
+/// 程序版本与 FFmpeg 可用性
+pub async fn get_system_info(
+    State(state): State<AppState>,
+    Query(query): Query<SystemQuery>,
+) -> impl IntoResponse {
+    let ffmpeg = match ffmpeg_info() {
+        Some(info) if !query.refresh => info,
+        _ => detect_ffmpeg(&state.get_settings().await.ffmpeg_path).await,
+    };
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "native_remuxer": true,
+        "ffmpeg": ffmpeg,
+    }))
 }
 
 pub async fn update_settings(
     State(state): State<AppState>,
-    Json(new_settings): Json<AppSettings>,
+    Json(mut new_settings): Json<AppSettings>,
 ) -> impl IntoResponse {
+    // 设置页面回传的是隐去后的代理密码，未修改时沿用已保存的值
+    new_settings
+        .proxy
+        .restore_secrets(&state.get_settings().await.proxy);
     log::info!(
         "🔧 收到设置更新请求: download_dir={}, concurrent={}, retry={}",
         new_settings.download_dir,
@@ -591,6 +1152,22 @@ pub async fn update_settings(
         new_settings.retry
     );
 
+    if let Err(e) = validation::validate_max_active_tasks(new_settings.max_active_tasks)
+        .and_then(|()| new_settings.proxy.validate())
+        .and_then(|()| {
+            new_settings
+                .bandwidth_schedule
+                .iter()
+                .try_for_each(BandwidthRule::validate)
+        })
+    {
+        log::warn!("⚠️ 设置无效: {e}");
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        );
+    }
+
     match state.update_settings(new_settings.clone()).await {
         Ok(()) => {
             let saved = state.get_settings().await;
@@ -650,8 +1227,8 @@ pub async fn browse_directories(Query(query): Query<BrowseQuery>) -> impl IntoRe
                     }
                 }
 
-                dirs.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-                files.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+                dirs.sort_by_key(|a| a.name.to_lowercase());
+                files.sort_by_key(|a| a.name.to_lowercase());
 
                 entries.extend(dirs);
                 entries.extend(files);
@@ -689,8 +1266,8 @@ pub async fn browse_directories(Query(query): Query<BrowseQuery>) -> impl IntoRe
             }
         }
 
-        dirs.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-        files.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        dirs.sort_by_key(|a| a.name.to_lowercase());
+        files.sort_by_key(|a| a.name.to_lowercase());
 
         entries.extend(dirs);
         entries.extend(files);
@@ -734,7 +1311,10 @@ pub async fn stream_download(
     State(state): State<AppState>,
     Json(request): Json<DownloadRequest>,
 ) -> impl IntoResponse {
-    match state.add_task(request.clone()).await {
+    if let Some(rejection) = reject_http_options(&request.http) {
+        return rejection.into_response();
+    }
+    match state.add_stream_task(request.clone()).await {
         Ok(task_id) => build_stream_download_response(state, task_id, request).await,
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
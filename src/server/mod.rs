@@ -1,4 +1,5 @@
 ﻿mod handlers;
+pub mod queue;
 pub mod state;
 
 use axum::{
@@ -9,6 +10,7 @@ use std::path::PathBuf;
 use tower_http::cors::CorsLayer;
 
 use crate::error::Result;
+use crate::utils::ffmpeg::detect_ffmpeg;
 use state::AppState;
 
 pub fn create_router(state: AppState) -> Router {
@@ -18,6 +20,8 @@ pub fn create_router(state: AppState) -> Router {
         .route("/settings.html", get(handlers::settings_page))
         .route("/api/download", post(handlers::start_download))
         .route("/api/download/stream/init", post(handlers::init_stream_download))
+        .route("/api/variants", post(handlers::list_variants))
+        .route("/api/probe", post(handlers::probe_playlist))
         .route("/api/tasks", get(handlers::get_all_tasks))
         .route("/api/download/stream", post(handlers::stream_download))
         .route("/api/download/stream/:id", get(handlers::stream_download_by_task))
@@ -28,10 +32,20 @@ pub fn create_router(state: AppState) -> Router {
         .route("/api/tasks/:id", get(handlers::get_task))
         .route("/api/tasks/:id", delete(handlers::delete_task))
         .route("/api/tasks/:id/download", get(handlers::download_task_file))
+        .route("/api/tasks/:id/stop", post(handlers::stop_recording))
+        .route("/api/tasks/:id/pause", post(handlers::pause_task))
+        .route("/api/tasks/:id/resume", post(handlers::resume_task))
+        .route("/api/tasks/:id/cancel", post(handlers::cancel_task))
+        .route("/api/tasks/:id/top", post(handlers::move_task_to_top))
+        .route("/api/tasks/:id/priority", put(handlers::set_task_priority))
+        .route("/api/tasks/:id/rate-limit", put(handlers::set_task_rate_limit))
+        .route("/api/queue", get(handlers::get_queue))
+        .route("/api/queue", put(handlers::reorder_queue))
         .route("/api/tasks/:id/ws", get(handlers::websocket_handler))
         .route("/api/settings", get(handlers::get_settings))
         .route("/api/settings", put(handlers::update_settings))
         .route("/api/browse", get(handlers::browse_directories))
+        .route("/api/system", get(handlers::get_system_info))
         .layer(CorsLayer::permissive())
         .with_state(state)
 }
@@ -44,6 +58,10 @@ pub async fn start_server(host: &str, port: u16) -> Result<()> {
     if let Err(e) = state.load().await {
         log::warn!("加载数据失败: {e}");
     }
+    detect_ffmpeg(&state.get_settings().await.ffmpeg_path).await;
+    handlers::recover_interrupted_tasks(&state).await;
+    tokio::spawn(handlers::run_queue_dispatcher(state.clone()));
+    tokio::spawn(handlers::run_bandwidth_scheduler(state.clone()));
 
     let app = create_router(state.clone());
 
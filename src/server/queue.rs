@@ -0,0 +1,189 @@
+//! 全局下载队列：限制同时运行的任务数，按优先级先进先出调度
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// 任务优先级，高优先级的任务排在同级及更低优先级任务之前
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone)]
+struct QueuedTask {
+    id: String,
+    priority: TaskPriority,
+}
+
+/// 等待中的任务队列与正在运行的任务
+#[derive(Debug, Default)]
+pub struct TaskQueue {
+    pending: Vec<QueuedTask>,
+    active: HashSet<String>,
+}
+
+impl TaskQueue {
+    /// 加入队列：排在所有同级及更高优先级任务之后
+    pub fn push(&mut self, id: &str, priority: TaskPriority) {
+        self.remove(id);
+        let position = self
+            .pending
+            .iter()
+            .rposition(|t| t.priority >= priority)
+            .map_or(0, |i| i + 1);
+        self.pending.insert(
+            position,
+            QueuedTask {
+                id: id.to_string(),
+                priority,
+            },
+        );
+    }
+
+    /// 运行中的任务数未达上限时取出队首任务并占用一个名额
+    pub fn pop(&mut self, max_active: usize) -> Option<String> {
+        if self.active.len() >= max_active || self.pending.is_empty() {
+            return None;
+        }
+        let task = self.pending.remove(0);
+        self.active.insert(task.id.clone());
+        Some(task.id)
+    }
+
+    /// 任务结束后释放名额
+    pub fn release(&mut self, id: &str) -> bool {
+        self.active.remove(id)
+    }
+
+    /// 从队列中移除等待中的任务
+    pub fn remove(&mut self, id: &str) -> bool {
+        let len = self.pending.len();
+        self.pending.retain(|t| t.id != id);
+        self.pending.len() != len
+    }
+
+    /// 将等待中的任务移到队首
+    pub fn move_to_top(&mut self, id: &str) -> bool {
+        let Some(index) = self.pending.iter().position(|t| t.id == id) else {
+            return false;
+        };
+        let task = self.pending.remove(index);
+        self.pending.insert(0, task);
+        true
+    }
+
+    /// 按给定顺序重排：列出的任务依次排在最前，其余任务保持原有顺序
+    ///
+    /// 包含不在队列中的任务时返回该任务 ID，队列保持不变。
+    pub fn reorder(&mut self, ids: &[String]) -> Result<(), String> {
+        if let Some(unknown) = ids.iter().find(|id| !self.contains(id)) {
+            return Err(unknown.clone());
+        }
+        let mut reordered: Vec<QueuedTask> = Vec::with_capacity(self.pending.len());
+        for id in ids {
+            if let Some(index) = self.pending.iter().position(|t| &t.id == id) {
+                reordered.push(self.pending.remove(index));
+            }
+        }
+        reordered.append(&mut self.pending);
+        self.pending = reordered;
+        Ok(())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.pending.iter().any(|t| t.id == id)
+    }
+
+    /// 等待中的任务 ID，按调度顺序
+    pub fn pending_ids(&self) -> Vec<String> {
+        self.pending.iter().map(|t| t.id.clone()).collect()
+    }
+
+    /// 正在运行的任务 ID
+    pub fn active_ids(&self) -> Vec<String> {
+        self.active.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(tasks: &[(&str, TaskPriority)]) -> TaskQueue {
+        let mut queue = TaskQueue::default();
+        for (id, priority) in tasks {
+            queue.push(id, *priority);
+        }
+        queue
+    }
+
+    fn ids(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn push_orders_by_priority_then_arrival() {
+        let mut queue = queue(&[
+            ("a", TaskPriority::Normal),
+            ("b", TaskPriority::Low),
+            ("c", TaskPriority::High),
+            ("d", TaskPriority::Normal),
+            ("e", TaskPriority::High),
+        ]);
+        assert_eq!(queue.pending_ids(), ["c", "e", "a", "d", "b"]);
+
+        // 重新加入的任务不会重复，按新的优先级排队
+        queue.push("b", TaskPriority::High);
+        assert_eq!(queue.pending_ids(), ["c", "e", "b", "a", "d"]);
+    }
+
+    #[test]
+    fn pop_respects_active_limit() {
+        let mut queue = queue(&[("a", TaskPriority::Normal), ("b", TaskPriority::Normal)]);
+        assert_eq!(queue.pop(1).as_deref(), Some("a"));
+        assert_eq!(queue.pop(1), None);
+        assert_eq!(queue.active_ids(), ["a"]);
+        assert!(queue.release("a"));
+        assert!(!queue.release("a"));
+        assert_eq!(queue.pop(1).as_deref(), Some("b"));
+        assert_eq!(queue.pop(2), None);
+    }
+
+    #[test]
+    fn move_to_top_ignores_priority() {
+        let mut queue = queue(&[("a", TaskPriority::High), ("b", TaskPriority::Low)]);
+        assert!(queue.move_to_top("b"));
+        assert!(!queue.move_to_top("missing"));
+        assert_eq!(queue.pending_ids(), ["b", "a"]);
+    }
+
+    #[test]
+    fn reorder_puts_listed_tasks_first() {
+        let mut queue = queue(&[
+            ("a", TaskPriority::Normal),
+            ("b", TaskPriority::Normal),
+            ("c", TaskPriority::Normal),
+            ("d", TaskPriority::Normal),
+        ]);
+        assert_eq!(queue.reorder(&ids(&["d", "b"])), Ok(()));
+        assert_eq!(queue.pending_ids(), ["d", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn reorder_rejects_unknown_ids() {
+        let mut queue = queue(&[
+            ("a", TaskPriority::Normal),
+            ("b", TaskPriority::Normal),
+            ("c", TaskPriority::Normal),
+        ]);
+        queue.pop(1);
+        assert_eq!(queue.reorder(&ids(&["c", "x"])), Err("x".to_string()));
+        // 运行中的任务不在等待队列中，同样视为未知
+        assert_eq!(queue.reorder(&ids(&["a"])), Err("a".to_string()));
+        assert_eq!(queue.pending_ids(), ["b", "c"]);
+    }
+}
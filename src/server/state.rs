@@ -5,13 +5,37 @@ use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 use tokio::time::{Duration, sleep};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 use crate::config::{
-    DEFAULT_CONCURRENT_DOWNLOADS, DEFAULT_RETRY_COUNT, HTTP_TIMEOUT_SECONDS, TASK_SAVE_DEBOUNCE_MS,
+    DEFAULT_CONCURRENT_DOWNLOADS, DEFAULT_MAX_ACTIVE_TASKS, DEFAULT_RETRY_COUNT,
+    HTTP_TIMEOUT_SECONDS, TASK_SAVE_DEBOUNCE_MS,
 };
+use crate::downloader::{
+    AdFilter, ClipOptions, OutputOptions, PeriodFilter, SkippedRange, VariantPolicy,
+    VerificationReport, VerifyOptions,
+};
+use crate::server::queue::{TaskPriority, TaskQueue};
+use crate::utils::{HttpOptions, SegmentCheck};
+use crate::utils::ffmpeg::detect_ffmpeg;
+use crate::utils::http::ProxyOptions;
+use crate::utils::rate_limit::{BandwidthRule, RateLimiter, effective_limit, global_rate_limiter};
+
+/// 服务重启后对中断任务（等待中、下载中、合并中）的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecoveryPolicy {
+    /// 自动继续下载，复用已下载的片段
+    #[default]
+    Resume,
+    /// 标记为已暂停，等待手动继续
+    Pause,
+    /// 标记为失败
+    Fail,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppSettings {
@@ -21,6 +45,45 @@ pub struct AppSettings {
     pub retry: usize,
     pub ffmpeg_path: String,
     pub timeout: u64,
+    /// 全局代理，任务可单独覆盖
+    #[serde(default, flatten)]
+    pub proxy: ProxyOptions,
+    /// 服务重启后对中断任务的处理方式
+    #[serde(default)]
+    pub recovery: RecoveryPolicy,
+    /// 同时运行的最大任务数，其余任务在队列中等待
+    #[serde(default = "default_max_active_tasks")]
+    pub max_active_tasks: usize,
+    /// 所有任务共享的总限速（字节/秒），0 表示不限速
+    #[serde(default)]
+    pub bandwidth_limit: u64,
+    /// 按时段覆盖总限速的规则，按顺序匹配第一条
+    #[serde(default)]
+    pub bandwidth_schedule: Vec<BandwidthRule>,
+}
+
+impl AppSettings {
+    /// 返回给客户端的副本，隐去代理密码
+    #[must_use]
+    pub fn redacted(&self) -> Self {
+        Self {
+            proxy: self.proxy.redacted(),
+            ..self.clone()
+        }
+    }
+
+    /// 当前时刻生效的总限速
+    pub fn current_bandwidth_limit(&self) -> u64 {
+        effective_limit(
+            self.bandwidth_limit,
+            &self.bandwidth_schedule,
+            Local::now().time(),
+        )
+    }
+}
+
+const fn default_max_active_tasks() -> usize {
+    DEFAULT_MAX_ACTIVE_TASKS
 }
 
 impl Default for AppSettings {
@@ -32,6 +95,11 @@ impl Default for AppSettings {
             retry: DEFAULT_RETRY_COUNT,
             ffmpeg_path: String::new(),
             timeout: HTTP_TIMEOUT_SECONDS,
+            proxy: ProxyOptions::default(),
+            recovery: RecoveryPolicy::default(),
+            max_active_tasks: DEFAULT_MAX_ACTIVE_TASKS,
+            bandwidth_limit: 0,
+            bandwidth_schedule: Vec::new(),
         }
     }
 }
@@ -48,6 +116,19 @@ pub enum TaskStatus {
     Completed,
     #[serde(rename = "failed", alias = "Failed")]
     Failed,
+    /// 已暂停，保留已下载的片段，可继续下载
+    #[serde(rename = "paused", alias = "Paused")]
+    Paused,
+    /// 已取消，临时文件已清理
+    #[serde(rename = "cancelled", alias = "Cancelled")]
+    Cancelled,
+}
+
+impl TaskStatus {
+    /// 任务是否可以暂停或取消
+    pub const fn is_active(&self) -> bool {
+        matches!(self, Self::Pending | Self::Downloading | Self::Merging)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -62,6 +143,98 @@ pub struct TaskInfo {
     pub error: Option<String>,
     pub output_file: Option<String>,
     pub file_size: Option<u64>,
+    /// 是否为直播录制任务
+    #[serde(default)]
+    pub live: bool,
+    /// 创建任务时指定的输出目录，继续下载时沿用
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    /// 直播录制的最长时长（秒）
+    #[serde(default)]
+    pub max_duration: Option<u64>,
+    /// 子流选择策略
+    #[serde(default)]
+    pub variant: VariantPolicy,
+    /// 优先选择的视频编码
+    #[serde(default)]
+    pub codec: Option<String>,
+    /// 音频语言偏好
+    #[serde(default)]
+    pub audio_languages: Vec<String>,
+    /// 字幕语言偏好
+    #[serde(default)]
+    pub subtitle_languages: Vec<String>,
+    /// 请求头、Cookie、Referer 与 User-Agent
+    #[serde(default, flatten)]
+    pub http: HttpOptions,
+    /// 是否为直传任务（服务重启后无法恢复）
+    #[serde(default)]
+    pub stream: bool,
+    /// 队列优先级
+    #[serde(default)]
+    pub priority: TaskPriority,
+    /// 任务限速（字节/秒）
+    #[serde(default)]
+    pub rate_limit: Option<u64>,
+    /// 输出格式、faststart、标题与转码预设
+    #[serde(default, flatten)]
+    pub output: OutputOptions,
+    /// 不连续时段过滤条件
+    #[serde(default)]
+    pub periods: PeriodFilter,
+    /// 广告/填充片段过滤条件
+    #[serde(default, flatten)]
+    pub ads: AdFilter,
+    /// 只下载的时间范围
+    #[serde(default, flatten)]
+    pub clip: ClipOptions,
+    /// 合并后的完整性校验选项
+    #[serde(default, flatten)]
+    pub verify: VerifyOptions,
+    /// 继续下载时对已存在片段的校验方式
+    #[serde(default)]
+    pub segment_check: SegmentCheck,
+    /// 下载时被过滤掉的片段范围
+    #[serde(default)]
+    pub skipped_segments: Vec<SkippedRange>,
+    /// 合并后的完整性校验结果
+    #[serde(default)]
+    pub verification: Option<VerificationReport>,
+}
+
+impl TaskInfo {
+    /// 返回给客户端的副本，隐去请求选项中的敏感信息；保存的任务保留原值以便继续下载
+    #[must_use]
+    pub fn redacted(&self) -> Self {
+        Self {
+            http: self.http.redacted(),
+            ..self.clone()
+        }
+    }
+
+    /// 按任务记录的参数重建下载请求，用于继续下载
+    pub fn to_request(&self) -> DownloadRequest {
+        DownloadRequest {
+            name: self.name.clone(),
+            url: self.url.clone(),
+            output_dir: self.output_dir.clone(),
+            live: self.live,
+            max_duration: self.max_duration,
+            variant: self.variant,
+            codec: self.codec.clone(),
+            audio_languages: self.audio_languages.clone(),
+            subtitle_languages: self.subtitle_languages.clone(),
+            http: self.http.clone(),
+            priority: self.priority,
+            rate_limit: self.rate_limit,
+            output: self.output.clone(),
+            periods: self.periods.clone(),
+            ads: self.ads.clone(),
+            clip: self.clip.clone(),
+            verify: self.verify.clone(),
+            segment_check: self.segment_check,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,6 +243,54 @@ pub struct DownloadRequest {
     pub url: String,
     #[serde(default)]
     pub output_dir: Option<String>,
+    /// 直播录制模式
+    #[serde(default)]
+    pub live: bool,
+    /// 直播录制的最长时长（秒）
+    #[serde(default)]
+    pub max_duration: Option<u64>,
+    /// 子流选择策略，如 highest、lowest、max-height=720、max-bandwidth=3000000、index=0
+    #[serde(default)]
+    pub variant: VariantPolicy,
+    /// 优先选择的视频编码（如 avc1、hvc1）
+    #[serde(default)]
+    pub codec: Option<String>,
+    /// 需要下载的音频语言，如 ["en", "ja"]，`all` 表示全部，为空时选择默认音轨
+    #[serde(default)]
+    pub audio_languages: Vec<String>,
+    /// 需要下载的字幕语言，`all` 表示全部，为空时不下载字幕
+    #[serde(default)]
+    pub subtitle_languages: Vec<String>,
+    /// 自定义请求头（`名称: 值`）、Cookie 字符串或 Cookie 文件、Referer 与 User-Agent 覆盖
+    #[serde(default, flatten)]
+    pub http: HttpOptions,
+    /// 队列优先级：low、normal、high
+    #[serde(default)]
+    pub priority: TaskPriority,
+    /// 任务限速（字节/秒），与全局限速同时生效
+    #[serde(default)]
+    pub rate_limit: Option<u64>,
+    /// 输出格式（mp4、ts、mkv、m4a、mp3）、faststart、标题与转码预设（copy、h264、h265、small），
+    /// 直传任务始终输出 MP4
+    #[serde(default, flatten)]
+    pub output: OutputOptions,
+    /// 按不连续时段过滤：all、index=0,2、skip=1、min-duration=60、longest
+    #[serde(default)]
+    pub periods: PeriodFilter,
+    /// 广告/填充片段过滤：skip_uri（正则）、skip_foreign_host、max_ad_period（秒）、skip_cue
+    #[serde(default, flatten)]
+    pub ads: AdFilter,
+    /// 只下载的时间范围：start/end 为时间戳（1:02:03.5）、时长（90、1h2m3s）或秒数，
+    /// trim 为 true 时合并时精确裁剪
+    #[serde(default, flatten)]
+    pub clip: ClipOptions,
+    /// 合并后的完整性校验：verify 为 off、warn（默认）或 strict，verify_tolerance 为允许的时长误差比例
+    #[serde(default, flatten)]
+    pub verify: VerifyOptions,
+    /// 继续下载时对已存在片段的校验：fast（默认）检查长度、记录的大小与首尾同步字节，
+    /// thorough 另外逐包检查同步字节与连续计数器
+    #[serde(default)]
+    pub segment_check: SegmentCheck,
 }
 
 #[derive(Clone)]
@@ -80,6 +301,22 @@ pub struct AppState {
     pub data_file: PathBuf,
     pub tasks_dirty: Arc<AtomicBool>,
     pub save_scheduled: Arc<AtomicBool>,
+    /// 直播录制任务的停止信号
+    pub stop_signals: Arc<RwLock<HashMap<String, CancellationToken>>>,
+    /// 运行中任务的取消令牌
+    pub running_tasks: Arc<RwLock<HashMap<String, RunningTask>>>,
+    /// 全局下载队列
+    pub queue: Arc<RwLock<TaskQueue>>,
+    /// 队列有新任务或空出名额时唤醒调度
+    pub queue_notify: Arc<Notify>,
+}
+
+/// 运行中的任务：取消令牌、任务限速器，以及被中断后任务应进入的状态
+#[derive(Debug, Clone)]
+pub struct RunningTask {
+    pub token: CancellationToken,
+    pub rate_limiter: RateLimiter,
+    pub interrupted_as: Option<TaskStatus>,
 }
 
 impl AppState {
@@ -91,6 +328,10 @@ impl AppState {
             data_file,
             tasks_dirty: Arc::new(AtomicBool::new(false)),
             save_scheduled: Arc::new(AtomicBool::new(false)),
+            stop_signals: Arc::new(RwLock::new(HashMap::new())),
+            running_tasks: Arc::new(RwLock::new(HashMap::new())),
+            queue: Arc::new(RwLock::new(TaskQueue::default())),
+            queue_notify: Arc::new(Notify::new()),
         }
     }
 
@@ -111,6 +352,7 @@ impl AppState {
             let settings: AppSettings = serde_json::from_str(&content)?;
             *self.settings.write().await = settings;
             log::info!("✅ 已加载设置: {:?}", self.settings.read().await);
+            self.apply_bandwidth_limit().await;
         } else {
             log::info!(
                 "📝 设置文件不存在，使用默认设置: {:?}",
@@ -195,12 +437,125 @@ impl AppState {
     }
 
     pub async fn update_settings(&self, new_settings: AppSettings) -> Result<()> {
-        *self.settings.write().await = new_settings;
+        let ffmpeg_path = {
+            let mut settings = self.settings.write().await;
+            let changed = settings.ffmpeg_path != new_settings.ffmpeg_path;
+            *settings = new_settings;
+            changed.then(|| settings.ffmpeg_path.clone())
+        };
         self.save_settings().await?;
+        self.apply_bandwidth_limit().await;
+        // FFmpeg 路径变化后重新探测，之后的任务使用新路径
+        if let Some(path) = ffmpeg_path {
+            detect_ffmpeg(&path).await;
+        }
+        // 最大任务数可能调大，尝试启动排队中的任务
+        self.queue_notify.notify_one();
         Ok(())
     }
 
+    /// 按设置与当前时段更新全局限速，正在下载的任务随即生效
+    pub async fn apply_bandwidth_limit(&self) {
+        let limit = self.settings.read().await.current_bandwidth_limit();
+        let limiter = global_rate_limiter();
+        if limiter.rate() != limit {
+            log::info!("🐢 全局限速调整为: {limit} 字节/秒（0 表示不限速）");
+            limiter.set_rate(limit);
+        }
+    }
+
+    /// 修改任务限速，任务运行中时立即生效
+    pub async fn set_task_rate_limit(&self, id: &str, rate_limit: Option<u64>) -> bool {
+        {
+            let mut tasks = self.tasks.write().await;
+            let Some(task) = tasks.get_mut(id) else {
+                return false;
+            };
+            task.rate_limit = rate_limit;
+            task.updated_at = Local::now();
+        }
+        if let Some(task) = self.running_tasks.read().await.get(id) {
+            task.rate_limiter.set_rate(rate_limit.unwrap_or(0));
+        }
+        self.schedule_save();
+        true
+    }
+
+    /// 将任务加入下载队列并唤醒调度
+    pub async fn enqueue_task(&self, id: &str, priority: TaskPriority) {
+        self.queue.write().await.push(id, priority);
+        self.queue_notify.notify_one();
+    }
+
+    /// 运行中的任务数未达上限时取出下一个排队的任务
+    pub async fn next_queued_task(&self) -> Option<String> {
+        let max_active = self.settings.read().await.max_active_tasks.max(1);
+        self.queue.write().await.pop(max_active)
+    }
+
+    /// 任务结束后释放队列名额
+    pub async fn release_task_slot(&self, id: &str) {
+        if self.queue.write().await.release(id) {
+            self.queue_notify.notify_one();
+        }
+    }
+
+    /// 将任务移出等待队列（暂停、取消或删除时）
+    pub async fn dequeue_task(&self, id: &str) -> bool {
+        self.queue.write().await.remove(id)
+    }
+
+    pub async fn move_task_to_top(&self, id: &str) -> bool {
+        self.queue.write().await.move_to_top(id)
+    }
+
+    /// 重排等待队列，包含不在队列中的任务时返回该任务 ID
+    pub async fn reorder_queue(&self, ids: &[String]) -> std::result::Result<(), String> {
+        self.queue.write().await.reorder(ids)
+    }
+
+    /// 修改任务优先级，任务在队列中等待时按新优先级重新排队
+    pub async fn set_task_priority(&self, id: &str, priority: TaskPriority) -> bool {
+        {
+            let mut tasks = self.tasks.write().await;
+            let Some(task) = tasks.get_mut(id) else {
+                return false;
+            };
+            task.priority = priority;
+            task.updated_at = Local::now();
+        }
+        {
+            let mut queue = self.queue.write().await;
+            if queue.contains(id) {
+                queue.push(id, priority);
+            }
+        }
+        self.schedule_save();
+        true
+    }
+
+    /// 排队中与运行中的任务，排队任务按调度顺序
+    pub async fn queued_tasks(&self) -> (Vec<TaskInfo>, Vec<TaskInfo>) {
+        let (pending_ids, active_ids) = {
+            let queue = self.queue.read().await;
+            (queue.pending_ids(), queue.active_ids())
+        };
+        let tasks = self.tasks.read().await;
+        let collect =
+            |ids: Vec<String>| ids.iter().filter_map(|id| tasks.get(id).cloned()).collect();
+        (collect(pending_ids), collect(active_ids))
+    }
+
     pub async fn add_task(&self, request: DownloadRequest) -> Result<String> {
+        self.insert_task(request, false).await
+    }
+
+    /// 创建直传任务，输出直接写入 HTTP 响应
+    pub async fn add_stream_task(&self, request: DownloadRequest) -> Result<String> {
+        self.insert_task(request, true).await
+    }
+
+    async fn insert_task(&self, request: DownloadRequest, stream: bool) -> Result<String> {
         let id = Uuid::new_v4().to_string();
         let now = Local::now();
 
@@ -215,6 +570,25 @@ impl AppState {
             error: None,
             output_file: None,
             file_size: None,
+            live: request.live,
+            output_dir: request.output_dir,
+            max_duration: request.max_duration,
+            variant: request.variant,
+            codec: request.codec,
+            audio_languages: request.audio_languages,
+            subtitle_languages: request.subtitle_languages,
+            http: request.http,
+            stream,
+            priority: request.priority,
+            rate_limit: request.rate_limit,
+            output: request.output,
+            periods: request.periods,
+            ads: request.ads,
+            clip: request.clip,
+            verify: request.verify,
+            segment_check: request.segment_check,
+            skipped_segments: Vec::new(),
+            verification: None,
         };
 
         {
@@ -274,6 +648,136 @@ impl AppState {
         Ok(())
     }
 
+    /// 记录下载时被过滤掉的片段范围
+    pub async fn update_task_skipped(&self, id: &str, skipped: Vec<SkippedRange>) -> Result<()> {
+        {
+            let mut tasks = self.tasks.write().await;
+            if let Some(task) = tasks.get_mut(id) {
+                task.skipped_segments = skipped;
+                task.updated_at = Local::now();
+            }
+        }
+        self.schedule_save();
+        Ok(())
+    }
+
+    pub async fn update_task_verification(
+        &self,
+        id: &str,
+        verification: VerificationReport,
+    ) -> Result<()> {
+        {
+            let mut tasks = self.tasks.write().await;
+            if let Some(task) = tasks.get_mut(id) {
+                task.verification = Some(verification);
+                task.updated_at = Local::now();
+            }
+        }
+        self.schedule_save();
+        Ok(())
+    }
+
+    /// 为直播录制任务注册停止信号
+    pub async fn register_stop_signal(&self, id: &str) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.stop_signals
+            .write()
+            .await
+            .insert(id.to_string(), token.clone());
+        token
+    }
+
+    pub async fn remove_stop_signal(&self, id: &str) {
+        self.stop_signals.write().await.remove(id);
+    }
+
+    /// 触发直播录制任务的停止信号，任务不在录制中时返回 false
+    pub async fn stop_recording(&self, id: &str) -> bool {
+        self.stop_signals.read().await.get(id).is_some_and(|token| {
+            token.cancel();
+            true
+        })
+    }
+
+    /// 登记运行中的任务，返回用于暂停/取消的令牌及任务限速器
+    pub async fn register_running_task(&self, id: &str, rate_limit: Option<u64>) -> RunningTask {
+        let task = RunningTask {
+            token: CancellationToken::new(),
+            rate_limiter: RateLimiter::new(rate_limit.unwrap_or(0)),
+            interrupted_as: None,
+        };
+        self.running_tasks
+            .write()
+            .await
+            .insert(id.to_string(), task.clone());
+        task
+    }
+
+    /// 任务结束时注销，返回被中断后应进入的状态（未被中断时为 `None`）
+    pub async fn finish_running_task(&self, id: &str) -> Option<TaskStatus> {
+        self.running_tasks
+            .write()
+            .await
+            .remove(id)
+            .and_then(|task| task.interrupted_as)
+    }
+
+    /// 中断运行中的任务并将其标记为 `status`（暂停或取消），任务未在运行时返回 false
+    pub async fn interrupt_task(&self, id: &str, status: TaskStatus) -> bool {
+        let interrupted = {
+            let mut running = self.running_tasks.write().await;
+            running.get_mut(id).is_some_and(|task| {
+                task.interrupted_as = Some(status.clone());
+                task.token.cancel();
+                true
+            })
+        };
+        if interrupted {
+            let _ = self.update_task_status(id, status, None).await;
+        }
+        interrupted
+    }
+
+    /// 上次运行时未结束的任务：下载中、合并中，以及尚未开始的普通任务
+    ///
+    /// 等待中的直传任务仍可由客户端发起下载，不算作中断。
+    pub async fn interrupted_tasks(&self) -> Vec<TaskInfo> {
+        let mut result: Vec<TaskInfo> = self
+            .tasks
+            .read()
+            .await
+            .values()
+            .filter(|t| t.status.is_active() && !(t.stream && t.status == TaskStatus::Pending))
+            .cloned()
+            .collect();
+        result.sort_by_key(|t| t.created_at);
+        result
+    }
+
+    pub async fn is_task_running(&self, id: &str) -> bool {
+        self.running_tasks.read().await.contains_key(id)
+    }
+
+    /// 任务处于 `from` 中的某个状态时切换为 `to`，返回切换前的任务
+    pub async fn transition_task(
+        &self,
+        id: &str,
+        from: &[TaskStatus],
+        to: TaskStatus,
+    ) -> Option<TaskInfo> {
+        let previous = {
+            let mut tasks = self.tasks.write().await;
+            let task = tasks.get_mut(id).filter(|t| from.contains(&t.status))?;
+            let previous = task.clone();
+            task.status = to;
+            task.error = None;
+            task.updated_at = Local::now();
+            previous
+        };
+        self.schedule_save();
+        Some(previous)
+    }
+
     pub async fn get_task(&self, id: &str) -> Option<TaskInfo> {
         let tasks = self.tasks.read().await;
         tasks.get(id).cloned()
@@ -281,7 +785,7 @@ impl AppState {
 
     pub async fn get_all_tasks(&self) -> Vec<TaskInfo> {
         let mut result: Vec<TaskInfo> = self.tasks.read().await.values().cloned().collect();
-        result.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        result.sort_by_key(|t| std::cmp::Reverse(t.created_at));
         result
     }
 
@@ -294,7 +798,7 @@ impl AppState {
             .filter(|t| t.status == status)
             .cloned()
             .collect();
-        result.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        result.sort_by_key(|t| std::cmp::Reverse(t.created_at));
         result
     }
 
@@ -319,7 +823,7 @@ impl AppState {
             .map(|(_, task)| task.clone())
             .collect();
         let mut result = result;
-        result.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        result.sort_by_key(|t| std::cmp::Reverse(t.created_at));
         result
     }
 
@@ -343,6 +847,14 @@ impl AppState {
                 .values()
                 .filter(|t| t.status == TaskStatus::Failed)
                 .count(),
+            paused: tasks
+                .values()
+                .filter(|t| t.status == TaskStatus::Paused)
+                .count(),
+            cancelled: tasks
+                .values()
+                .filter(|t| t.status == TaskStatus::Cancelled)
+                .count(),
             total_size: tasks.values().filter_map(|t| t.file_size).sum(),
         }
     }
@@ -355,5 +867,7 @@ pub struct TaskStats {
     pub downloading: usize,
     pub completed: usize,
     pub failed: usize,
+    pub paused: usize,
+    pub cancelled: usize,
     pub total_size: u64,
 }
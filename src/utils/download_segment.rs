@@ -1,33 +1,44 @@
-﻿use crate::config::{
-    HTTP_CONNECT_TIMEOUT_SECONDS, HTTP_TIMEOUT_SECONDS, POOL_IDLE_TIMEOUT_SECONDS,
-    POOL_MAX_IDLE_PER_HOST, TCP_KEEPALIVE_SECONDS, WRITE_BUFFER_SIZE,
-};
+﻿use crate::config::{PROGRESS_REPORT_INTERVAL_MS, WRITE_BUFFER_SIZE};
 use crate::downloader::{
-    Args, DownloadStats, decrypt_segment, extract_encryption_key, merge_segments,
-    process_download_tasks,
+    AdFilter, Args, ClipOptions, ContainerFormat, DownloadStats, InitSections, KeyCache,
+    Mp4Stream, OutputOptions, PeriodFilter, Remuxer, RenditionInfo, RenditionKind,
+    OutputFormat, RenditionTrack, SegmentDecryptor, SegmentKey, SelectedRendition, SkipReason,
+    TrimWindow, VariantInfo, VariantPolicy, VerifyMode, VerifyOptions,
+    decrypt_segment, list_renditions, list_variants, merge_requires_ffmpeg, merge_segments,
+    merge_track_segments, native_merge_error, process_download_tasks, resolve_segment_keys,
+    retain_segments, select_renditions, select_variant, skipped_ranges, split_periods,
+    track_format, verify_output,
 };
 use crate::error::{DownloadError, Result};
 use bytes::Bytes;
 use tokio::sync::mpsc;
 use crate::validation;
+use futures::StreamExt;
 use futures::future::join_all;
+use futures::stream::BoxStream;
 use indicatif::{ProgressBar, ProgressStyle};
-use log::{error, info};
-use m3u8_rs::{MediaPlaylist, MediaSegment};
+use log::{error, info, warn};
+use m3u8_rs::{KeyMethod, MediaPlaylist, MediaSegment};
 use reqwest::Client;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self};
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::process::Command;
-use tokio::time::sleep;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{Instant, sleep};
+use tokio_util::sync::CancellationToken;
 use url::Url;
 // AES解密相关
+use crate::utils::http::{HttpOptions, build_http_client};
+use crate::utils::ffmpeg::require_ffmpeg;
+use crate::utils::rate_limit::{RateLimiter, global_rate_limiter};
 use crate::utils::json_loader::load_download_tasks_from_json;
-use crate::utils::{is_valid_ts_file, resolve_url};
+use crate::utils::{
+    SegmentCheck, SegmentFormat, SegmentSize, check_segment_file, get_segment_filename,
+    load_segment_sizes, range_header_value, record_segment_size, resolve_byte_range_offsets,
+    resolve_url,
+};
 
 /// 从JSON文件加载并处理下载任务（并发版）
 pub async fn load_and_process_download_tasks(json_path: &str, max_concurrent: usize) -> Result<()> {
@@ -38,6 +49,59 @@ pub async fn load_and_process_download_tasks(json_path: &str, max_concurrent: us
     process_download_tasks(&tasks, max_concurrent).await
 }
 
+/// 获取播放列表文本
+pub async fn fetch_playlist_text(client: &Client, url: &str) -> Result<String> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| DownloadError::http(0, format!("请求失败: {e}")))?;
+    if !response.status().is_success() {
+        return Err(DownloadError::http(response.status().as_u16(), url));
+    }
+    response
+        .text()
+        .await
+        .map_err(|e| DownloadError::parse(format!("读取响应失败: {e}")))
+}
+
+/// 获取主播放列表中的子流与备选音频/字幕列表，媒体播放列表返回空列表
+pub async fn fetch_variants(
+    url: &str,
+    http: &HttpOptions,
+) -> Result<(Vec<VariantInfo>, Vec<RenditionInfo>)> {
+    validation::validate_url(url)?;
+    let base_url =
+        Url::parse(url).map_err(|e| DownloadError::parse(format!("URL解析失败: {e}")))?;
+    let client = build_http_client(&base_url, http)?;
+    let content = fetch_playlist_text(&client, url).await?;
+
+    Ok(match m3u8_rs::parse_master_playlist(content.as_bytes()) {
+        Ok((_, master)) => (
+            list_variants(&master.variants),
+            list_renditions(&master.alternatives),
+        ),
+        Err(_) => (Vec::new(), Vec::new()),
+    })
+}
+
+/// 在取消令牌触发时中断 `future` 并返回 [`DownloadError::Cancelled`]
+///
+/// 被中断的 future 会被直接丢弃，其中进行中的请求与 FFmpeg 子进程随之终止。
+pub async fn cancellable<T>(
+    token: Option<&CancellationToken>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match token {
+        Some(token) => tokio::select! {
+            biased;
+            () = token.cancelled() => Err(DownloadError::Cancelled),
+            result = future => result,
+        },
+        None => future.await,
+    }
+}
+
 pub type ProgressCallback = Arc<dyn Fn(f64) + Send + Sync>;
 pub type StatusCallback = Arc<dyn Fn(&str) + Send + Sync>;
 
@@ -56,6 +120,57 @@ pub struct M3u8Downloader {
     pub status_callback: Option<StatusCallback>,
     pub stream_output: Option<mpsc::Sender<std::result::Result<Bytes, String>>>,
     pub current_base_url: Arc<tokio::sync::Mutex<Url>>,
+    pub live: bool,
+    pub max_duration: Option<Duration>,
+    pub stop_signal: Option<CancellationToken>,
+    pub cancel_token: Option<CancellationToken>,
+    pub variant: VariantPolicy,
+    pub codec: Option<String>,
+    pub audio_languages: Vec<String>,
+    pub subtitle_languages: Vec<String>,
+    pub output: OutputOptions,
+    pub remuxer: Remuxer,
+    /// 按不连续时段过滤片段
+    pub periods: PeriodFilter,
+    /// 广告/填充片段过滤
+    pub ads: AdFilter,
+    /// 只下载的时间范围
+    pub clip: ClipOptions,
+    /// 合并后的完整性校验
+    pub verify: VerifyOptions,
+    /// 继续下载时对已存在片段的校验方式
+    pub segment_check: SegmentCheck,
+    /// 下载目录中记录的片段大小，首次校验已存在的片段时读取
+    pub recorded_sizes: Arc<tokio::sync::OnceCell<HashMap<String, SegmentSize>>>,
+    /// 片段下载依次经过的限速器：全局限速及任务限速
+    pub rate_limiters: Vec<RateLimiter>,
+}
+
+/// 按数据块读取中的响应体，已按请求的字节区间截取
+struct ResponseBody {
+    chunks: BoxStream<'static, reqwest::Result<Bytes>>,
+    /// 服务端忽略 Range 时需要丢弃的开头字节数
+    skip: u64,
+    /// 区间内尚未读取的字节数，`None` 表示读到响应结束
+    remaining: Option<u64>,
+    /// 预计的总字节数，未知时无法计算片段内进度
+    expected: Option<u64>,
+    received: u64,
+}
+
+impl ResponseBody {
+    #[allow(clippy::cast_precision_loss)]
+    fn fraction(&self) -> Option<f64> {
+        self.expected
+            .filter(|&expected| expected > 0)
+            .map(|expected| (self.received as f64 / expected as f64).min(1.0))
+    }
+
+    fn capacity_hint(&self) -> usize {
+        self.expected
+            .and_then(|len| usize::try_from(len).ok())
+            .unwrap_or_default()
+    }
 }
 
 impl M3u8Downloader {
@@ -82,32 +197,12 @@ impl M3u8Downloader {
             fs::create_dir_all(&output_dir)?;
         }
 
-        // 从URL提取origin作为Referer
-        let origin = format!(
-            "{}://{}",
-            base_url.scheme(),
-            base_url.host_str().unwrap_or("")
-        );
-        let referer = format!("{origin}/");
-
-        let client = Client::builder()
-            .timeout(Duration::from_secs(HTTP_TIMEOUT_SECONDS))
-            .connect_timeout(Duration::from_secs(HTTP_CONNECT_TIMEOUT_SECONDS))
-            .pool_idle_timeout(Duration::from_secs(POOL_IDLE_TIMEOUT_SECONDS))
-            .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
-            .tcp_keepalive(Duration::from_secs(TCP_KEEPALIVE_SECONDS))
-            .tcp_nodelay(true)
-
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36")
-            .default_headers({
-                let mut headers = reqwest::header::HeaderMap::new();
-                headers.insert(reqwest::header::REFERER, reqwest::header::HeaderValue::from_str(&referer).unwrap());
-                headers.insert(reqwest::header::ACCEPT, reqwest::header::HeaderValue::from_static("*/*"));
-                headers.insert(reqwest::header::ACCEPT_LANGUAGE, reqwest::header::HeaderValue::from_static("zh-CN,zh;q=0.9,en;q=0.8"));
-                headers.insert("Origin".parse::<reqwest::header::HeaderName>().unwrap(), reqwest::header::HeaderValue::from_str(&origin).unwrap());
-                headers
-            })
-            .build()?;
+        let client = build_http_client(&base_url, &args.http)?;
+
+        let mut rate_limiters = vec![global_rate_limiter().clone()];
+        if let Some(rate) = args.rate_limit {
+            rate_limiters.push(RateLimiter::new(rate));
+        }
 
         let progress_bar = ProgressBar::new(100);
         progress_bar.set_style(
@@ -132,9 +227,35 @@ impl M3u8Downloader {
             status_callback: None,
             stream_output: None,
             current_base_url: Arc::new(tokio::sync::Mutex::new(base_url)),
+            live: args.live,
+            max_duration: args.max_duration.map(Duration::from_secs),
+            stop_signal: None,
+            cancel_token: None,
+            variant: args.variant,
+            codec: args.codec,
+            audio_languages: args.audio_languages,
+            subtitle_languages: args.subtitle_languages,
+            output: args.output,
+            remuxer: args.remuxer,
+            periods: args.periods,
+            ads: args.ads,
+            clip: args.clip,
+            verify: args.verify,
+            segment_check: args.segment_check,
+            recorded_sizes: Arc::default(),
+            rate_limiters,
         })
     }
 
+    /// 合并后的输出文件路径
+    pub fn output_path(&self) -> PathBuf {
+        self.output_dir.join(format!(
+            "{}.{}",
+            self.output_filename,
+            self.output.format.extension()
+        ))
+    }
+
     pub fn with_progress_callback(mut self, callback: ProgressCallback) -> Self {
         self.progress_callback = Some(callback);
         self
@@ -150,19 +271,45 @@ impl M3u8Downloader {
         self
     }
 
+    /// 直播录制的停止信号，触发后停止刷新并合并已录制的片段
+    pub fn with_stop_signal(mut self, token: CancellationToken) -> Self {
+        self.stop_signal = Some(token);
+        self
+    }
+
+    /// 暂停/取消任务的令牌，触发后中断所有请求与合并过程，保留已下载的片段
+    pub fn with_cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// 追加一个限速器，速率可在下载过程中通过其克隆调整
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiters.push(limiter);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel_token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
     fn notify_status(&self, status: &str) {
         if let Some(callback) = &self.status_callback {
             callback(status);
         }
     }
 
-    pub async fn download(&self) -> Result<()> {
+    /// 获取媒体播放列表内容，以及按语言偏好选中的需要单独下载的音频/字幕
+    async fn fetch_media_playlist(&self) -> Result<(String, Vec<SelectedRendition>)> {
         info!("正在获取 M3U8 播放列表...");
 
         // 下载并解析 M3U8 文件
         let mut m3u8_content = self.download_text(self.base_url.as_ref()).await?;
+        let mut renditions = Vec::new();
 
-        // 检查是否是主播放列表，如果是则选择最高带宽的流
+        // 检查是否是主播放列表，如果是则按选择策略选择子流
         if let Ok((_, master)) = m3u8_rs::parse_master_playlist(m3u8_content.as_bytes())
             && !master.variants.is_empty()
         {
@@ -181,14 +328,24 @@ impl M3u8Downloader {
                 );
             }
 
-            // 选择最高带宽的流
-            let best_variant = master.variants.iter().max_by_key(|v| v.bandwidth).unwrap();
+            let best_variant =
+                select_variant(&master.variants, self.variant, self.codec.as_deref())?;
 
             info!(
-                "选择流: {} (带宽: {})",
-                best_variant.uri, best_variant.bandwidth
+                "选择流: {} (带宽: {}, 策略: {})",
+                best_variant.uri, best_variant.bandwidth, self.variant
             );
 
+            renditions = select_renditions(
+                &master.alternatives,
+                best_variant,
+                &self.audio_languages,
+                &self.subtitle_languages,
+            );
+            for rendition in &mut renditions {
+                rendition.uri = resolve_url(&self.base_url, &rendition.uri)?;
+            }
+
             // 下载子播放列表
             let sub_url = resolve_url(&self.base_url, &best_variant.uri)?;
             m3u8_content = self.download_text(&sub_url).await?;
@@ -199,17 +356,43 @@ impl M3u8Downloader {
             *current_url = new_base_url;
         }
 
-        let playlist = Self::parse_m3u8(&m3u8_content)?;
+        Ok((m3u8_content, renditions))
+    }
 
-        info!("发现 {} 个视频片段", playlist.segments.len());
+    pub async fn download(&self) -> Result<()> {
+        let result = cancellable(self.cancel_token.as_ref(), self.download_playlist()).await;
+        // 片段任务因取消而失败时，错误可能先于取消信号返回
+        if self.is_cancelled() {
+            info!("任务 {} 已中断", self.output_filename);
+            return Err(DownloadError::Cancelled);
+        }
+        result
+    }
 
-        // 更新统计信息
+    async fn download_playlist(&self) -> Result<()> {
+        let (m3u8_content, renditions) = self.fetch_media_playlist().await?;
+        let mut playlist = Self::parse_m3u8(&m3u8_content)?;
+        resolve_byte_range_offsets(&mut playlist.segments);
+
+        if !renditions.is_empty() && (self.live || self.stream_output.is_some()) {
+            warn!("直播录制与直传模式暂不支持单独的音频/字幕轨道，已忽略");
+        }
+        if self.live
+            && (self.periods != PeriodFilter::All || self.ads.is_enabled() || self.clip.is_enabled())
         {
-            let mut stats = self.stats.lock().await;
-            stats.total_segments = playlist.segments.len();
+            warn!("直播录制不支持按时段、广告或时间范围过滤，已忽略");
+        }
+        if self.clip.trim && self.stream_output.is_some() {
+            warn!("直传模式不支持精确裁剪，只按时间范围选取片段");
+        }
+        if self.live {
+            return self.record_live(playlist).await;
+        }
+        if !playlist.end_list {
+            info!("播放列表没有 EXT-X-ENDLIST，仅下载当前可见片段（可使用直播录制模式）");
         }
 
-        self.progress_bar.set_length(playlist.segments.len() as u64);
+        info!("发现 {} 个视频片段", playlist.segments.len());
 
         // 获取当前base_url用于解析
         let current_url = {
@@ -217,38 +400,178 @@ impl M3u8Downloader {
             url.clone()
         };
 
-        // 检查加密 - 从播放列表内容中提取密钥信息
-        let key_data = extract_encryption_key(&m3u8_content, &self.client, &current_url).await?;
+        // 检查加密 - 按片段解析生效的 EXT-X-KEY，支持密钥轮换
+        let segment_keys = resolve_segment_keys(
+            &playlist.segments,
+            playlist.media_sequence,
+            &self.client,
+            &current_url,
+            &mut KeyCache::new(),
+        )
+        .await?;
+
+        // 密钥（含按序列号推算的 IV）解析完成后再去掉被过滤的时段与广告片段
+        let period_count = split_periods(&playlist.segments).len();
+        let reasons = self.skip_reasons(&playlist.segments, &current_url, true)?;
+        let skipped = skipped_ranges(&playlist.segments, &reasons);
+        let keep: Vec<bool> = reasons.iter().map(Option::is_none).collect();
+        let indices: Vec<usize> = (0..keep.len()).filter(|&i| keep[i]).collect();
+        let trim = self.clip.trim_window(&playlist.segments, &keep);
+        if trim.is_some() && self.remuxer == Remuxer::Native {
+            return Err(DownloadError::remux("精确裁剪需要使用FFmpeg"));
+        }
+        let segment_keys = if skipped.is_empty() {
+            segment_keys
+        } else {
+            for range in &skipped {
+                info!(
+                    "去掉片段 {}..{}（{:.1}s 起，共 {:.1}s）：{}",
+                    range.first_segment,
+                    range.first_segment + range.segment_count,
+                    range.start,
+                    range.duration,
+                    range.reason
+                );
+            }
+            let (segments, keys) = retain_segments(playlist.segments, segment_keys, &keep)?;
+            playlist.segments = segments;
+            info!("过滤后保留 {} 个片段", playlist.segments.len());
+            keys
+        };
+        let kept_periods = split_periods(&playlist.segments).len();
 
-        if key_data.is_some() {
-            info!("检测到加密流，已获取密钥");
+        // 更新统计信息
+        {
+            let mut stats = self.stats.lock().await;
+            stats.total_segments = playlist.segments.len();
+            stats.skipped = skipped;
         }
 
+        self.progress_bar.set_length(playlist.segments.len() as u64);
+
+        // fMP4 / CMAF 播放列表需要先下载初始化段
+        let container = ContainerFormat::detect(&playlist.segments);
+        let mut init_sections = InitSections::from_segments(&playlist.segments);
+
+        // 合并需要FFmpeg但不可用时，在下载片段前直接失败
+        let init_count = init_sections.maps.len();
+        let requires_ffmpeg = if self.stream_output.is_some() {
+            Mp4Stream::requires_ffmpeg(self.remuxer, container, init_count)
+        } else {
+            let has_tracks = renditions
+                .iter()
+                .any(|r| r.kind != RenditionKind::Subtitles || self.output.format.supports_subtitles());
+            if self.remuxer == Remuxer::Native
+                && let Some(e) = native_merge_error(
+                    container,
+                    init_count,
+                    kept_periods,
+                    has_tracks,
+                    &self.output,
+                    trim.is_some(),
+                )
+            {
+                return Err(e);
+            }
+            merge_requires_ffmpeg(
+                container,
+                init_count,
+                kept_periods,
+                has_tracks,
+                &self.output,
+                self.remuxer,
+                trim.is_some(),
+            )
+        };
+        if requires_ffmpeg {
+            require_ffmpeg()?;
+        }
+
+        if container == ContainerFormat::Fmp4 {
+            info!("检测到fMP4片段，共 {} 个初始化段", init_sections.maps.len());
+            init_sections.data = self
+                .download_init_sections(&init_sections, &segment_keys, &current_url)
+                .await?;
+        }
+
+        let segment_keys = Arc::new(segment_keys);
         let segments = Arc::new(playlist.segments);
 
         if self.stream_output.is_some() {
             info!("开始直传模式下载片段...{}", segments.len());
-            self.stream_segments_to_mp4(segments, key_data).await?;
+            self.stream_segments_to_mp4(segments, segment_keys, &init_sections, container)
+                .await?;
             self.notify_status("completed");
             info!("直传下载完成");
             return Ok(());
         }
 
+        // 音频/字幕轨道与视频片段同时下载
+        let rendition_handles: Vec<_> = renditions
+            .into_iter()
+            .enumerate()
+            .map(|(i, rendition)| {
+                let downloader = self.rendition_downloader(i, &rendition);
+                tokio::spawn(async move {
+                    let downloader = downloader?;
+                    let token = downloader.cancel_token.clone();
+                    let rendition = downloader.download_rendition(rendition, period_count);
+                    cancellable(token.as_ref(), rendition).await
+                })
+            })
+            .collect();
+
         // 并行下载片段
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(self.concurrent));
         info!("开始下载片段...{}", segments.len());
+        self.download_all_segments(&segments, &segment_keys, container.into())
+            .await?;
+
+        let mut tracks = Vec::with_capacity(rendition_handles.len());
+        for handle in rendition_handles {
+            let track = handle.await.map_err(|e| {
+                DownloadError::task(&self.output_filename, format!("轨道下载任务异常: {e}"))
+            })??;
+            tracks.push(track);
+        }
+
+        self.progress_bar.finish_with_message("所有片段下载完成");
+
+        // 合并文件
+        info!("正在合并视频文件...");
+        self.notify_status("merging");
+        self.merge_segments(&segments, &init_sections, container, &tracks, trim)
+            .await?;
+        self.verify_output(&segments, &indices, trim, kept_periods > 1)
+            .await?;
+        self.notify_status("completed");
+
+        info!("下载完成！输出文件: {}", self.output_path().display());
+        Ok(())
+    }
+
+    /// 并行下载全部片段到下载目录
+    async fn download_all_segments(
+        &self,
+        segments: &Arc<Vec<MediaSegment>>,
+        segment_keys: &Arc<Vec<Option<SegmentKey>>>,
+        format: SegmentFormat,
+    ) -> Result<()> {
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(self.concurrent));
         let download_tasks: Vec<_> = (0..segments.len())
             .map(|i| {
                 let downloader = self.clone();
                 let segments = segments.clone();
-                let key_data = key_data.clone();
+                let segment_keys = segment_keys.clone();
                 let semaphore = semaphore.clone();
 
                 tokio::spawn(async move {
-                    let _permit = semaphore.acquire().await.unwrap();
-                    downloader
-                        .download_segment(i, &segments[i], key_data.as_ref())
-                        .await
+                    cancellable(downloader.cancel_token.as_ref(), async {
+                        let _permit = semaphore.acquire().await.unwrap();
+                        downloader
+                            .download_segment(i, &segments[i], segment_keys[i].as_ref(), format)
+                            .await
+                    })
+                    .await
                 })
             })
             .collect();
@@ -265,26 +588,349 @@ impl M3u8Downloader {
             }
         }
 
-        self.progress_bar.finish_with_message("所有片段下载完成");
+        Ok(())
+    }
 
-        // 合并文件
-        info!("正在合并视频文件...");
-        self.notify_status("merging");
-        self.merge_segments(&segments).await?;
-        self.notify_status("completed");
+    /// 为单独的音频/字幕轨道创建下载器：使用下载目录下的子目录，不上报进度
+    fn rendition_downloader(&self, index: usize, rendition: &SelectedRendition) -> Result<Self> {
+        let url = Url::parse(&rendition.uri)?;
+        let download_dir = self
+            .download_dir
+            .join(format!("{}_{index}", rendition.kind.dir_prefix()));
+        if !download_dir.exists() {
+            fs::create_dir_all(&download_dir)?;
+        }
+
+        let mut downloader = self.clone();
+        downloader.base_url = url.clone();
+        downloader.current_base_url = Arc::new(tokio::sync::Mutex::new(url));
+        downloader.download_dir = download_dir;
+        downloader.recorded_sizes = Arc::default();
+        downloader.stats = Arc::new(tokio::sync::Mutex::new(DownloadStats::new(0)));
+        downloader.progress_bar = ProgressBar::hidden();
+        downloader.progress_callback = None;
+        downloader.status_callback = None;
+        downloader.stream_output = None;
+        Ok(downloader)
+    }
 
+    /// 逐个片段判断是否需要去掉：不在时间范围内、广告检测命中，
+    /// 或 `by_period` 时所在时段被时段过滤条件去掉
+    fn skip_reasons(
+        &self,
+        segments: &[MediaSegment],
+        base_url: &Url,
+        by_period: bool,
+    ) -> Result<Vec<Option<SkipReason>>> {
+        let mut reasons = self.ads.detect(segments, base_url)?;
+        if self.clip.is_enabled() {
+            let inside = self.clip.overlapping(segments)?;
+            for (reason, inside) in reasons.iter_mut().zip(inside) {
+                if !inside {
+                    *reason = Some(SkipReason::OutsideClip);
+                }
+            }
+        }
+        if by_period && self.periods != PeriodFilter::All {
+            let keep = self.periods.kept_segments(segments);
+            for (reason, kept) in reasons.iter_mut().zip(keep) {
+                if !kept {
+                    reason.get_or_insert(SkipReason::PeriodFilter);
+                }
+            }
+        }
+        Ok(reasons)
+    }
+
+    /// 下载单独的音频/字幕轨道并合并为一个文件
+    ///
+    /// 轨道的不连续时段数与视频（`period_count`）一致时按相同的条件过滤时段。
+    async fn download_rendition(
+        self,
+        rendition: SelectedRendition,
+        period_count: usize,
+    ) -> Result<RenditionTrack> {
+        let content = self.download_text(&rendition.uri).await?;
+        let mut playlist = Self::parse_m3u8(&content)?;
+        resolve_byte_range_offsets(&mut playlist.segments);
         info!(
-            "下载完成！输出文件: {}/{}.ts",
-            self.download_dir.display(),
-            self.output_filename
+            "开始下载轨道 {}，共 {} 个片段",
+            rendition.name,
+            playlist.segments.len()
         );
+
+        let segment_keys = resolve_segment_keys(
+            &playlist.segments,
+            playlist.media_sequence,
+            &self.client,
+            &self.base_url,
+            &mut KeyCache::new(),
+        )
+        .await?;
+        let same_periods = split_periods(&playlist.segments).len() == period_count;
+        if !same_periods && self.periods != PeriodFilter::All {
+            warn!("轨道 {} 的不连续时段与视频不一致，未按时段过滤", rendition.name);
+        }
+        let reasons = self.skip_reasons(&playlist.segments, &self.base_url, same_periods)?;
+        let segment_keys = if reasons.iter().any(Option::is_some) {
+            let keep: Vec<bool> = reasons.iter().map(Option::is_none).collect();
+            let (segments, keys) = retain_segments(playlist.segments, segment_keys, &keep)?;
+            playlist.segments = segments;
+            keys
+        } else {
+            segment_keys
+        };
+        let container = ContainerFormat::detect(&playlist.segments);
+        let mut init_sections = InitSections::from_segments(&playlist.segments);
+        if container == ContainerFormat::Fmp4 {
+            init_sections.data = self
+                .download_init_sections(&init_sections, &segment_keys, &self.base_url)
+                .await?;
+        }
+
+        {
+            let mut stats = self.stats.lock().await;
+            stats.total_segments = playlist.segments.len();
+        }
+        let ffmpeg_format = track_format(rendition.kind, &playlist.segments, container);
+        let segments = Arc::new(playlist.segments);
+        self.download_all_segments(
+            &segments,
+            &Arc::new(segment_keys),
+            SegmentFormat::from_track_format(ffmpeg_format),
+        )
+        .await?;
+
+        let path = self.download_dir.join(format!("track.{ffmpeg_format}"));
+        merge_track_segments(
+            &self.download_dir,
+            &segments,
+            &init_sections,
+            ffmpeg_format,
+            &path,
+        )
+        .await?;
+        info!("轨道 {} 下载完成", rendition.name);
+
+        Ok(RenditionTrack {
+            kind: rendition.kind,
+            path,
+            ffmpeg_format,
+            name: rendition.name,
+            language: rendition.language,
+        })
+    }
+
+    /// 直播录制：按目标时长刷新播放列表，按媒体序列号去重并追加新片段，
+    /// 在 ENDLIST、达到最长时长或收到取消信号时停止，最后合并为单个文件
+    async fn record_live(&self, mut playlist: MediaPlaylist) -> Result<()> {
+        info!("进入直播录制模式");
+        let started = Instant::now();
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(self.concurrent));
+        let mut key_cache = KeyCache::new();
+        let mut seen = HashSet::new();
+        let mut recorded: Vec<MediaSegment> = Vec::new();
+        let mut recorded_keys: Vec<Option<SegmentKey>> = Vec::new();
+        let mut handles = Vec::new();
+        let mut reload_failures = 0;
+
+        loop {
+            let current_url = self.current_base_url.lock().await.clone();
+            let segment_keys = resolve_segment_keys(
+                &playlist.segments,
+                playlist.media_sequence,
+                &self.client,
+                &current_url,
+                &mut key_cache,
+            )
+            .await?;
+            let container = ContainerFormat::detect(&playlist.segments);
+
+            let mut new_segments = 0;
+            for (offset, (segment, key)) in playlist.segments.iter().zip(segment_keys).enumerate() {
+                if !seen.insert(playlist.media_sequence + offset as u64) {
+                    continue;
+                }
+
+                let index = recorded.len();
+                recorded.push(segment.clone());
+                recorded_keys.push(key.clone());
+                new_segments += 1;
+
+                let downloader = self.clone();
+                let segment = segment.clone();
+                let semaphore = semaphore.clone();
+                handles.push(tokio::spawn(async move {
+                    cancellable(downloader.cancel_token.as_ref(), async {
+                        let _permit = semaphore.acquire_owned().await.unwrap();
+                        downloader
+                            .download_segment(index, &segment, key.as_ref(), container.into())
+                            .await
+                    })
+                    .await
+                }));
+            }
+
+            {
+                let mut stats = self.stats.lock().await;
+                stats.total_segments = recorded.len();
+            }
+            self.progress_bar.set_length(recorded.len() as u64);
+            if new_segments > 0 {
+                info!("直播录制: 新增 {new_segments} 个片段，累计 {} 个", recorded.len());
+            }
+
+            if playlist.end_list {
+                info!("播放列表已结束 (EXT-X-ENDLIST)，停止录制");
+                break;
+            }
+            if self.max_duration.is_some_and(|max| started.elapsed() >= max) {
+                info!("已达到最长录制时长，停止录制");
+                break;
+            }
+
+            // 没有新片段时按目标时长的一半刷新
+            let mut wait = Duration::from_secs(playlist.target_duration.max(1));
+            if new_segments == 0 {
+                wait /= 2;
+            }
+            if let Some(max) = self.max_duration {
+                wait = wait.min(max.saturating_sub(started.elapsed()));
+            }
+            match &self.stop_signal {
+                Some(token) => {
+                    tokio::select! {
+                        () = token.cancelled() => {}
+                        () = sleep(wait) => {}
+                    }
+                }
+                None => sleep(wait).await,
+            }
+            if self
+                .stop_signal
+                .as_ref()
+                .is_some_and(CancellationToken::is_cancelled)
+            {
+                info!("收到停止信号，停止录制");
+                break;
+            }
+
+            match self.download_text(current_url.as_str()).await {
+                Ok(content) => match Self::parse_m3u8(&content) {
+                    Ok(mut reloaded) => {
+                        resolve_byte_range_offsets(&mut reloaded.segments);
+                        playlist = reloaded;
+                        reload_failures = 0;
+                    }
+                    Err(e) => {
+                        reload_failures += 1;
+                        error!("直播播放列表解析失败 ({reload_failures}/{}): {e}", self.retry);
+                    }
+                },
+                Err(e) => {
+                    reload_failures += 1;
+                    error!("直播播放列表刷新失败 ({reload_failures}/{}): {e}", self.retry);
+                }
+            }
+            if reload_failures > self.retry {
+                return Err(DownloadError::task(
+                    &self.output_filename,
+                    "直播播放列表多次刷新失败".to_string(),
+                ));
+            }
+        }
+
+        for (i, result) in join_all(handles).await.into_iter().enumerate() {
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(DownloadError::segment(i, 0, e.to_string())),
+                Err(e) => return Err(DownloadError::segment(i, 0, e.to_string())),
+            }
+        }
+
+        if recorded.is_empty() {
+            return Err(DownloadError::task(
+                &self.output_filename,
+                "直播录制未获取到任何片段".to_string(),
+            ));
+        }
+
+        self.progress_bar.finish_with_message("直播录制完成");
+
+        let container = ContainerFormat::detect(&recorded);
+        let mut init_sections = InitSections::from_segments(&recorded);
+        if container == ContainerFormat::Fmp4 {
+            let current_url = self.current_base_url.lock().await.clone();
+            init_sections.data = self
+                .download_init_sections(&init_sections, &recorded_keys, &current_url)
+                .await?;
+        }
+
+        info!("正在合并直播录制的 {} 个片段...", recorded.len());
+        self.notify_status("merging");
+        self.merge_segments(&recorded, &init_sections, container, &[], None)
+            .await?;
+        let indices: Vec<usize> = (0..recorded.len()).collect();
+        let multi_period = split_periods(&recorded).len() > 1;
+        self.verify_output(&recorded, &indices, None, multi_period)
+            .await?;
+        self.notify_status("completed");
+        Ok(())
+    }
+
+    /// 校验合并后的输出并记录结果，`strict` 模式下不通过时返回错误
+    ///
+    /// 多时段的 TS 输出保留各时段的原始时间戳，时间戳跳变不视为间断。
+    async fn verify_output(
+        &self,
+        segments: &[MediaSegment],
+        indices: &[usize],
+        trim: Option<TrimWindow>,
+        multi_period: bool,
+    ) -> Result<()> {
+        if self.verify.mode == VerifyMode::Off {
+            return Ok(());
+        }
+        info!("正在校验输出文件...");
+        let allow_jumps = multi_period && self.output.format == OutputFormat::Ts;
+        let report = verify_output(
+            &self.output_path(),
+            self.output.format,
+            segments,
+            indices,
+            trim,
+            allow_jumps,
+            &self.verify,
+        )
+        .await;
+        if let Some(message) = &report.message {
+            warn!("{message}");
+        } else {
+            info!(
+                "校验通过：时长 {:.1}s（预期 {:.1}s）",
+                report.actual_duration.unwrap_or_default(),
+                report.expected_duration
+            );
+        }
+        if !report.missing_segments.is_empty() {
+            warn!("可能缺失的片段: {:?}", report.missing_segments);
+        }
+        let passed = report.passed;
+        let message = report.message.clone().unwrap_or_default();
+        self.stats.lock().await.verification = Some(report);
+
+        if !passed && self.verify.mode == VerifyMode::Strict {
+            return Err(DownloadError::verification(message));
+        }
         Ok(())
     }
 
     async fn stream_segments_to_mp4(
         &self,
         segments: Arc<Vec<MediaSegment>>,
-        key_data: Option<Vec<u8>>,
+        segment_keys: Arc<Vec<Option<SegmentKey>>>,
+        init_sections: &InitSections,
+        container: ContainerFormat,
     ) -> Result<()> {
         let tx = self
             .stream_output
@@ -292,69 +938,7 @@ impl M3u8Downloader {
             .ok_or_else(|| DownloadError::Unknown("直传输出通道不存在".to_string()))?
             .clone();
 
-        let mut child = Command::new("ffmpeg")
-            .args([
-                "-nostdin",
-                "-f",
-                "mpegts",
-                "-i",
-                "pipe:0",
-                "-c",
-                "copy",
-                "-bsf:a",
-                "aac_adtstoasc",
-                "-movflags",
-                "frag_keyframe+empty_moov+default_base_moof",
-                "-f",
-                "mp4",
-                "pipe:1",
-            ])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| DownloadError::ffmpeg(format!("启动FFmpeg失败: {e}")))?;
-
-        let mut stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准输入".to_string()))?;
-        let mut stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准输出".to_string()))?;
-        let mut stderr = child
-            .stderr
-            .take()
-            .ok_or_else(|| DownloadError::ffmpeg("无法获取FFmpeg标准错误输出".to_string()))?;
-
-        let stdout_tx = tx.clone();
-        let stdout_task = tokio::spawn(async move {
-            let mut buf = vec![0u8; 65536];
-            loop {
-                let n = stdout
-                    .read(&mut buf)
-                    .await
-                    .map_err(|e| DownloadError::ffmpeg(format!("读取FFmpeg输出失败: {e}")))?;
-                if n == 0 {
-                    break;
-                }
-                if stdout_tx
-                    .send(Ok(Bytes::copy_from_slice(&buf[..n])))
-                    .await
-                    .is_err()
-                {
-                    break;
-                }
-            }
-            Ok::<(), DownloadError>(())
-        });
-
-        let stderr_task = tokio::spawn(async move {
-            let mut stderr_buf = Vec::new();
-            let _ = stderr.read_to_end(&mut stderr_buf).await;
-            stderr_buf
-        });
+        let mut output = Mp4Stream::new(self.remuxer, container, init_sections.maps.len(), tx)?;
 
         let semaphore = Arc::new(tokio::sync::Semaphore::new(self.concurrent));
         let (segment_tx, mut segment_rx) =
@@ -364,15 +948,22 @@ impl M3u8Downloader {
         for index in 0..segments.len() {
             let downloader = self.clone();
             let segments = segments.clone();
-            let key_data = key_data.clone();
+            let segment_keys = segment_keys.clone();
             let semaphore = semaphore.clone();
             let segment_tx = segment_tx.clone();
 
             handles.push(tokio::spawn(async move {
-                let _permit = semaphore.acquire_owned().await.unwrap();
-                let result = downloader
-                    .download_segment_bytes(index, &segments[index], key_data.as_ref())
-                    .await;
+                let result = cancellable(downloader.cancel_token.as_ref(), async {
+                    let _permit = semaphore.acquire_owned().await.unwrap();
+                    downloader
+                        .download_segment_bytes(
+                            index,
+                            &segments[index],
+                            segment_keys[index].as_ref(),
+                        )
+                        .await
+                })
+                .await;
                 let _ = segment_tx.send((index, result)).await;
             }));
         }
@@ -387,10 +978,13 @@ impl M3u8Downloader {
                 Ok(data) => {
                     buffer.insert(index, data);
                     while let Some(segment_data) = buffer.remove(&next_index) {
-                        stdin
-                            .write_all(&segment_data)
-                            .await
-                            .map_err(|e| DownloadError::ffmpeg(format!("写入FFmpeg失败: {e}")))?;
+                        if next_index > 0 && segments[next_index].discontinuity {
+                            output.discontinuity()?;
+                        }
+                        if let Some(init) = init_sections.init_before(next_index) {
+                            output.write(init).await?;
+                        }
+                        output.write(&segment_data).await?;
                         next_index += 1;
                     }
                 }
@@ -421,75 +1015,35 @@ impl M3u8Downloader {
         }
 
         if let Some(err) = stream_error {
-            let _ = child.kill().await;
-            let _ = stdout_task.await;
-            let _ = stderr_task.await;
             return Err(err);
         }
 
-        stdin
-            .flush()
-            .await
-            .map_err(|e| DownloadError::ffmpeg(format!("刷新FFmpeg输入失败: {e}")))?;
-        drop(stdin);
-
         self.notify_status("merging");
-
-        let status = child
-            .wait()
-            .await
-            .map_err(|e| DownloadError::ffmpeg(format!("等待FFmpeg失败: {e}")))?;
-        let stdout_result = stdout_task
-            .await
-            .map_err(|e| DownloadError::ffmpeg(format!("读取FFmpeg输出任务失败: {e}")))?;
-        stdout_result?;
-        let stderr_output = stderr_task.await.unwrap_or_default();
-
-        if !status.success() {
-            let stderr_text = String::from_utf8_lossy(&stderr_output).trim().to_string();
-            if stderr_text.is_empty() {
-                return Err(DownloadError::ffmpeg("FFmpeg转换失败".to_string()));
-            }
-            return Err(DownloadError::ffmpeg(format!("FFmpeg转换失败: {stderr_text}")));
-        }
-
-        Ok(())
+        output.finish().await
     }
 
     async fn download_text(&self, url: &str) -> Result<String> {
         let full_url = resolve_url(&self.base_url, url)?;
-        let response = self
-            .client
-            .get(&full_url)
-            .send()
-            .await
-            .map_err(|e| DownloadError::http(0, format!("请求失败: {e}")))?;
-
-        if !response.status().is_success() {
-            return Err(DownloadError::http(response.status().as_u16(), full_url));
-        }
-
-        response
-            .text()
-            .await
-            .map_err(|e| DownloadError::parse(format!("读取响应失败: {e}")))
+        fetch_playlist_text(&self.client, &full_url).await
     }
 
     async fn download_segment(
         &self,
         index: usize,
         segment: &MediaSegment,
-        key: Option<&Vec<u8>>,
+        key: Option<&SegmentKey>,
+        format: SegmentFormat,
     ) -> Result<()> {
         let mut retry_count = 0;
 
         loop {
-            match self.try_download_segment(index, segment, key).await {
+            match self.try_download_segment(index, segment, key, format).await {
                 Ok(()) => {
-                    self.record_segment_completion().await;
+                    self.record_segment_completion(index).await;
                     return Ok(());
                 }
                 Err(e) => {
+                    self.stats.lock().await.segment_progress.remove(&index);
                     retry_count += 1;
                     if retry_count > self.retry {
                         return Err(DownloadError::segment(index, self.retry, e.to_string()));
@@ -512,17 +1066,18 @@ impl M3u8Downloader {
         &self,
         index: usize,
         segment: &MediaSegment,
-        key: Option<&Vec<u8>>,
+        key: Option<&SegmentKey>,
     ) -> Result<Vec<u8>> {
         let mut retry_count = 0;
 
         loop {
             match self.try_fetch_segment_data(index, segment, key).await {
                 Ok(data) => {
-                    self.record_segment_completion().await;
+                    self.record_segment_completion(index).await;
                     return Ok(data);
                 }
                 Err(e) => {
+                    self.stats.lock().await.segment_progress.remove(&index);
                     retry_count += 1;
                     if retry_count > self.retry {
                         return Err(DownloadError::segment(index, self.retry, e.to_string()));
@@ -540,10 +1095,12 @@ impl M3u8Downloader {
         }
     }
 
-    async fn record_segment_completion(&self) {
+    async fn record_segment_completion(&self, index: usize) {
         let (completed, total, speed, percentage) = {
             let mut stats = self.stats.lock().await;
+            stats.segment_progress.remove(&index);
             stats.completed_segments += 1;
+            stats.last_report = Instant::now();
             let completed = stats.completed_segments;
             let total = stats.total_segments;
             let speed = stats.get_speed();
@@ -552,6 +1109,35 @@ impl M3u8Downloader {
         };
 
         self.progress_bar.set_position(completed as u64);
+        self.report_progress(completed, total, speed, percentage);
+    }
+
+    /// 记录片段内的下载进度，按固定间隔上报，避免每个数据块都触发回调
+    async fn record_segment_progress(&self, index: usize, fraction: Option<f64>) {
+        let Some(fraction) = fraction else {
+            return;
+        };
+        let report = {
+            let mut stats = self.stats.lock().await;
+            stats.segment_progress.insert(index, fraction);
+            if stats.last_report.elapsed() < Duration::from_millis(PROGRESS_REPORT_INTERVAL_MS) {
+                None
+            } else {
+                stats.last_report = Instant::now();
+                Some((
+                    stats.completed_segments,
+                    stats.total_segments,
+                    stats.get_speed(),
+                    stats.get_progress_percentage(),
+                ))
+            }
+        };
+        if let Some((completed, total, speed, percentage)) = report {
+            self.report_progress(completed, total, speed, percentage);
+        }
+    }
+
+    fn report_progress(&self, completed: usize, total: usize, speed: f64, percentage: f64) {
         self.progress_bar.set_message(format!(
             "这个是第{}个任务,名称是:{} 已下载: {}/{} ({:.1}%) 速度: {:.1} KB/s",
             self.index,
@@ -567,117 +1153,321 @@ impl M3u8Downloader {
         }
     }
 
-    async fn try_fetch_segment_data(
-        &self,
-        index: usize,
-        segment: &MediaSegment,
-        key: Option<&Vec<u8>>,
-    ) -> Result<Vec<u8>> {
+    /// 片段的完整地址与字节区间
+    async fn segment_request(&self, segment: &MediaSegment) -> Result<(String, Option<(u64, u64)>)> {
         let current_url = {
             let url = self.current_base_url.lock().await;
             url.clone()
         };
 
         let segment_url = resolve_url(&current_url, &segment.uri)?;
-        let response = self
-            .client
-            .get(&segment_url)
+        let range = segment
+            .byte_range
+            .as_ref()
+            .map(|r| (r.length, r.offset.unwrap_or(0)));
+        Ok((segment_url, range))
+    }
+
+    /// 下载并解密整个片段到内存（直传模式按顺序写入 FFmpeg 时使用）
+    async fn try_fetch_segment_data(
+        &self,
+        index: usize,
+        segment: &MediaSegment,
+        key: Option<&SegmentKey>,
+    ) -> Result<Vec<u8>> {
+        let (segment_url, range) = self.segment_request(segment).await?;
+        let mut body = self.open_body(&segment_url, range).await?;
+        let mut decryptor = SegmentDecryptor::new(key)?;
+        let mut data = Vec::with_capacity(body.capacity_hint());
+        while let Some(chunk) = self.next_chunk(&mut body).await? {
+            data.extend_from_slice(&decryptor.update(chunk));
+            self.record_segment_progress(index, body.fraction()).await;
+        }
+        data.extend_from_slice(&decryptor.finish()?);
+        Ok(data)
+    }
+
+    /// 边下载边解密，将片段按数据块写入文件
+    async fn stream_segment_to_file(
+        &self,
+        index: usize,
+        segment: &MediaSegment,
+        key: Option<&SegmentKey>,
+        path: &Path,
+    ) -> Result<SegmentSize> {
+        let (segment_url, range) = self.segment_request(segment).await?;
+        let mut body = self.open_body(&segment_url, range).await?;
+        let mut decryptor = SegmentDecryptor::new(key)?;
+        let mut written = 0;
+
+        let file = tokio::fs::File::create(path)
+            .await
+            .map_err(|e| DownloadError::file(path, e.to_string()))?;
+        let mut writer = tokio::io::BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
+        while let Some(chunk) = self.next_chunk(&mut body).await? {
+            let data = decryptor.update(chunk);
+            written += data.len() as u64;
+            writer
+                .write_all(&data)
+                .await
+                .map_err(|e| DownloadError::file(path, e.to_string()))?;
+            self.record_segment_progress(index, body.fraction()).await;
+        }
+        let data = decryptor.finish()?;
+        written += data.len() as u64;
+        writer
+            .write_all(&data)
+            .await
+            .map_err(|e| DownloadError::file(path, e.to_string()))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| DownloadError::file(path, e.to_string()))?;
+        Ok(SegmentSize {
+            content_length: body.expected,
+            written,
+        })
+    }
+
+    /// 下载二进制内容，`range` 为 (长度, 偏移) 时发送 Range 请求
+    async fn fetch_bytes(&self, url: &str, range: Option<(u64, u64)>) -> Result<Vec<u8>> {
+        let mut body = self.open_body(url, range).await?;
+        let mut data = Vec::with_capacity(body.capacity_hint());
+        while let Some(chunk) = self.next_chunk(&mut body).await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// 发送请求并返回按数据块读取的响应体
+    async fn open_body(&self, url: &str, range: Option<(u64, u64)>) -> Result<ResponseBody> {
+        let mut request = self.client.get(url);
+        if let Some((length, offset)) = range {
+            request = request.header(reqwest::header::RANGE, range_header_value(length, offset));
+        }
+
+        let response = request
             .send()
             .await
             .map_err(|e| DownloadError::http(0, format!("请求失败: {e}")))?;
 
-        if !response.status().is_success() {
-            return Err(DownloadError::http(response.status().as_u16(), segment_url));
+        let status = response.status();
+        if !status.is_success() {
+            return Err(DownloadError::http(status.as_u16(), url));
         }
 
-        let mut data = response
-            .bytes()
-            .await
-            .map_err(|e| DownloadError::parse(format!("读取响应体失败: {e}")))?
-            .to_vec();
+        // 服务端忽略 Range 返回完整内容时，边读取边截取对应区间
+        let (skip, remaining) = match range {
+            Some((length, offset)) if status != reqwest::StatusCode::PARTIAL_CONTENT => {
+                (offset, Some(length))
+            }
+            Some((length, _)) => (0, Some(length)),
+            None => (0, None),
+        };
+        Ok(ResponseBody {
+            expected: remaining.or_else(|| response.content_length()),
+            chunks: response.bytes_stream().boxed(),
+            skip,
+            remaining,
+            received: 0,
+        })
+    }
 
-        {
-            let mut stats = self.stats.lock().await;
-            stats.downloaded_bytes += data.len() as u64;
+    /// 读取下一个数据块，每块都经过限速器
+    async fn next_chunk(&self, body: &mut ResponseBody) -> Result<Option<Bytes>> {
+        loop {
+            if body.remaining == Some(0) {
+                return Ok(None);
+            }
+            let Some(chunk) = body.chunks.next().await else {
+                if let Some(remaining) = body.remaining {
+                    return Err(DownloadError::parse(format!(
+                        "响应长度不足，还缺少 {remaining} 字节"
+                    )));
+                }
+                // 传输中断时读取到的长度与 Content-Length 不符
+                if let Some(expected) = body.expected
+                    && body.received != expected
+                {
+                    return Err(DownloadError::parse(format!(
+                        "响应长度 {} 与 Content-Length {expected} 不符",
+                        body.received
+                    )));
+                }
+                return Ok(None);
+            };
+            let mut chunk =
+                chunk.map_err(|e| DownloadError::parse(format!("读取响应体失败: {e}")))?;
+
+            for limiter in &self.rate_limiters {
+                limiter.acquire(chunk.len()).await;
+            }
+            self.stats.lock().await.downloaded_bytes += chunk.len() as u64;
+
+            if body.skip > 0 {
+                let skipped = usize::try_from(body.skip).unwrap_or(usize::MAX).min(chunk.len());
+                chunk = chunk.slice(skipped..);
+                body.skip -= skipped as u64;
+            }
+            if let Some(remaining) = body.remaining.as_mut() {
+                chunk.truncate(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                *remaining -= chunk.len() as u64;
+            }
+            if chunk.is_empty() {
+                continue;
+            }
+            body.received += chunk.len() as u64;
+            return Ok(Some(chunk));
         }
+    }
+
+    /// 下载所有初始化段（`EXT-X-MAP`），按需使用对应片段的 AES-128 密钥解密
+    async fn download_init_sections(
+        &self,
+        init_sections: &InitSections,
+        segment_keys: &[Option<SegmentKey>],
+        base_url: &Url,
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut sections = Vec::with_capacity(init_sections.maps.len());
+
+        for (map_index, map) in init_sections.maps.iter().enumerate() {
+            let url = resolve_url(base_url, &map.uri)?;
+            let range = map
+                .byte_range
+                .as_ref()
+                .map(|r| (r.length, r.offset.unwrap_or(0)));
+            let mut data = self.fetch_bytes(&url, range).await?;
+
+            // 初始化段只可能使用 AES-128 整段加密，SAMPLE-AES 不加密初始化段
+            let key = init_sections
+                .segment_map
+                .iter()
+                .position(|m| *m == Some(map_index))
+                .and_then(|i| segment_keys[i].as_ref())
+                .filter(|k| k.method == KeyMethod::AES128);
+            if let Some(segment_key) = key {
+                data = decrypt_segment(&data, segment_key)?;
+            }
 
-        if let Some(key_data) = key {
-            data = decrypt_segment(&data, key_data, index)?;
+            info!("已下载初始化段 {} ({} 字节)", map.uri, data.len());
+            sections.push(data);
         }
 
-        Ok(data)
+        Ok(sections)
     }
 
     async fn try_download_segment(
         &self,
         index: usize,
         segment: &MediaSegment,
-        key: Option<&Vec<u8>>,
+        key: Option<&SegmentKey>,
+        format: SegmentFormat,
     ) -> Result<()> {
         // 从segment.uri中提取文件名
-        let segment_filename = Path::new(&segment.uri)
-            .file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or(&format!("segment_{index:06}.ts"))
-            .to_string();
+        let segment_filename = get_segment_filename(segment);
 
         // 检查分段文件是否已存在
         let segment_path = self.download_dir.join(&segment_filename);
         if segment_path.exists() {
             // 校验已存在的文件是否有效
-            if is_valid_ts_file(&segment_path) {
-                info!("片段 {index} ({segment_filename}) 已存在且校验通过，跳过下载");
-                return Ok(());
+            let recorded = self
+                .recorded_sizes
+                .get_or_init(|| async { load_segment_sizes(&self.download_dir) })
+                .await;
+            match check_segment_file(
+                &segment_path,
+                format,
+                self.segment_check,
+                recorded.get(&segment_filename).copied(),
+                key.is_some(),
+            ) {
+                Ok(()) => {
+                    info!("片段 {index} ({segment_filename}) 已存在且校验通过，跳过下载");
+                    return Ok(());
+                }
+                Err(reason) => {
+                    error!("片段 {index} ({segment_filename}) 已存在但校验失败（{reason}），将重新下载");
+                }
             }
-            error!("片段 {index} ({segment_filename}) 已存在但校验失败，将重新下载");
             // 删除损坏的文件
             if let Err(e) = fs::remove_file(&segment_path) {
                 error!("删除损坏文件失败 {}: {e}", segment_path.display());
             }
         }
 
-        let data = self.try_fetch_segment_data(index, segment, key).await?;
-
-        // 保存到下载目录
-        let file = tokio::fs::File::create(&segment_path)
+        // 先写入临时文件，下载完成后再重命名，中断时不会留下不完整的片段
+        let part_path = self.download_dir.join(format!("{segment_filename}.part"));
+        let size = match self
+            .stream_segment_to_file(index, segment, key, &part_path)
             .await
-            .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
-        let mut writer = tokio::io::BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
-        writer
-            .write_all(&data)
-            .await
-            .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
-        writer
-            .flush()
+        {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&part_path).await;
+                return Err(e);
+            }
+        };
+        tokio::fs::rename(&part_path, &segment_path)
             .await
             .map_err(|e| DownloadError::file(&segment_path, e.to_string()))?;
-        // info!("片段 {} 已保存到 {}", index, segment_path.display());
+        record_segment_size(&self.download_dir, &segment_filename, size).await;
 
         Ok(())
     }
 
-    async fn merge_segments(&self, segments: &[m3u8_rs::MediaSegment]) -> Result<()> {
+    async fn merge_segments(
+        &self,
+        segments: &[m3u8_rs::MediaSegment],
+        init_sections: &InitSections,
+        container: ContainerFormat,
+        tracks: &[RenditionTrack],
+        trim: Option<TrimWindow>,
+    ) -> Result<()> {
         if let Some(tx) = &self.stream_output {
-            // 流式模式：通过ffmpeg pipe输出
-            let temp_ts_path = self.download_dir.join("temp.ts");
-            crate::downloader::merge_segments_to_temp_ts(&self.download_dir, segments, &temp_ts_path).await?;
-            crate::downloader::merge_to_mp4_stream(&temp_ts_path, tx).await?;
+            // 流式模式：转为分片MP4后输出
+            let temp_path = self
+                .download_dir
+                .join(format!("temp.{}", container.extension()));
+            crate::downloader::merge_segments_to_temp(
+                &self.download_dir,
+                segments,
+                init_sections,
+                &temp_path,
+            )
+            .await?;
+            crate::downloader::merge_to_mp4_stream(
+                &temp_path,
+                container,
+                init_sections.maps.len(),
+                self.remuxer,
+                tx,
+            )
+            .await?;
             // 清理
             let _ = std::fs::remove_dir_all(&self.download_dir);
             info!("流式输出完成，已清理临时文件");
             Ok(())
         } else {
-            let output_path = self
-                .output_dir
-                .join(format!("{}.mp4", self.output_filename));
-            merge_segments(&self.download_dir, segments, &output_path).await?;
+            let output_path = self.output_path();
+            merge_segments(
+                &self.download_dir,
+                segments,
+                init_sections,
+                container,
+                tracks,
+                &self.output,
+                self.remuxer,
+                trim,
+                &output_path,
+            )
+            .await?;
             // 清理下载目录
             if let Err(e) = std::fs::remove_dir_all(&self.download_dir) {
                 error!("删除下载目录失败 {}: {}", self.download_dir.display(), e);
             } else {
                 info!("已删除下载目录: {}", self.download_dir.display());
             }
-            info!("视频文件已转换为MP4: {}", output_path.display());
+            info!("视频文件已生成: {}", output_path.display());
             Ok(())
         }
     }
@@ -704,6 +1494,23 @@ impl M3u8Downloader {
             status_callback: self.status_callback.clone(),
             stream_output: self.stream_output.clone(),
             current_base_url: self.current_base_url.clone(),
+            live: self.live,
+            max_duration: self.max_duration,
+            stop_signal: self.stop_signal.clone(),
+            cancel_token: self.cancel_token.clone(),
+            variant: self.variant,
+            codec: self.codec.clone(),
+            audio_languages: self.audio_languages.clone(),
+            subtitle_languages: self.subtitle_languages.clone(),
+            output: self.output.clone(),
+            remuxer: self.remuxer,
+            periods: self.periods.clone(),
+            ads: self.ads.clone(),
+            clip: self.clip.clone(),
+            verify: self.verify.clone(),
+            segment_check: self.segment_check,
+            recorded_sizes: self.recorded_sizes.clone(),
+            rate_limiters: self.rate_limiters.clone(),
         }
     }
 }
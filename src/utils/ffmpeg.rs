@@ -0,0 +1,161 @@
+//! FFmpeg 定位：优先使用设置中的路径，不可用时从 PATH 查找，启动时探测版本
+
+use crate::error::{DownloadError, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// 探测 `ffmpeg -version` 的超时时间
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// FFmpeg 探测结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FfmpegInfo {
+    pub available: bool,
+    /// 实际使用的可执行文件
+    pub path: Option<String>,
+    /// `ffmpeg -version` 报告的版本号
+    pub version: Option<String>,
+    /// 设置中配置的路径，为空表示使用 PATH
+    pub configured_path: String,
+    /// 不可用或配置路径无效的原因
+    pub error: Option<String>,
+}
+
+/// 尚未探测时为 `None`，此时直接从 PATH 调用 `ffmpeg`
+static FFMPEG: LazyLock<RwLock<Option<FfmpegInfo>>> = LazyLock::new(RwLock::default);
+
+/// 定位并探测 FFmpeg，结果供之后的所有调用使用
+///
+/// `configured` 可以是可执行文件或其所在目录，为空或不可用时从 PATH 查找。
+pub async fn detect_ffmpeg(configured: &str) -> FfmpegInfo {
+    let configured = configured.trim();
+    let mut info = FfmpegInfo {
+        configured_path: configured.to_string(),
+        ..FfmpegInfo::default()
+    };
+
+    let mut errors = Vec::new();
+    if !configured.is_empty() {
+        let path = executable_in(Path::new(configured));
+        match probe(&path).await {
+            Ok(version) => {
+                info.available = true;
+                info.path = Some(path.display().to_string());
+                info.version = Some(version);
+            }
+            Err(e) => errors.push(format!("配置的路径不可用 ({}): {e}", path.display())),
+        }
+    }
+    if !info.available {
+        match find_in_path() {
+            Some(path) => match probe(&path).await {
+                Ok(version) => {
+                    info.available = true;
+                    info.path = Some(path.display().to_string());
+                    info.version = Some(version);
+                }
+                Err(e) => errors.push(format!("{}: {e}", path.display())),
+            },
+            None => errors.push("PATH 中未找到 ffmpeg".to_string()),
+        }
+    }
+    if !errors.is_empty() {
+        info.error = Some(errors.join("; "));
+    }
+
+    match (&info.path, &info.version) {
+        (Some(path), Some(version)) => {
+            if let Some(error) = &info.error {
+                warn!("FFmpeg {error}，改用 PATH 中的 FFmpeg");
+            }
+            info!("🎬 FFmpeg {version}: {path}");
+        }
+        _ => warn!(
+            "未找到可用的FFmpeg，只能使用内置转封装: {}",
+            info.error.as_deref().unwrap_or_default()
+        ),
+    }
+
+    *FFMPEG.write().unwrap_or_else(|e| e.into_inner()) = Some(info.clone());
+    info
+}
+
+/// 最近一次探测的结果，尚未探测时返回 `None`
+pub fn ffmpeg_info() -> Option<FfmpegInfo> {
+    FFMPEG.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// 确认 FFmpeg 可用，返回要执行的可执行文件
+pub fn require_ffmpeg() -> Result<PathBuf> {
+    match ffmpeg_info() {
+        None => Ok(PathBuf::from(executable_name())),
+        Some(FfmpegInfo {
+            available: true,
+            path: Some(path),
+            ..
+        }) => Ok(PathBuf::from(path)),
+        Some(info) => Err(DownloadError::ffmpeg(format!(
+            "未找到可用的FFmpeg，请安装FFmpeg或指定FFmpeg路径（{}）",
+            info.error.unwrap_or_default()
+        ))),
+    }
+}
+
+/// 使用定位到的 FFmpeg 创建命令
+pub fn ffmpeg_command() -> Result<Command> {
+    Ok(Command::new(require_ffmpeg()?))
+}
+
+const fn executable_name() -> &'static str {
+    if cfg!(windows) {
+        "ffmpeg.exe"
+    } else {
+        "ffmpeg"
+    }
+}
+
+/// 配置的是目录时取其中的 ffmpeg 可执行文件
+fn executable_in(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.join(executable_name())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+fn find_in_path() -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(executable_name()))
+        .find(|path| path.is_file())
+}
+
+/// 执行 `ffmpeg -version`，返回版本号
+async fn probe(path: &Path) -> std::result::Result<String, String> {
+    let output = Command::new(path)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(PROBE_TIMEOUT, output)
+        .await
+        .map_err(|_| "执行超时".to_string())?
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("退出状态 {}", output.status));
+    }
+
+    // 首行形如 `ffmpeg version 6.1.1-3ubuntu5 Copyright (c) ...`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first_line = stdout.lines().next().unwrap_or_default();
+    first_line
+        .strip_prefix("ffmpeg version ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(str::to_string)
+        .ok_or_else(|| format!("无法识别的版本信息: {first_line}"))
+}
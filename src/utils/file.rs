@@ -1,23 +1,247 @@
-use crate::config::TS_SYNC_BYTE;
+use crate::config::{
+    SEGMENT_SIZES_FILE, TS_MAX_CONTINUITY_ERROR_RATIO, TS_PACKET_SIZE, TS_SYNC_BYTE,
+    WRITE_BUFFER_SIZE,
+};
 use crate::error::{DownloadError, Result};
-use log::info;
+use log::{info, warn};
+use m3u8_rs::MediaSegment;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
+use tokio::io::AsyncWriteExt;
 
+use crate::downloader::{ContainerFormat, ContinuityCheck, parse_packet};
 use crate::utils::DownloadTask;
 
-/// 检查TS文件是否有效（通过检查文件头）
-pub fn is_valid_ts_file(path: &Path) -> bool {
-    File::open(path).is_ok_and(|mut file| {
-        let mut buffer = [0u8; 4];
-        if file.read_exact(&mut buffer).is_ok() {
-            // TS文件通常以0x47开头（MPEG-TS同步字节）
-            buffer[0] == TS_SYNC_BYTE
+/// fMP4 片段允许出现的顶层 box 类型
+const FMP4_TOP_LEVEL_BOXES: [&[u8; 4]; 9] = [
+    b"ftyp", b"styp", b"sidx", b"moof", b"mdat", b"emsg", b"prft", b"free", b"moov",
+];
+
+/// 继续下载时对已存在片段的校验方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentCheck {
+    /// 检查文件长度、记录的大小与首尾包的同步字节（默认）
+    #[default]
+    Fast,
+    /// 另外逐包检查同步字节与连续计数器
+    Thorough,
+}
+
+/// 检查TS文件是否有效：长度为188的整数倍且包边界处为同步字节
+///
+/// `Fast` 只检查首尾两个包，`Thorough` 检查每个包并统计连续计数器错误。
+pub fn check_ts_file(path: &Path, check: SegmentCheck) -> std::result::Result<(), String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let file_len = file.metadata().map_err(|e| e.to_string())?.len();
+    let packet_size = TS_PACKET_SIZE as u64;
+    if file_len == 0 || file_len % packet_size != 0 {
+        return Err(format!("文件长度 {file_len} 不是 {TS_PACKET_SIZE} 的整数倍"));
+    }
+
+    if check == SegmentCheck::Fast {
+        for offset in [0, file_len - packet_size] {
+            let mut sync = [0u8; 1];
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut sync))
+                .map_err(|e| e.to_string())?;
+            if sync[0] != TS_SYNC_BYTE {
+                return Err(format!("偏移 {offset} 处缺少同步字节"));
+            }
+        }
+        return Ok(());
+    }
+
+    let mut reader = BufReader::with_capacity(WRITE_BUFFER_SIZE, file);
+    let mut buf = [0u8; TS_PACKET_SIZE];
+    let mut continuity = ContinuityCheck::default();
+    for index in 0..file_len / packet_size {
+        reader.read_exact(&mut buf).map_err(|e| e.to_string())?;
+        let Some(packet) = parse_packet(&buf) else {
+            return Err(format!("第 {index} 个包缺少同步字节或适配域无效"));
+        };
+        continuity.push(&packet);
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let ratio = continuity.errors as f64 / continuity.packets as f64;
+    if ratio > TS_MAX_CONTINUITY_ERROR_RATIO {
+        return Err(format!(
+            "{} 个包中有 {} 处连续计数器错误",
+            continuity.packets, continuity.errors
+        ));
+    }
+    Ok(())
+}
+
+/// 检查fMP4片段是否有效（顶层 box 结构完整且恰好覆盖整个文件）
+pub fn is_valid_fmp4_file(path: &Path) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    let Ok(file_len) = file.metadata().map(|m| m.len()) else {
+        return false;
+    };
+
+    let mut offset = 0u64;
+    while offset < file_len {
+        let mut header = [0u8; 8];
+        if file.seek(SeekFrom::Start(offset)).is_err() || file.read_exact(&mut header).is_err() {
+            return false;
+        }
+        let box_type = &header[4..8];
+        if !FMP4_TOP_LEVEL_BOXES
+            .iter()
+            .any(|t| t.as_slice() == box_type)
+        {
+            return false;
+        }
+        let box_size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            0 => file_len - offset,
+            1 => {
+                let mut large = [0u8; 8];
+                if file.read_exact(&mut large).is_err() {
+                    return false;
+                }
+                u64::from_be_bytes(large)
+            }
+            size => u64::from(size),
+        };
+        if box_size < 8 {
+            return false;
+        }
+        offset += box_size;
+    }
+
+    offset == file_len && file_len > 0
+}
+
+/// 片段文件的格式，决定继续下载时的结构校验
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentFormat {
+    MpegTs,
+    Fmp4,
+    /// WebVTT 字幕与不带封装的独立音频（AAC/MP3/AC-3），只校验大小
+    Other,
+}
+
+impl SegmentFormat {
+    /// 按轨道的 FFmpeg 输入格式（见 `track_format`）判断片段格式
+    pub fn from_track_format(ffmpeg_format: &str) -> Self {
+        if ffmpeg_format == ContainerFormat::MpegTs.ffmpeg_format() {
+            Self::MpegTs
+        } else if ffmpeg_format == ContainerFormat::Fmp4.ffmpeg_format() {
+            Self::Fmp4
         } else {
-            false
+            Self::Other
         }
-    })
+    }
+}
+
+impl From<ContainerFormat> for SegmentFormat {
+    fn from(container: ContainerFormat) -> Self {
+        match container {
+            ContainerFormat::MpegTs => Self::MpegTs,
+            ContainerFormat::Fmp4 => Self::Fmp4,
+        }
+    }
+}
+
+/// 片段下载完成时记录的大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentSize {
+    /// 响应的 Content-Length（区间请求时为区间长度），未知时为 `None`
+    pub content_length: Option<u64>,
+    /// 写入文件的字节数（解密后）
+    pub written: u64,
+}
+
+/// 按格式校验已下载的片段文件
+///
+/// `recorded` 为下载完成时记录的大小：未加密的片段应与 Content-Length 一致，
+/// 解密后的片段（AES-128 去掉了填充）与写入的字节数比较。
+pub fn check_segment_file(
+    path: &Path,
+    format: SegmentFormat,
+    check: SegmentCheck,
+    recorded: Option<SegmentSize>,
+    decrypted: bool,
+) -> std::result::Result<(), String> {
+    if let Some(recorded) = recorded {
+        let size = path.metadata().map_err(|e| e.to_string())?.len();
+        match recorded.content_length {
+            Some(length) if !decrypted && size != length => {
+                return Err(format!("文件大小 {size} 与 Content-Length {length} 不符"));
+            }
+            _ if size != recorded.written => {
+                return Err(format!(
+                    "文件大小 {size} 与下载时写入的 {} 不符",
+                    recorded.written
+                ));
+            }
+            _ => {}
+        }
+    }
+    match format {
+        SegmentFormat::MpegTs => check_ts_file(path, check),
+        SegmentFormat::Fmp4 if is_valid_fmp4_file(path) => Ok(()),
+        SegmentFormat::Fmp4 => Err("box 结构不完整".to_string()),
+        SegmentFormat::Other => Ok(()),
+    }
+}
+
+/// 读取下载目录中记录的片段大小（文件名 -> 大小），没有记录时返回空表
+pub fn load_segment_sizes(download_dir: &Path) -> HashMap<String, SegmentSize> {
+    let Ok(content) = std::fs::read_to_string(download_dir.join(SEGMENT_SIZES_FILE)) else {
+        return HashMap::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.rsplitn(3, '\t');
+            let written = fields.next()?.parse().ok()?;
+            let content_length = match fields.next()? {
+                "-" => None,
+                length => Some(length.parse().ok()?),
+            };
+            let name = fields.next()?;
+            Some((
+                name.to_string(),
+                SegmentSize {
+                    content_length,
+                    written,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// 追加记录下载完成的片段大小，记录失败只影响继续下载时的校验
+pub async fn record_segment_size(download_dir: &Path, file_name: &str, size: SegmentSize) {
+    let path = download_dir.join(SEGMENT_SIZES_FILE);
+    let content_length = size
+        .content_length
+        .map_or_else(|| "-".to_string(), |length| length.to_string());
+    let line = format!("{file_name}\t{content_length}\t{}\n", size.written);
+    let result = async {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(line.as_bytes()).await
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("记录片段大小失败 {}: {e}", path.display());
+    }
+}
+
+/// 生成 HTTP Range 请求头的值
+pub fn range_header_value(length: u64, offset: u64) -> String {
+    format!("bytes={offset}-{}", offset + length.saturating_sub(1))
 }
 
 /// 解析URL
@@ -33,19 +257,65 @@ pub fn resolve_url(base_url: &url::Url, url: &str) -> Result<String> {
 }
 
 /// 从segment URI中提取文件名
-pub fn get_segment_filename(segment_uri: &str, index: usize) -> String {
-    Path::new(segment_uri)
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or(&format!("segment_{index:06}.ts"))
-        .to_string()
+///
+/// 文件名只取决于片段本身，与按时段、广告或时间范围过滤后的序号无关，
+/// 以不同的过滤条件继续下载时不会误用其他片段的文件。
+/// 不同路径或主机下的同名片段（如广告与正片）通过完整 URI 的哈希区分；
+/// 带 `EXT-X-BYTERANGE` 的片段通常共用同一个URI，文件名中再附加区间偏移（需先补全偏移）。
+pub fn get_segment_filename(segment: &MediaSegment) -> String {
+    let hash = fnv1a_hash(&segment.uri);
+    let path = segment.uri.split(['?', '#']).next().unwrap_or_default();
+    let name = Path::new(path).file_name().and_then(|name| name.to_str());
+    let (stem, ext) = match name {
+        Some(name) => {
+            let path = Path::new(name);
+            (
+                path.file_stem().and_then(|s| s.to_str()).unwrap_or(name),
+                path.extension().and_then(|e| e.to_str()).unwrap_or("ts"),
+            )
+        }
+        None => ("segment", "ts"),
+    };
+    match &segment.byte_range {
+        Some(range) => format!(
+            "{stem}_{hash:016x}_{}.{ext}",
+            range.offset.unwrap_or_default()
+        ),
+        None => format!("{stem}_{hash:016x}.{ext}"),
+    }
+}
+
+/// FNV-1a 64 位哈希，结果不随程序版本变化，可用于持久的文件名
+fn fnv1a_hash(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// 补全 `EXT-X-BYTERANGE` 中省略的偏移
+///
+/// 省略偏移时，区间紧接同一资源中上一个片段的区间之后。
+pub fn resolve_byte_range_offsets(segments: &mut [MediaSegment]) {
+    let mut previous: Option<(String, u64)> = None;
+    for segment in segments.iter_mut() {
+        let Some(range) = segment.byte_range.as_mut() else {
+            previous = None;
+            continue;
+        };
+        let offset = range.offset.unwrap_or_else(|| match &previous {
+            Some((uri, end)) if *uri == segment.uri => *end,
+            _ => 0,
+        });
+        range.offset = Some(offset);
+        previous = Some((segment.uri.clone(), offset + range.length));
+    }
 }
 
 /// 检查任务是否已下载成功
 /// 规则：文件存在且大小大于0（可根据需要加MD5校验）
 pub fn is_already_downloaded(task: &DownloadTask, download_dir: &Path) -> bool {
     // 假设 task 里有 output_path 字段指定下载路径
-    let file_name = format!("{}.mp4", task.name);
+    let file_name = format!("{}.{}", task.name, task.output.format.extension());
     let file_path = download_dir.join(file_name);
     // 简单检查：文件存在且非空
     // 检查文件存在且非空
@@ -60,3 +330,241 @@ pub fn is_already_downloaded(task: &DownloadTask, download_dir: &Path) -> bool {
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use m3u8_rs::ByteRange;
+
+    fn segment(uri: &str) -> MediaSegment {
+        MediaSegment {
+            uri: uri.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ranged(uri: &str, length: u64, offset: Option<u64>) -> MediaSegment {
+        MediaSegment {
+            byte_range: Some(ByteRange { length, offset }),
+            ..segment(uri)
+        }
+    }
+
+    #[test]
+    fn same_basename_in_different_paths_gets_distinct_names() {
+        let ad = get_segment_filename(&segment("ads/seg1.ts"));
+        let main = get_segment_filename(&segment("main/seg1.ts"));
+        let other_host = get_segment_filename(&segment("https://cdn.example.com/main/seg1.ts"));
+        assert_ne!(ad, main);
+        assert_ne!(main, other_host);
+        for name in [&ad, &main, &other_host] {
+            assert!(name.starts_with("seg1_") && name.ends_with(".ts"), "{name}");
+        }
+    }
+
+    #[test]
+    fn segment_filename_is_stable() {
+        assert_eq!(
+            get_segment_filename(&segment("main/seg1.ts")),
+            get_segment_filename(&segment("main/seg1.ts"))
+        );
+        let name = get_segment_filename(&segment("seg2.m4s?token=abc"));
+        assert!(
+            name.starts_with("seg2_") && name.ends_with(".m4s"),
+            "{name}"
+        );
+    }
+
+    #[test]
+    fn byte_range_segments_include_offset() {
+        let first = get_segment_filename(&ranged("video.ts", 1000, Some(0)));
+        let second = get_segment_filename(&ranged("video.ts", 1000, Some(1000)));
+        assert_ne!(first, second);
+        assert!(second.ends_with("_1000.ts"), "{second}");
+    }
+
+    fn offsets(segments: &[MediaSegment]) -> Vec<Option<u64>> {
+        segments
+            .iter()
+            .map(|s| s.byte_range.as_ref().and_then(|r| r.offset))
+            .collect()
+    }
+
+    #[test]
+    fn omitted_offset_follows_previous_range_of_same_uri() {
+        let mut segments = vec![
+            ranged("video.ts", 1000, None),
+            ranged("video.ts", 500, None),
+            ranged("video.ts", 200, None),
+        ];
+        resolve_byte_range_offsets(&mut segments);
+        assert_eq!(offsets(&segments), [Some(0), Some(1000), Some(1500)]);
+    }
+
+    #[test]
+    fn uri_change_resets_offset() {
+        let mut segments = vec![
+            ranged("a.ts", 1000, None),
+            ranged("b.ts", 400, None),
+            ranged("b.ts", 400, None),
+            segment("c.ts"),
+            ranged("b.ts", 300, None),
+        ];
+        resolve_byte_range_offsets(&mut segments);
+        assert_eq!(
+            offsets(&segments),
+            [Some(0), Some(0), Some(400), None, Some(0)]
+        );
+    }
+
+    #[test]
+    fn explicit_offset_is_kept_and_continued() {
+        let mut segments = vec![
+            ranged("video.ts", 1000, Some(5000)),
+            ranged("video.ts", 1000, None),
+            ranged("video.ts", 100, Some(0)),
+            ranged("video.ts", 100, None),
+        ];
+        resolve_byte_range_offsets(&mut segments);
+        assert_eq!(
+            offsets(&segments),
+            [Some(5000), Some(6000), Some(0), Some(100)]
+        );
+    }
+
+    /// 每个测试使用单独的临时目录
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("m3u8_file_{}_{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// `counters` 中每项生成一个 PID 0x100 的 TS 包
+    fn ts_packets(counters: impl IntoIterator<Item = u8>) -> Vec<u8> {
+        let mut out = Vec::new();
+        for counter in counters {
+            out.extend_from_slice(&[TS_SYNC_BYTE, 0x01, 0x00, 0x10 | counter]);
+            out.extend_from_slice(&[0xAB; TS_PACKET_SIZE - 4]);
+        }
+        out
+    }
+
+    fn check_ts(name: &str, data: &[u8], check: SegmentCheck) -> std::result::Result<(), String> {
+        let dir = temp_dir(name);
+        let path = dir.join("segment.ts");
+        std::fs::write(&path, data).unwrap();
+        let result = check_ts_file(&path, check);
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    #[test]
+    fn ts_file_length_must_be_whole_packets() {
+        let mut data = ts_packets(0..4);
+        assert!(check_ts("whole", &data, SegmentCheck::Fast).is_ok());
+        data.truncate(data.len() - 5);
+        assert!(check_ts("truncated", &data, SegmentCheck::Fast).is_err());
+        assert!(check_ts("empty", &[], SegmentCheck::Fast).is_err());
+    }
+
+    #[test]
+    fn fast_check_only_reads_first_and_last_packet() {
+        let mut data = ts_packets(0..4);
+        data[TS_PACKET_SIZE] = 0;
+        assert!(check_ts("middle_fast", &data, SegmentCheck::Fast).is_ok());
+        assert!(check_ts("middle_thorough", &data, SegmentCheck::Thorough).is_err());
+        let mut data = ts_packets(0..4);
+        data[3 * TS_PACKET_SIZE] = 0;
+        assert!(check_ts("last_fast", &data, SegmentCheck::Fast).is_err());
+    }
+
+    #[test]
+    fn thorough_check_counts_continuity_errors() {
+        // 200 个包：计数器连续，允许重复一次
+        let mut counters: Vec<u8> = (0..200).map(|i| (i % 16) as u8).collect();
+        counters.insert(10, 9);
+        counters.pop();
+        let data = ts_packets(counters.iter().copied());
+        assert!(check_ts("continuous", &data, SegmentCheck::Thorough).is_ok());
+
+        // 一个包的计数器出错会造成前后 2 处不连续：1 个包（1%）在允许范围内，2 个包（2%）超过 1%
+        let mut counters: Vec<u8> = (0..200).map(|i| (i % 16) as u8).collect();
+        counters[50] = (counters[50] + 5) & 0x0F;
+        let data = ts_packets(counters.iter().copied());
+        assert!(check_ts("one_packet", &data, SegmentCheck::Thorough).is_ok());
+        counters[100] = (counters[100] + 5) & 0x0F;
+        let data = ts_packets(counters.iter().copied());
+        let result = check_ts("two_packets", &data, SegmentCheck::Thorough);
+        assert!(result.unwrap_err().contains("连续计数器"));
+    }
+
+    fn check_segment(
+        name: &str,
+        recorded: Option<SegmentSize>,
+        decrypted: bool,
+    ) -> std::result::Result<(), String> {
+        let dir = temp_dir(name);
+        let path = dir.join("segment.ts");
+        std::fs::write(&path, ts_packets(0..2)).unwrap();
+        let result = check_segment_file(
+            &path,
+            SegmentFormat::MpegTs,
+            SegmentCheck::Fast,
+            recorded,
+            decrypted,
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    fn size(content_length: Option<u64>, written: u64) -> Option<SegmentSize> {
+        Some(SegmentSize {
+            content_length,
+            written,
+        })
+    }
+
+    #[test]
+    fn segment_size_must_match_record() {
+        // 文件为 2 个包（376 字节）
+        assert!(check_segment("unrecorded", None, false).is_ok());
+        assert!(check_segment("matching", size(Some(376), 376), false).is_ok());
+        let result = check_segment("short", size(Some(564), 564), false);
+        assert!(result.unwrap_err().contains("Content-Length"));
+        let result = check_segment("written", size(Some(376), 564), false);
+        assert!(result.unwrap_err().contains("写入"));
+    }
+
+    #[test]
+    fn decrypted_segment_is_compared_with_written_size() {
+        // AES-128 解密去掉了 16 字节填充，文件比 Content-Length 短
+        assert!(check_segment("decrypted", size(Some(392), 376), true).is_ok());
+        assert!(check_segment("not_decrypted", size(Some(392), 376), false).is_err());
+        assert!(check_segment("decrypted_short", size(Some(392), 380), true).is_err());
+    }
+
+    #[test]
+    fn unknown_content_length_uses_written_size() {
+        assert!(check_segment("unknown", size(None, 376), false).is_ok());
+        assert!(check_segment("unknown_short", size(None, 564), false).is_err());
+    }
+
+    #[test]
+    fn loads_recorded_segment_sizes() {
+        let dir = temp_dir("sizes");
+        assert!(load_segment_sizes(&dir).is_empty());
+        std::fs::write(
+            dir.join(SEGMENT_SIZES_FILE),
+            "a.ts\t1000\t1000\nb.ts\t-\t500\nname\twith tab.ts\t10\t10\n\
+             garbage\nc.ts\tx\t5\nd.ts\t5\n",
+        )
+        .unwrap();
+        let sizes = load_segment_sizes(&dir);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes["a.ts"], size(Some(1000), 1000).unwrap());
+        assert_eq!(sizes["b.ts"], size(None, 500).unwrap());
+        assert_eq!(sizes["name\twith tab.ts"], size(Some(10), 10).unwrap());
+    }
+}
@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
 
+use crate::downloader::{
+    AdFilter, ClipOptions, OutputOptions, PeriodFilter, VariantPolicy, VerifyOptions,
+};
 use crate::error::{Result, DownloadError};
+use crate::utils::{HttpOptions, SegmentCheck};
 
 /// 定义下载任务结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +14,42 @@ pub struct DownloadTask {
     pub url: String,
     #[serde(default)]
     pub output_dir: String,
+    /// 子流选择策略
+    #[serde(default)]
+    pub variant: VariantPolicy,
+    /// 优先选择的视频编码
+    #[serde(default)]
+    pub codec: Option<String>,
+    /// 音频语言偏好
+    #[serde(default)]
+    pub audio_languages: Vec<String>,
+    /// 字幕语言偏好
+    #[serde(default)]
+    pub subtitle_languages: Vec<String>,
+    /// 请求头、Cookie、Referer 与 User-Agent
+    #[serde(default, flatten)]
+    pub http: HttpOptions,
+    /// 任务限速（字节/秒）
+    #[serde(default)]
+    pub rate_limit: Option<u64>,
+    /// 输出格式、faststart、标题与转码预设
+    #[serde(default, flatten)]
+    pub output: OutputOptions,
+    /// 不连续时段过滤条件
+    #[serde(default)]
+    pub periods: PeriodFilter,
+    /// 广告/填充片段过滤条件
+    #[serde(default, flatten)]
+    pub ads: AdFilter,
+    /// 只下载的时间范围（start、end）与是否精确裁剪（trim）
+    #[serde(default, flatten)]
+    pub clip: ClipOptions,
+    /// 合并后的完整性校验（verify: off/warn/strict）与允许的时长误差比例
+    #[serde(default, flatten)]
+    pub verify: VerifyOptions,
+    /// 继续下载时对已存在片段的校验方式（fast、thorough）
+    #[serde(default)]
+    pub segment_check: SegmentCheck,
 }
 
 /// 从JSON文件加载下载任务
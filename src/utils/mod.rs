@@ -1,7 +1,11 @@
 pub mod download_segment;
+pub mod ffmpeg;
 pub mod file;
+pub mod http;
 pub mod json_loader;
 mod logger;
+pub mod rate_limit;
 pub use file::*;
+pub use http::HttpOptions;
 pub use json_loader::DownloadTask;
 pub use logger::init_logger;
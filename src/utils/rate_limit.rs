@@ -0,0 +1,292 @@
+//! 令牌桶限速：全局限速在所有下载器间共享，任务限速只作用于单个任务
+
+use crate::error::{DownloadError, Result};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{Instant, sleep};
+
+/// 令牌桶限速器，克隆后共享同一个桶，速率可在运行时调整
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+    /// 速率变化时唤醒等待中的请求，按新速率重新计算等待时间
+    changed: Arc<Notify>,
+}
+
+/// 以累计字节数表示的令牌桶：`allowed - consumed` 即可用令牌数，为负时表示已透支
+#[derive(Debug)]
+struct Bucket {
+    /// 每秒字节数，0 表示不限速
+    rate: u64,
+    /// 按速率累计允许传输的字节数
+    allowed: f64,
+    /// 累计已申请的字节数
+    consumed: f64,
+    updated: Instant,
+}
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self {
+            rate: 0,
+            allowed: 0.0,
+            consumed: 0.0,
+            updated: Instant::now(),
+        }
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        if self.rate == 0 {
+            self.allowed = self.consumed;
+            return;
+        }
+        // 桶容量为一秒的流量，允许短时突发
+        let rate = self.rate as f64;
+        self.allowed = (self.allowed + elapsed * rate).min(self.consumed + rate);
+    }
+}
+
+impl RateLimiter {
+    /// 创建限速器，`rate` 为每秒字节数，0 表示不限速
+    pub fn new(rate: u64) -> Self {
+        let limiter = Self::default();
+        limiter.set_rate(rate);
+        limiter
+    }
+
+    /// 调整速率，等待中的请求立即按新速率重新计算
+    pub fn set_rate(&self, rate: u64) {
+        {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            bucket.refill();
+            if bucket.rate == rate {
+                return;
+            }
+            bucket.rate = rate;
+            bucket.refill();
+        }
+        self.changed.notify_waiters();
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner()).rate
+    }
+
+    /// 申请 `bytes` 个令牌，令牌不足时等待
+    pub async fn acquire(&self, bytes: usize) {
+        let target = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            if bucket.rate == 0 {
+                return;
+            }
+            bucket.consumed += bytes as f64;
+            bucket.consumed
+        };
+        loop {
+            let changed = self.changed.notified();
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+                bucket.refill();
+                if bucket.allowed >= target {
+                    return;
+                }
+                (target - bucket.allowed) / bucket.rate as f64
+            };
+            tokio::select! {
+                () = sleep(Duration::from_secs_f64(wait)) => {}
+                () = changed => {}
+            }
+        }
+    }
+}
+
+static GLOBAL_RATE_LIMITER: LazyLock<RateLimiter> = LazyLock::new(RateLimiter::default);
+
+/// 进程内所有下载器共享的全局限速器，默认不限速
+pub fn global_rate_limiter() -> &'static RateLimiter {
+    &GLOBAL_RATE_LIMITER
+}
+
+/// 解析速率，如 `500K`、`2M`、`1.5M`、`1048576`（字节/秒，K/M/G 按 1024 进位）
+pub fn parse_rate(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("/S") {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("无法识别的速率单位: {value}")),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("速率格式无效: {value}"))?;
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// 按时段生效的限速规则，`end` 早于 `start` 时表示跨越午夜
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandwidthRule {
+    /// 开始时间，格式 `HH:MM`
+    pub start: String,
+    /// 结束时间（不含），格式 `HH:MM`
+    pub end: String,
+    /// 该时段内的限速（字节/秒），0 表示不限速
+    pub limit: u64,
+}
+
+fn parse_time_of_day(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M").map_err(|e| {
+        DownloadError::validation(
+            "bandwidth_schedule",
+            format!("时间格式应为 HH:MM: {value} - {e}"),
+        )
+    })
+}
+
+impl BandwidthRule {
+    pub fn validate(&self) -> Result<()> {
+        parse_time_of_day(&self.start)?;
+        parse_time_of_day(&self.end)?;
+        Ok(())
+    }
+
+    fn contains(&self, now: NaiveTime) -> bool {
+        let (Ok(start), Ok(end)) = (parse_time_of_day(&self.start), parse_time_of_day(&self.end))
+        else {
+            return false;
+        };
+        if start <= end {
+            start <= now && now < end
+        } else {
+            now >= start || now < end
+        }
+    }
+}
+
+/// 当前时刻生效的限速：命中的第一条时段规则，否则为默认限速
+pub fn effective_limit(default: u64, schedule: &[BandwidthRule], now: NaiveTime) -> u64 {
+    schedule
+        .iter()
+        .find(|rule| rule.contains(now))
+        .map_or(default, |rule| rule.limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    /// 上次补充令牌在 `seconds` 秒之前的桶
+    fn idle_bucket(rate: u64, consumed: f64, seconds: f64) -> Bucket {
+        Bucket {
+            rate,
+            allowed: consumed,
+            consumed,
+            updated: Instant::now() - Duration::from_secs_f64(seconds),
+        }
+    }
+
+    #[test]
+    fn refill_accumulates_at_rate() {
+        let mut bucket = idle_bucket(1000, 0.0, 0.5);
+        bucket.refill();
+        assert!((bucket.allowed - 500.0).abs() < 10.0, "{}", bucket.allowed);
+    }
+
+    #[test]
+    fn refill_is_capped_at_one_second() {
+        let mut bucket = idle_bucket(1000, 4000.0, 10.0);
+        bucket.refill();
+        assert!((bucket.allowed - 5000.0).abs() < f64::EPSILON);
+        // 不限速时不积累令牌
+        let mut bucket = idle_bucket(0, 4000.0, 10.0);
+        bucket.refill();
+        assert!((bucket.allowed - 4000.0).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_for_tokens() {
+        let limiter = RateLimiter::new(10_000);
+        let start = std::time::Instant::now();
+        limiter.acquire(1000).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(90), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn unlimited_acquire_returns_immediately() {
+        let limiter = RateLimiter::new(0);
+        timeout(Duration::from_millis(100), limiter.acquire(usize::MAX))
+            .await
+            .expect("不限速时不应等待");
+    }
+
+    #[tokio::test]
+    async fn set_rate_wakes_waiting_requests() {
+        // 100 B/s 下需要等待 10 秒，提速后立即按新速率完成
+        let limiter = RateLimiter::new(100);
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(1000).await }
+        });
+        sleep(Duration::from_millis(50)).await;
+        limiter.set_rate(1_000_000);
+        assert_eq!(limiter.rate(), 1_000_000);
+        timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("提速后应被唤醒")
+            .unwrap();
+
+        // 取消限速同样唤醒等待中的请求
+        limiter.set_rate(100);
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(1000).await }
+        });
+        sleep(Duration::from_millis(50)).await;
+        limiter.set_rate(0);
+        timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("取消限速后应被唤醒")
+            .unwrap();
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("1048576"), Ok(1_048_576));
+        assert_eq!(parse_rate("500K"), Ok(512_000));
+        assert_eq!(parse_rate("1.5M"), Ok(1_572_864));
+        assert_eq!(parse_rate("2 mb/s"), Ok(2_097_152));
+        assert!(parse_rate("5X").is_err());
+        assert!(parse_rate("M").is_err());
+    }
+
+    #[test]
+    fn schedule_rules_wrap_midnight() {
+        let rule = |start: &str, end: &str, limit| BandwidthRule {
+            start: start.to_string(),
+            end: end.to_string(),
+            limit,
+        };
+        let schedule = [rule("09:00", "18:00", 100), rule("23:00", "06:00", 0)];
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(effective_limit(500, &schedule, at(12, 0)), 100);
+        assert_eq!(effective_limit(500, &schedule, at(18, 0)), 500);
+        assert_eq!(effective_limit(500, &schedule, at(23, 30)), 0);
+        assert_eq!(effective_limit(500, &schedule, at(5, 59)), 0);
+        assert!(rule("9am", "18:00", 0).validate().is_err());
+    }
+}
@@ -4,6 +4,7 @@
 //! - URL 验证：防止 SSRF 攻击
 //! - 路径验证：防止路径遍历攻击
 //! - 配置验证：确保用户输入的合法性
+//! - 请求头验证：解析自定义 HTTP 请求头
 //!
 //! # 示例
 //!
@@ -18,6 +19,7 @@
 //! ```
 
 use crate::error::{DownloadError, Result};
+use reqwest::header::{HeaderName, HeaderValue};
 use std::path::Path;
 use url::Url;
 
@@ -55,6 +57,50 @@ pub fn validate_url(url: &str) -> Result<()> {
     }
 }
 
+/// 验证代理地址是否有效
+///
+/// 支持 http/https/socks5/socks5h 协议，必须包含主机名；`none` 表示直连。
+///
+/// # 参数
+///
+/// * `url` - 代理地址
+///
+/// # 返回
+///
+/// * `Ok(())` - 代理地址有效
+/// * `Err(DownloadError)` - 地址格式无效或协议不支持
+///
+/// # 示例
+///
+/// ```
+/// validate_proxy_url("socks5h://127.0.0.1:1080")?;  // 有效
+/// validate_proxy_url("ftp://proxy.example.com")?;   // 返回错误
+/// ```
+pub fn validate_proxy_url(url: &str) -> Result<()> {
+    let url = url.trim();
+    if url.is_empty() || url.eq_ignore_ascii_case(crate::utils::http::PROXY_DIRECT) {
+        return Ok(());
+    }
+
+    let parsed_url = Url::parse(url)
+        .map_err(|e| DownloadError::validation("proxy", format!("代理地址格式无效: {e}")))?;
+
+    if !matches!(parsed_url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(DownloadError::validation(
+            "proxy",
+            format!(
+                "不支持的代理协议: {}，仅支持 http/https/socks5/socks5h",
+                parsed_url.scheme()
+            ),
+        ));
+    }
+    if parsed_url.host_str().is_none_or(str::is_empty) {
+        return Err(DownloadError::validation("proxy", "代理地址缺少主机名"));
+    }
+
+    Ok(())
+}
+
 /// 验证文件路径是否安全（防止路径遍历攻击）
 ///
 /// 该函数检查相对路径是否逃逸出基础目录，防止路径遍历攻击。
@@ -168,3 +214,67 @@ pub fn validate_retry_count(retry: usize) -> Result<()> {
 
     Ok(())
 }
+
+/// 验证同时运行的最大任务数是否在合理范围内
+///
+/// 最大任务数限制在 1-16 之间，每个任务还会占用 `concurrent` 个片段连接。
+///
+/// # 参数
+///
+/// * `max_active_tasks` - 同时运行的最大任务数
+///
+/// # 返回
+///
+/// * `Ok(())` - 任务数有效
+/// * `Err(DownloadError)` - 任务数超出范围
+///
+/// # 示例
+///
+/// ```
+/// validate_max_active_tasks(3)?;  // 有效
+/// validate_max_active_tasks(0)?;  // 无效
+/// ```
+pub fn validate_max_active_tasks(max_active_tasks: usize) -> Result<()> {
+    const MAX_ACTIVE_TASKS: usize = 16;
+
+    if !(1..=MAX_ACTIVE_TASKS).contains(&max_active_tasks) {
+        return Err(DownloadError::validation(
+            "max_active_tasks",
+            format!("同时运行的任务数应在 1-{MAX_ACTIVE_TASKS} 之间"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// 解析并验证 `名称: 值` 形式的自定义请求头
+///
+/// 名称与值都必须是合法的 HTTP 请求头，值两端的空白会被去掉。
+///
+/// # 参数
+///
+/// * `raw` - 请求头字符串
+///
+/// # 返回
+///
+/// * `Ok((HeaderName, HeaderValue))` - 解析后的请求头
+/// * `Err(DownloadError)` - 格式无效
+///
+/// # 示例
+///
+/// ```
+/// parse_header("Authorization: Bearer token")?; // 有效
+/// parse_header("Authorization")?;               // 无效
+/// ```
+pub fn parse_header(raw: &str) -> Result<(HeaderName, HeaderValue)> {
+    let (name, value) = raw.split_once(':').ok_or_else(|| {
+        DownloadError::validation("header", format!("请求头格式应为 `名称: 值`: {raw}"))
+    })?;
+
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| DownloadError::validation("header", format!("请求头名称无效: {raw} - {e}")))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|e| DownloadError::validation("header", format!("请求头的值无效: {raw} - {e}")))?;
+
+    Ok((name, value))
+}